@@ -1,5 +1,6 @@
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
 use light_field_test::app::AppState;
+use light_field_test::parallelism::Parallelism;
 use std::{collections::HashSet, path::PathBuf, time::Duration};
 
 #[derive(Eq, Hash, PartialEq)]
@@ -17,6 +18,10 @@ fn bench_transport(c: &mut Criterion) {
     //let sizes = [500];
     // Grab a target image from curated
     let mut state = app.state.unwrap();
+    // LF_THREADS=single for deterministic timings
+    let parallelism = Parallelism::from_env();
+    state.factorizer.set_parallelism(parallelism);
+    state.stereoscope.set_parallelism(parallelism);
     let samples = 100;
     let mut selection = BenchSelection::new();
     selection.insert(Bench::SepOld);
@@ -183,6 +188,34 @@ fn run_fact_bench(
                 b.iter(|| state.factorizer.old_factorization());
             });
         }
+        // Score the panels of the solvers that were timed, one after the other
+        for bench in [Bench::Sep, Bench::Stereo, Bench::SepOld] {
+            if !selection.contains(&bench) {
+                continue;
+            }
+            let name = match bench {
+                Bench::Sep => {
+                    state.solver_light_field();
+                    "Sep"
+                }
+                Bench::Stereo => {
+                    state.solve_stereo();
+                    "Stereo"
+                }
+                Bench::SepOld => {
+                    state.solve_old();
+                    "MatrixSep"
+                }
+            };
+            let failed = [
+                state.factorizer.solve_error.take(),
+                state.stereoscope.solve_error.take(),
+            ];
+            match failed.into_iter().flatten().next() {
+                Some(err) => println!("{name} could not solve at {size}: {err}"),
+                None => println!("{name} quality at {size}:\n{}", state.evaluate_quality()),
+            }
+        }
         // DO a compute pass
     }
 
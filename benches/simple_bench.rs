@@ -9,28 +9,49 @@ use std::hint::black_box;
 fn bench_methods(c: &mut Criterion) {
     let settings = LFSettings {
         debug_prints: false,
+        parallelism: parallelism::Parallelism::from_env(),
         ..Default::default()
     };
+    // Projects the target and the panels for every update, what the cache saves
+    let uncached = LFSettings {
+        cache_budget: 0.0,
+        ..settings.clone()
+    };
 
-    let stereo = StereoMatrix::load("Kernel.ro".to_string());
+    let stereo = StereoMatrix::load("Kernel.ro".to_string()).unwrap();
     println!("Initialized Stereo Matrices");
 
-    let mut diagonal = LFMatrices::load("Kernel.ro".to_string());
+    let mut diagonal = LFMatrices::load("Kernel.ro".to_string()).unwrap();
 
     diagonal.c_t = DynamicImage::new_rgb8(diagonal.target_size.0, diagonal.target_size.1);
     let stacked_matrices = diagonal.stack();
 
     println!("Initialized Separable Matrices");
 
+    // One solve of each, how far the benchmarked iterations get
+    for (name, report) in [
+        ("Separable", diagonal.factorize(&settings, &())),
+        ("Stereo", stereo.factorize(&settings, &())),
+    ] {
+        match report {
+            Ok(Some(report)) => println!("{name} Approach: {report}"),
+            Ok(None) => {}
+            Err(err) => println!("{name} Approach could not solve: {err}"),
+        }
+    }
+
     // c.bench_function("Separable Old Approach", |b| {
     //     b.iter(|| diagonal.old_factorize(black_box(&settings), black_box(&stacked_matrices)))
     // });
     c.bench_function("Separable Approach", |b| {
-        b.iter(|| diagonal.factorize(black_box(&settings)))
+        b.iter(|| diagonal.factorize(black_box(&settings), &()))
+    });
+    c.bench_function("Separable Approach (uncached)", |b| {
+        b.iter(|| diagonal.factorize(black_box(&uncached), &()))
     });
 
     c.bench_function("Stereo Approach", |b| {
-        b.iter(|| stereo.factorize(black_box(&settings)))
+        b.iter(|| stereo.factorize(black_box(&settings), &()))
     });
 }
 
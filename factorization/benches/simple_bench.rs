@@ -1,4 +1,5 @@
 use factorization::*;
+use faer::Par;
 use image::DynamicImage;
 
 use criterion::{Criterion, criterion_group, criterion_main};
@@ -7,8 +8,14 @@ use std::hint::black_box;
 // Mockup of your three methods:
 
 fn bench_methods(c: &mut Criterion) {
+    // Threads from LF_THREADS like the benches of the app, 0 or unset for every core
+    let threads = std::env::var("LF_THREADS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(0);
     let settings = LFSettings {
         debug_prints: false,
+        parallelism: Par::rayon(threads),
         ..Default::default()
     };
 
@@ -1,5 +1,5 @@
 pub mod utils;
-use std::{num::NonZero, path::PathBuf};
+use std::path::PathBuf;
 
 // Library File that exposes and will be used to import as well
 //
@@ -13,7 +13,7 @@ use std::{
 };
 
 use faer::{
-    Mat,
+    Mat, Par,
     stats::prelude::{Rng, thread_rng},
     unzip, zip,
 };
@@ -208,16 +208,17 @@ impl LFMatrices {
         settings: &LFSettings,
         matrices: &OldLFMatrices,
     ) -> Option<(DynamicImage, DynamicImage, Option<L2Norm>)> {
-        faer::set_global_parallelism(faer::Par::Rayon(NonZero::new(10).unwrap()));
+        settings.install(|| self.old_solve(settings, matrices))
+    }
+
+    fn old_solve(
+        &self,
+        settings: &LFSettings,
+        matrices: &OldLFMatrices,
+    ) -> Option<(DynamicImage, DynamicImage, Option<L2Norm>)> {
         let target_size = self.target_size;
         let number_of_view_points = self.number_of_view_points;
         let c_t = &self.c_t;
-        if settings.debug_prints {
-            println!(
-                "Global Parallelism is: {:?}",
-                faer::get_global_parallelism()
-            );
-        }
 
         let m_a_x = matrices.m_a_x.as_ref();
         let m_a_y = matrices.m_a_y.as_ref();
@@ -488,6 +489,8 @@ pub struct LFSettings {
     pub save_error: bool,
     pub debug_prints: bool,
     pub save_to: String,
+    /// Threads of the pool every solve runs in, nothing global is touched
+    pub parallelism: Par,
 }
 impl Default for LFSettings {
     fn default() -> Self {
@@ -503,16 +506,36 @@ impl Default for LFSettings {
             save_error: true,
             debug_prints: true,
             save_to: "Default".to_string(),
+            parallelism: Par::rayon(0),
         }
     }
 }
 
+impl LFSettings {
+    /// Runs `solve` in a rayon pool of `parallelism` threads, the matrix products inside run on it
+    fn install<R, F>(&self, solve: F) -> R
+    where
+        F: FnOnce() -> R + Send,
+        R: Send,
+    {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.parallelism.degree())
+            .build()
+            .expect("Could not build the solver thread pool")
+            .install(solve)
+    }
+}
+
 type L2Norm = Vec<f32>;
-pub trait Lff {
+pub trait Lff: Sync {
+    /// Solves in the thread pool of `settings`
     fn factorize(
         &self,
         settings: &LFSettings,
     ) -> Option<(DynamicImage, DynamicImage, Option<L2Norm>)> {
+        settings.install(|| self.solve(settings))
+    }
+    fn solve(&self, settings: &LFSettings) -> Option<(DynamicImage, DynamicImage, Option<L2Norm>)> {
         let _ = settings;
         let _ = self;
         None
@@ -520,20 +543,10 @@ pub trait Lff {
 }
 
 impl Lff for LFMatrices {
-    fn factorize(
-        &self,
-        settings: &LFSettings,
-    ) -> Option<(DynamicImage, DynamicImage, Option<L2Norm>)> {
-        faer::set_global_parallelism(faer::Par::Rayon(NonZero::new(10).unwrap()));
+    fn solve(&self, settings: &LFSettings) -> Option<(DynamicImage, DynamicImage, Option<L2Norm>)> {
         let target_size = self.target_size;
         let number_of_view_points = self.number_of_view_points;
         let c_t = &self.c_t;
-        if settings.debug_prints {
-            println!(
-                "Global Parallelism is: {:?}",
-                faer::get_global_parallelism()
-            );
-        }
 
         let rays_cast = (
             target_size.0 * number_of_view_points,
@@ -766,12 +779,7 @@ impl Lff for LFMatrices {
 }
 
 impl Lff for StereoMatrix {
-    fn factorize(
-        &self,
-        settings: &LFSettings,
-    ) -> Option<(DynamicImage, DynamicImage, Option<L2Norm>)> {
-        faer::set_global_parallelism(faer::Par::Rayon(NonZero::new(10).unwrap()));
-
+    fn solve(&self, settings: &LFSettings) -> Option<(DynamicImage, DynamicImage, Option<L2Norm>)> {
         let matrices = self;
         if settings.debug_prints {
             println!(
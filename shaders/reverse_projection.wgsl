@@ -88,6 +88,9 @@ var<storage, read_write> a_buffer: array<u32>;
 var<storage, read_write> b_buffer: array<u32>;
 @group(6) @binding(2)
 var<storage, read_write> l_buffer: array<f32>;
+// Only as long as the rays when factorizing in colour, a single entry otherwise
+@group(6) @binding(3)
+var<storage, read_write> rgb_buffer: array<vec4<f32>>;
 
 @compute @workgroup_size(8, 8, 1)
 fn main(@builtin(global_invocation_id) GlobalInvocationID: vec3<u32>) {
@@ -187,6 +190,7 @@ fn double_intersection(ray: Ray, current_pixel: vec2<u32>, observer_index: u32)
     let gray_scale = color.r * 0.299 + 0.587 * color.g + 0.114 * color.b;
     //record_hit_T(ray_index, current_pixel, observer_index);
     record_hit_l(ray_index, gray_scale);
+    record_hit_rgb(ray_index, color);
 
 //textureStore(color_buffer, current_pixel, color);
 }
@@ -209,6 +213,12 @@ fn record_hit_l(ray_index: u32, sample: f32) {
 
 }
 
+fn record_hit_rgb(ray_index: u32, sample: vec4<f32>) {
+    if ray_index < arrayLength(&rgb_buffer) {
+        rgb_buffer[ray_index] = sample;
+    }
+}
+
 fn record_hit_Theta_A(ray_index: u32, a_coords: vec2<u32>) {
     let vectorized_a_coords = a_coords.x + a_coords.y * panels[0].pixel_count.x;
     a_buffer[ray_index] = vectorized_a_coords;
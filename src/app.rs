@@ -1,12 +1,19 @@
 use crate::camera::CameraHistory;
+use crate::capture::CaptureMetadata;
 use crate::compute_pass::ReverseProj;
+use crate::cpu_sampler::CpuSampler;
 use crate::egui_tools::EguiRenderer;
+use crate::error::Result;
 use crate::gif::GifPlayer;
 use crate::headless::HeadlessImage;
 use crate::light_factor::LFBuffers;
+use crate::mapping::MappingMode;
+use crate::observer::ObserverRenderer;
+use crate::quality::{ImageQuality, QualityEvaluator, QualityReport};
 use crate::raytracer::RayTraceInfo;
+use crate::report::SolveReport;
 use crate::save::{ImageCache, Save, SaveManager};
-use crate::scene::Scene;
+use crate::scene::{Scene, GPU_PANELS};
 use crate::shape::Quad;
 use crate::stereoscope::StereoscopeBuffer;
 use crate::utils::DrawUI;
@@ -56,6 +63,7 @@ pub struct AppState {
     distort_rays: bool,
     pub headless: HeadlessImage,
     pub gif: GifPlayer,
+    pub quality: QualityEvaluator,
 }
 
 impl AppState {
@@ -271,6 +279,7 @@ impl AppState {
             camera_history,
             headless,
             gif: GifPlayer::create(Vec::new()),
+            quality: QualityEvaluator::default(),
         }
     }
     async fn request_adapter(instance: &wgpu::Instance) -> Adapter {
@@ -360,6 +369,7 @@ impl AppState {
             camera_history,
             headless,
             gif: GifPlayer::create(Vec::new()),
+            quality: QualityEvaluator::default(),
         }
     }
 
@@ -471,9 +481,13 @@ impl AppState {
                 let target = DynamicImage::from(frame.clone().into_buffer());
                 self.factorizer.update_target(&target);
 
-                self.factorizer
-                    .alternative_factorization()
-                    .map(|im| (im.0, im.1))
+                match self.factorizer.alternative_factorization() {
+                    Ok(report) => report.map(|report| report.layers().to_vec()),
+                    Err(err) => {
+                        self.factorizer.solve_error = Some(err);
+                        None
+                    }
+                }
             })
             .take_while(Option::is_some)
             .map(|x| x.unwrap());
@@ -484,12 +498,36 @@ impl AppState {
 
     pub fn play_gif(&mut self) {
         if self.gif.animate_gif(&mut self.image_cache).is_some() {
-            self.update_panel(0);
-            self.update_panel(1);
+            self.update_panels();
             self.displaying_panel_textures = true;
         }
     }
 
+    /// Scores the current panel output from every saved view, rendered on the CPU
+    pub fn evaluate_quality(&self) -> QualityReport {
+        // Layers without a solution yet let every ray through
+        let mut panel_images = self.image_cache.panels.clone();
+        panel_images.resize(self.scene.panels.len(), DynamicImage::default());
+        let renderer = ObserverRenderer::from_scene(
+            &self.scene,
+            &self.camera_history.current_camera,
+            &panel_images,
+            &self.image_cache.target_image,
+        );
+        let cameras = if self.camera_history.history.is_empty() {
+            vec![&self.camera_history.current_camera]
+        } else {
+            self.camera_history.history.iter().collect()
+        };
+        QualityReport::evaluate(
+            &renderer,
+            cameras,
+            self.quality.width,
+            self.quality.height,
+            None,
+        )
+    }
+
     pub fn compute_pass(&mut self) {
         // Update the world first!
         self.camera_history.update_buffer(&self.queue);
@@ -506,6 +544,13 @@ impl AppState {
             self.distort_rays,
         );
 
+        // Checked before dispatching, a capture the buffers can't hold is not read back
+        let pixel_count = self.scene.world.pixel_count;
+        let rays_cast = pixel_count.x * pixel_count.y * self.camera_history.len() as u32;
+        if let Err(err) = self.stereoscope.prepare(&self.device, rays_cast) {
+            self.stereoscope.sample_error = Some(err);
+        }
+
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
@@ -632,7 +677,43 @@ impl AppState {
 
         //self.stereoscope.verify_m_a(&self.device, rays_cast);
     }
+    /// The GPU samplers cast one ray per pixel through two panels and record the nearest pixel.
+    /// Deeper stacks, weighted mappings and supersampling are sampled on the CPU.
+    fn cpu_sampler(&self) -> Option<CpuSampler> {
+        let scene = &self.scene;
+        let gpu = scene.panels.len() <= GPU_PANELS
+            && scene.mapping_mode == MappingMode::Nearest
+            && scene.supersampling == 1;
+        (!gpu).then(|| {
+            CpuSampler::new(
+                &scene.world,
+                &scene.panels,
+                &self.camera_history.current_camera,
+                &self.camera_history.history,
+            )
+            .with_mapping_mode(scene.mapping_mode)
+            .with_supersampling(scene.supersampling, scene.sample_pattern)
+        })
+    }
+
+    /// Scene the samplers are about to capture
+    fn capture_metadata(&self) -> CaptureMetadata {
+        CaptureMetadata::new(
+            &self.scene.world,
+            &self.scene.panels,
+            &self.camera_history.history,
+            self.camera_history.kernel,
+            self.camera_history.kernel_size,
+        )
+    }
+
     pub fn sample_stereo(&mut self) {
+        self.stereoscope.capture_metadata = self.capture_metadata();
+        if let Some(sampler) = self.cpu_sampler() {
+            let stereo = sampler.sample_stereo(&self.image_cache.target_image);
+            self.stereoscope.matrix_rep = Some(stereo);
+            return;
+        }
         let pixel_count_a = self.scene.panels[0].panel.pixel_count.yx();
         let pixel_count_b = self.scene.panels[1].panel.pixel_count.yx();
 
@@ -653,6 +734,12 @@ impl AppState {
     }
 
     pub fn sample_sep(&mut self) {
+        self.factorizer.capture_metadata = self.capture_metadata();
+        if let Some(sampler) = self.cpu_sampler() {
+            let matrices = sampler.sample_separable(&self.image_cache.target_image);
+            self.factorizer.matrix_rep = Some(matrices);
+            return;
+        }
         let c_t = &self.image_cache.target_image;
         let pixel_count_a = self.scene.panels[0].panel.pixel_count.yx();
         let pixel_count_b = self.scene.panels[1].panel.pixel_count.yx();
@@ -682,23 +769,65 @@ impl AppState {
     }
 
     pub fn solve_stereo(&mut self) {
-        let imgs = self.stereoscope.factorize_stereo();
-        self.image_cache.cache_output(true, imgs);
-        self.update_panel(0);
-        self.update_panel(1);
+        match self.stereoscope.factorize_stereo() {
+            Ok(solution) => self.show_stereo_solution(solution),
+            Err(err) => self.stereoscope.solve_error = Some(err),
+        }
+    }
+
+    fn show_stereo_solution(&mut self, solution: Option<SolveReport>) {
+        self.cache_solution(true, solution);
         let _ = self.image_cache.plot_error("L2Norm.png".into(), true);
     }
 
     pub fn solver_light_field(&mut self) {
         // Y here maps to additional rows and X to additional Columns
-        let images = self.factorizer.alternative_factorization();
+        match self.factorizer.alternative_factorization() {
+            Ok(solution) => self.show_separable_solution(solution),
+            Err(err) => self.factorizer.solve_error = Some(err),
+        }
+    }
+
+    /// Stacked separable solve, shown like the separable one
+    pub fn solve_old(&mut self) {
+        match self.factorizer.old_factorization() {
+            Ok(solution) => self.show_separable_solution(solution),
+            Err(err) => self.factorizer.solve_error = Some(err),
+        }
+    }
 
-        self.image_cache.cache_output(false, images);
-        self.update_panel(0);
-        self.update_panel(1);
+    fn show_separable_solution(&mut self, solution: Option<SolveReport>) {
+        self.cache_solution(false, solution);
+        let _ = self
+            .image_cache
+            .plot_error("L2NormSeparable.png".into(), false);
     }
 
+    /// Shows the first frame, a time multiplexed solution is cycled by the gif player
+    fn cache_solution(&mut self, stereo: bool, solution: Option<SolveReport>) {
+        if let Some(report) = &solution {
+            if report.frames.len() > 1 {
+                self.gif = GifPlayer::multiplexed(report.frames.clone());
+                self.gif.start_animation();
+            } else if self.gif.multiplexed.is_some() {
+                self.gif = GifPlayer::create(Vec::new());
+            }
+        }
+        self.image_cache.cache_output(stereo, solution);
+        self.update_panels();
+    }
+
+    fn update_panels(&self) {
+        for panel_entry in 0..self.image_cache.panels.len() {
+            self.update_panel(panel_entry);
+        }
+    }
+
+    /// Deeper layers than the panel texture array are only seen by the CPU renderer
     fn update_panel(&self, panel_entry: usize) {
+        if panel_entry >= GPU_PANELS {
+            return;
+        }
         let image = &self.image_cache.panels[panel_entry];
         let dimensions = image.dimensions();
 
@@ -767,42 +896,47 @@ impl AppState {
     fn save_pop_up(&mut self) {
         self.save_manager.save_open = true;
     }
-    fn save(&mut self) {
+    fn save(&mut self) -> Result<()> {
         if self.save_manager.name_inserted {
             let name = &self.save_manager.current_save_name;
-            let save = Save::from_cache(
+            let saved = Save::from_cache(
                 &self.camera_history.history,
                 name,
                 &self.image_cache,
                 &self.scene,
-            );
-            self.save_manager.add_save(save);
+            )
+            .and_then(|save| self.save_manager.add_save(save));
+            if saved.is_err() {
+                // Close the pop up, otherwise the save is retried every frame
+                self.save_manager.save_open = false;
+            }
+            saved?;
         }
+        Ok(())
     }
 
-    fn load_next_save(&mut self) -> Option<String> {
+    fn load_next_save(&mut self) -> Result<Option<String>> {
         let next_save = self.save_manager.next_save();
         if let Some(save) = next_save {
             let name = save.name.clone();
-            let new_cache = save.to_cache();
+            let new_cache = save.to_cache()?;
             let cameras = save.cameras.clone();
             save.update_scene(&mut self.scene);
             self.image_cache = new_cache;
             self.update_target_texture();
-            self.update_panel(0);
-            self.update_panel(1);
+            self.update_panels();
             self.displaying_panel_textures = true;
             self.camera_history.update_history(cameras);
-            Some(name)
+            Ok(Some(name))
         } else {
-            None
+            Ok(None)
         }
     }
-    fn load_previous_save(&mut self) -> Option<String> {
+    fn load_previous_save(&mut self) -> Result<Option<String>> {
         let next_save = self.save_manager.previous_save();
         if let Some(save) = next_save {
             let name = save.name.clone();
-            let new_cache = save.to_cache();
+            let new_cache = save.to_cache()?;
             let cameras = save.cameras.clone();
 
             save.update_scene(&mut self.scene);
@@ -810,13 +944,12 @@ impl AppState {
 
             self.camera_history.update_history(cameras);
             self.update_target_texture();
-            self.update_panel(0);
-            self.update_panel(1);
+            self.update_panels();
             self.displaying_panel_textures = true;
 
-            Some(name)
+            Ok(Some(name))
         } else {
-            None
+            Ok(None)
         }
     }
     fn give_image(&mut self) -> DynamicImage {
@@ -951,8 +1084,14 @@ impl App {
                     || self.pressed_keys.contains(&KeyCode::ShiftRight)
                 {
                     if let Some(state) = self.state.as_mut() {
-                        let name = state.load_next_save();
-                        self.toasts.info(format!("Loading next save {name:?}"));
+                        match state.load_next_save() {
+                            Ok(name) => {
+                                self.toasts.info(format!("Loading next save {name:?}"));
+                            }
+                            Err(err) => {
+                                self.toasts.error(format!("Could not load save: {err}"));
+                            }
+                        }
                         self.next_camera();
                     }
                 }
@@ -968,9 +1107,14 @@ impl App {
                     || self.pressed_keys.contains(&KeyCode::ShiftRight)
                 {
                     if let Some(state) = self.state.as_mut() {
-                        let name = state.load_previous_save();
-
-                        self.toasts.info(format!("Previous next save{name:?}"));
+                        match state.load_previous_save() {
+                            Ok(name) => {
+                                self.toasts.info(format!("Previous next save{name:?}"));
+                            }
+                            Err(err) => {
+                                self.toasts.error(format!("Could not load save: {err}"));
+                            }
+                        }
 
                         self.next_camera();
                     }
@@ -999,13 +1143,11 @@ impl App {
                     if state.image_cache.load_output(self.cache_stereo).is_ok() {
                         if self.cache_stereo {
                             self.toasts.info("Displaying stereo output");
-                            state.update_panel(0);
-                            state.update_panel(1);
+                            state.update_panels();
                         } else {
                             self.toasts.info("Displaying Separable Output");
 
-                            state.update_panel(0);
-                            state.update_panel(1);
+                            state.update_panels();
                         }
                     } else {
                         self.toasts.warning("No output found in cache");
@@ -1083,7 +1225,7 @@ impl App {
 
         //self.nmf_solver.reset();
     }
-    fn image_clean(&mut self, target: usize, panel: usize) {
+    fn image_clean(&mut self, target: usize, panel: usize) -> DynamicImage {
         let state = self.state.as_mut().unwrap();
         state.displaying_panel_textures = false;
         state.distort_rays = false;
@@ -1097,6 +1239,7 @@ impl App {
         let out = self.state.as_mut().unwrap().give_image();
         let outpath = format!("./resources/cycle/PerfectTarget{target}Panel{panel}.png");
         out.save(outpath).unwrap();
+        out
     }
 
     fn cycle_image_creation(&mut self) {
@@ -1131,7 +1274,9 @@ impl App {
                 let outpath = format!("./resources/cycle/Target{target}Panel{panel}.png");
                 out.save(outpath).unwrap();
 
-                self.image_clean(target, panel);
+                let perfect = self.image_clean(target, panel);
+                let quality = ImageQuality::compare(&perfect, &out);
+                println!("Target {target}, Panel {panel}: {quality}");
             }
         }
         // for panel in [250, 250, 500, 500, 1000, 1000, 2000, 2000] {
@@ -1149,7 +1294,7 @@ impl App {
     pub fn update_panel_texture(&mut self) {
         let state = self.state.as_mut().unwrap();
 
-        for x in 0..=1 {
+        for x in 0..state.scene.panels.len() {
             let panel = &state.scene.panels[x];
             if !panel.texture.change_file {
                 continue;
@@ -1221,24 +1366,71 @@ impl App {
         //
 
         let state = self.state.as_mut().unwrap();
-        state.save();
+        if let Err(err) = state.save() {
+            self.toasts.error(format!("Could not save scene: {err}"));
+        }
+        let capture_errors = [
+            state.factorizer.save_error.take(),
+            state.stereoscope.save_error.take(),
+        ];
+        for err in capture_errors.into_iter().flatten() {
+            self.toasts.error(format!("Could not save capture: {err}"));
+        }
+        if let Some(err) = state.stereoscope.sample_error.take() {
+            self.toasts
+                .error(format!("Could not sample stereo capture: {err}"));
+        }
+        let solve_errors = [
+            state.factorizer.solve_error.take(),
+            state.stereoscope.solve_error.take(),
+        ];
+        for err in solve_errors.into_iter().flatten() {
+            self.toasts.error(format!("Could not solve: {err}"));
+        }
         state.play_gif();
 
+        // Solves run in the background, the panels show up once they finish or are stopped
         if state.factorizer.will_solve() {
             state.compute_pass();
             state.sample_sep();
-            state.solver_light_field();
-            state.displaying_panel_textures = true;
+            state.factorizer.start_background_solve();
             state.factorizer.has_solved();
         }
+        if let Some(solution) = state.factorizer.finished_background_solve() {
+            if let Some(report) = &solution {
+                self.toasts.info(format!("Separable solve: {report}"));
+                if let Some(err) = &report.checkpoint_error {
+                    self.toasts
+                        .error(format!("Could not write checkpoint: {err}"));
+                }
+            }
+            state.show_separable_solution(solution);
+            state.displaying_panel_textures = true;
+        }
+
+        if state.quality.evaluate {
+            let report = state.evaluate_quality();
+            println!("{report}");
+            state.quality.report = Some(report);
+        }
 
         if state.stereoscope.will_solve() {
             state.compute_pass();
             state.sample_stereo();
-            state.solve_stereo();
-            state.displaying_panel_textures = true;
+            state.stereoscope.start_background_solve();
             state.stereoscope.has_solved();
         }
+        if let Some(solution) = state.stereoscope.finished_background_solve() {
+            if let Some(report) = &solution {
+                self.toasts.info(format!("Stereo solve: {report}"));
+                if let Some(err) = &report.checkpoint_error {
+                    self.toasts
+                        .error(format!("Could not write checkpoint: {err}"));
+                }
+            }
+            state.show_stereo_solution(solution);
+            state.displaying_panel_textures = true;
+        }
 
         let screen_descriptor = ScreenDescriptor {
             size_in_pixels: [
@@ -1360,6 +1552,7 @@ impl App {
             state.save_manager.draw_ui(context, None, None);
             state.headless.draw_ui(context, None, None);
             state.gif.draw_ui(context, None, None);
+            state.quality.draw_ui(context, None, None);
 
             state.egui_renderer.as_mut().unwrap().end_frame_and_draw(
                 &state.device,
@@ -0,0 +1,603 @@
+use std::{
+    collections::VecDeque,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bincode::error::DecodeError;
+use cgmath::Point3;
+use faer::Mat;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    camera::Camera,
+    error::{Error, Result, WithPath},
+    mapping::IndexMapping,
+    save::Save,
+    scene::{ScenePanel, Target},
+    CompleteMapping, LFMatrices, MappingMatrix, StereoMatrix,
+};
+
+/// First bytes of every capture that carries a header
+const MAGIC: &[u8; 8] = b"LFCAPTUR";
+/// Layout written by `save`, older ones are migrated on load. Captures without a header are
+/// version 0, version 1 stored the mappings as sparse matrices, version 2 a single pixel per ray.
+pub const CAPTURE_VERSION: u32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CaptureKind {
+    Separable,
+    Stereo,
+}
+
+impl CaptureKind {
+    /// Directory of the files of this kind, next to the other saves
+    pub fn approach(self) -> &'static str {
+        match self {
+            CaptureKind::Separable => "sep",
+            CaptureKind::Stereo => "stereo",
+        }
+    }
+    pub fn other(self) -> Self {
+        match self {
+            CaptureKind::Separable => CaptureKind::Stereo,
+            CaptureKind::Stereo => CaptureKind::Separable,
+        }
+    }
+}
+
+/// Scene a matrix capture was sampled from
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct CaptureMetadata {
+    pub target: Option<Target>,
+    /// Closest panel to the observers first
+    pub panels: Vec<ScenePanel>,
+    /// Every view point, in sampling order
+    pub camera_positions: Vec<Point3<f32>>,
+    /// Whether every saved camera was surrounded by a kernel of extra view points
+    pub kernel: bool,
+    pub kernel_size: f32,
+}
+
+impl CaptureMetadata {
+    pub fn new(
+        target: &Target,
+        panels: &[ScenePanel],
+        cameras: &VecDeque<Camera>,
+        kernel: bool,
+        kernel_size: f32,
+    ) -> Self {
+        CaptureMetadata {
+            target: Some(target.clone()),
+            panels: panels.to_vec(),
+            camera_positions: cameras.iter().map(|camera| camera.position).collect(),
+            kernel,
+            kernel_size,
+        }
+    }
+
+    /// Scene of `./saves/scene_capture/{name}/`. Scene captures do not record the kernel, its
+    /// view points are part of the cameras.
+    pub fn from_scene_capture(name: &str) -> Result<Self> {
+        let save = Save::load(name)?;
+        let panels: Vec<ScenePanel> = save.panels().into_iter().cloned().collect();
+        Ok(Self::new(&save.target, &panels, &save.cameras, false, 0.0))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CaptureHeader {
+    /// Layout the file was written with, 0 for captures from before the header
+    #[serde(skip)]
+    pub version: u32,
+    pub kind: CaptureKind,
+    /// Seconds since the unix epoch. Captures without a header use the file modification time.
+    pub created: u64,
+    pub metadata: CaptureMetadata,
+}
+
+/// Matrices that can be written to a capture file
+pub(crate) trait Capture: Serialize + DeserializeOwned {
+    const KIND: CaptureKind;
+
+    /// Rebuilds the matrices from the payload of an older `version`. Version 0 is a capture
+    /// written before the header, a bare bincode dump of one of the older layouts of the struct.
+    fn migrate(version: u32, bytes: &[u8]) -> Result<Self, DecodeError>;
+}
+
+pub(crate) fn save<T: Capture>(capture: &T, path: &Path, metadata: &CaptureMetadata) -> Result<()> {
+    let config = bincode::config::standard();
+    let header = CaptureHeader {
+        version: CAPTURE_VERSION,
+        kind: T::KIND,
+        created: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs()),
+        metadata: metadata.clone(),
+    };
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&CAPTURE_VERSION.to_le_bytes());
+    bytes.extend(bincode::serde::encode_to_vec(&header, config).with_path(path)?);
+    bytes.extend(bincode::serde::encode_to_vec(capture, config).with_path(path)?);
+    std::fs::write(path, bytes).with_path(path)
+}
+
+pub(crate) fn load<T: Capture>(path: &Path) -> Result<(CaptureHeader, T)> {
+    let config = bincode::config::standard();
+    let bytes = std::fs::read(path).with_path(path)?;
+
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        let capture = T::migrate(0, &bytes).with_path(path)?;
+        let created = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |time| time.as_secs());
+        let header = CaptureHeader {
+            version: 0,
+            kind: T::KIND,
+            created,
+            metadata: CaptureMetadata::default(),
+        };
+        return Ok((header, capture));
+    };
+
+    let version = rest
+        .get(..4)
+        .map(|version| u32::from_le_bytes(version.try_into().unwrap()))
+        .ok_or(DecodeError::UnexpectedEnd { additional: 4 })
+        .with_path(path)?;
+    if version > CAPTURE_VERSION {
+        return Err(Error::UnsupportedVersion {
+            path: path.to_path_buf(),
+            version,
+        });
+    }
+    let (mut header, read): (CaptureHeader, usize) =
+        bincode::serde::decode_from_slice(&rest[4..], config).with_path(path)?;
+    header.version = version;
+    if header.kind != T::KIND {
+        return Err(Error::WrongCaptureKind {
+            path: path.to_path_buf(),
+            expected: T::KIND,
+            found: header.kind,
+        });
+    }
+    let payload = &rest[4 + read..];
+    let capture = if version < CAPTURE_VERSION {
+        T::migrate(version, payload).with_path(path)?
+    } else {
+        bincode::serde::decode_from_slice(payload, config)
+            .with_path(path)?
+            .0
+    };
+    Ok((header, capture))
+}
+
+/// Sparse matrix as its triplets, how mappings were stored before version 2
+#[derive(Serialize, Deserialize)]
+struct SparseAsList {
+    shape: (usize, usize),
+    triplet_list: Vec<(usize, usize, f32)>,
+}
+
+impl SparseAsList {
+    fn into_mapping(self) -> Result<IndexMapping, DecodeError> {
+        IndexMapping::from_triplets(self.shape.0, self.shape.1, self.triplet_list).ok_or_else(
+            || DecodeError::OtherString("mapping with more than one pixel per ray".to_string()),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum SparsePass {
+    List(Vec<SparseAsList>),
+}
+
+impl SparsePass {
+    fn into_mapping(self) -> Result<MappingMatrix, DecodeError> {
+        let SparsePass::List(views) = self;
+        let matrix = views
+            .into_iter()
+            .map(SparseAsList::into_mapping)
+            .collect::<Result<_, _>>()?;
+        Ok(MappingMatrix::new(matrix))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SparseCompleteMapping {
+    x: SparsePass,
+    size: (u32, u32),
+    y: SparsePass,
+}
+
+impl SparseCompleteMapping {
+    fn into_mapping(self) -> Result<CompleteMapping, DecodeError> {
+        Ok(CompleteMapping::new(
+            self.x.into_mapping()?,
+            self.y.into_mapping()?,
+            self.size,
+        ))
+    }
+}
+
+/// Layout with a fixed pair of panels, before any number of layers could be stacked
+#[derive(Deserialize)]
+struct TwoPanelLFMatrices {
+    a: SparseCompleteMapping,
+    b: SparseCompleteMapping,
+    t: SparseCompleteMapping,
+    target_size: (u32, u32),
+    number_of_view_points: u32,
+}
+
+/// Stacked layers with sparse mappings, the payload of version 1
+#[derive(Serialize, Deserialize)]
+struct SparseLFMatrices {
+    layers: Vec<SparseCompleteMapping>,
+    t: SparseCompleteMapping,
+    target_size: (u32, u32),
+    number_of_view_points: u32,
+}
+
+impl SparseLFMatrices {
+    fn into_matrices(self) -> Result<LFMatrices, DecodeError> {
+        let layers = self
+            .layers
+            .into_iter()
+            .map(SparseCompleteMapping::into_mapping)
+            .collect::<Result<_, _>>()?;
+        Ok(LFMatrices::new(
+            layers,
+            self.t.into_mapping()?,
+            Default::default(),
+            self.target_size,
+            self.number_of_view_points,
+        ))
+    }
+}
+
+/// One pixel per ray, how mappings were stored in version 2
+#[derive(Serialize, Deserialize)]
+struct NearestMapping {
+    indices: Vec<u32>,
+    ncols: usize,
+}
+
+impl NearestMapping {
+    fn into_mapping(self) -> IndexMapping {
+        IndexMapping::from_hits(self.indices, self.ncols)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct NearestCompleteMapping {
+    x: Vec<NearestMapping>,
+    size: (u32, u32),
+    y: Vec<NearestMapping>,
+}
+
+impl NearestCompleteMapping {
+    fn into_mapping(self) -> CompleteMapping {
+        let views = |views: Vec<NearestMapping>| {
+            MappingMatrix::new(
+                views
+                    .into_iter()
+                    .map(NearestMapping::into_mapping)
+                    .collect(),
+            )
+        };
+        CompleteMapping::new(views(self.x), views(self.y), self.size)
+    }
+}
+
+/// Payload of version 2
+#[derive(Serialize, Deserialize)]
+struct NearestLFMatrices {
+    layers: Vec<NearestCompleteMapping>,
+    t: NearestCompleteMapping,
+    target_size: (u32, u32),
+    number_of_view_points: u32,
+}
+
+impl Capture for LFMatrices {
+    const KIND: CaptureKind = CaptureKind::Separable;
+
+    fn migrate(version: u32, bytes: &[u8]) -> Result<Self, DecodeError> {
+        let config = bincode::config::standard();
+        if version == 2 {
+            let (old, _): (NearestLFMatrices, usize) =
+                bincode::serde::decode_from_slice(bytes, config)?;
+            return Ok(LFMatrices::new(
+                old.layers
+                    .into_iter()
+                    .map(NearestCompleteMapping::into_mapping)
+                    .collect(),
+                old.t.into_mapping(),
+                Default::default(),
+                old.target_size,
+                old.number_of_view_points,
+            ));
+        }
+        // The two panel layout starts with the `SparsePass` variant of `a`, which is always 0.
+        // A list of layers starts with its length, at least 2.
+        if version == 0 && bytes.first() == Some(&0) {
+            let (old, _): (TwoPanelLFMatrices, usize) =
+                bincode::serde::decode_from_slice(bytes, config)?;
+            return SparseLFMatrices {
+                layers: vec![old.a, old.b],
+                t: old.t,
+                target_size: old.target_size,
+                number_of_view_points: old.number_of_view_points,
+            }
+            .into_matrices();
+        }
+        let (old, _): (SparseLFMatrices, usize) = bincode::serde::decode_from_slice(bytes, config)?;
+        old.into_matrices()
+    }
+}
+
+/// Layout of the first captures, only the grayscale samples of the rays
+#[derive(Deserialize)]
+struct GrayStereoMatrix {
+    l_vec: Mat<f32>,
+    a_matrix: SparseAsList,
+    b_matrix: SparseAsList,
+    panel_a_size: (u32, u32),
+    panel_b_size: (u32, u32),
+    target_size: (u32, u32),
+    number_of_view_points: u32,
+}
+
+/// Colour samples with a fixed pair of panels
+#[derive(Deserialize)]
+struct TwoPanelStereoMatrix {
+    l_vec: Mat<f32>,
+    l_rgb: Mat<f32>,
+    a_matrix: SparseAsList,
+    b_matrix: SparseAsList,
+    panel_a_size: (u32, u32),
+    panel_b_size: (u32, u32),
+    target_size: (u32, u32),
+    number_of_view_points: u32,
+}
+
+/// Stacked layers with sparse mappings, the payload of version 1
+#[derive(Serialize, Deserialize)]
+struct SparseStereoMatrix {
+    l_vec: Mat<f32>,
+    l_rgb: Mat<f32>,
+    layers: Vec<SparseAsList>,
+    layer_sizes: Vec<(u32, u32)>,
+    target_size: (u32, u32),
+    number_of_view_points: u32,
+}
+
+impl SparseStereoMatrix {
+    fn into_matrix(self) -> Result<StereoMatrix, DecodeError> {
+        let layers = self
+            .layers
+            .into_iter()
+            .map(SparseAsList::into_mapping)
+            .collect::<Result<_, _>>()?;
+        Ok(StereoMatrix {
+            l_vec: self.l_vec,
+            l_rgb: self.l_rgb,
+            layers,
+            layer_sizes: self.layer_sizes,
+            target_size: self.target_size,
+            number_of_view_points: self.number_of_view_points,
+        })
+    }
+}
+
+/// Payload of version 2
+#[derive(Serialize, Deserialize)]
+struct NearestStereoMatrix {
+    l_vec: Mat<f32>,
+    l_rgb: Mat<f32>,
+    layers: Vec<NearestMapping>,
+    layer_sizes: Vec<(u32, u32)>,
+    target_size: (u32, u32),
+    number_of_view_points: u32,
+}
+
+impl Capture for StereoMatrix {
+    const KIND: CaptureKind = CaptureKind::Stereo;
+
+    fn migrate(version: u32, bytes: &[u8]) -> Result<Self, DecodeError> {
+        let config = bincode::config::standard();
+        if version == 2 {
+            let (old, _): (NearestStereoMatrix, usize) =
+                bincode::serde::decode_from_slice(bytes, config)?;
+            return Ok(StereoMatrix {
+                l_vec: old.l_vec,
+                l_rgb: old.l_rgb,
+                layers: old
+                    .layers
+                    .into_iter()
+                    .map(NearestMapping::into_mapping)
+                    .collect(),
+                layer_sizes: old.layer_sizes,
+                target_size: old.target_size,
+                number_of_view_points: old.number_of_view_points,
+            });
+        }
+        if version == 0 {
+            // Every layout starts with `l_vec`. The colour samples that may follow are a matrix
+            // of one row per ray and 3 columns, a sparse mapping starts with its shape instead.
+            // Decoding a matrix from the wrong bytes would allocate whatever size it reads, so
+            // the layout is decided before decoding the rest.
+            let ((l_vec, rows, cols), _): ((Mat<f32>, usize, usize), usize) =
+                bincode::serde::decode_from_slice(bytes, config)?;
+            if (rows, cols) != (l_vec.nrows(), 3) {
+                let (old, _): (GrayStereoMatrix, usize) =
+                    bincode::serde::decode_from_slice(bytes, config)?;
+                let l_rgb = Mat::from_fn(old.l_vec.nrows(), 3, |x, _| old.l_vec[(x, 0)]);
+                return SparseStereoMatrix {
+                    l_vec: old.l_vec,
+                    l_rgb,
+                    layers: vec![old.a_matrix, old.b_matrix],
+                    layer_sizes: vec![old.panel_a_size, old.panel_b_size],
+                    target_size: old.target_size,
+                    number_of_view_points: old.number_of_view_points,
+                }
+                .into_matrix();
+            }
+
+            // A list of layers starts with its length, a mapping with its number of rays
+            let ((_, _, next), _): ((Mat<f32>, Mat<f32>, usize), usize) =
+                bincode::serde::decode_from_slice(bytes, config)?;
+            if next == l_vec.nrows() {
+                let (old, _): (TwoPanelStereoMatrix, usize) =
+                    bincode::serde::decode_from_slice(bytes, config)?;
+                return SparseStereoMatrix {
+                    l_vec: old.l_vec,
+                    l_rgb: old.l_rgb,
+                    layers: vec![old.a_matrix, old.b_matrix],
+                    layer_sizes: vec![old.panel_a_size, old.panel_b_size],
+                    target_size: old.target_size,
+                    number_of_view_points: old.number_of_view_points,
+                }
+                .into_matrix();
+            }
+        }
+        let (old, _): (SparseStereoMatrix, usize) =
+            bincode::serde::decode_from_slice(bytes, config)?;
+        old.into_matrix()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::capture_path;
+
+    #[test]
+    fn archived_captures_migrate() {
+        // Written before the header, with a fixed pair of panels and grayscale stereo samples
+        let (header, sep) = LFMatrices::load_with_header("Default".to_string()).unwrap();
+        assert_eq!(header.version, 0);
+        assert_eq!(sep.layers.len(), 2);
+
+        let (header, stereo) = StereoMatrix::load_with_header("Default".to_string()).unwrap();
+        assert_eq!(header.version, 0);
+        assert_eq!(stereo.layers.len(), 2);
+        assert_eq!(stereo.layer_sizes.len(), 2);
+        assert_eq!(stereo.l_rgb.ncols(), 3);
+        assert_eq!(stereo.l_rgb.col(2), stereo.l_vec.col(0));
+
+        // Stacked layers with sparse mappings, bare from before the header and as version 1
+        let config = bincode::config::standard();
+        let old = SparseStereoMatrix {
+            l_vec: stereo.l_vec.clone(),
+            l_rgb: stereo.l_rgb.clone(),
+            layers: stereo.layers.iter().map(sparse).collect(),
+            layer_sizes: stereo.layer_sizes.clone(),
+            target_size: stereo.target_size,
+            number_of_view_points: stereo.number_of_view_points,
+        };
+        let bytes = bincode::serde::encode_to_vec(&old, config).unwrap();
+        for version in [0, 1] {
+            let migrated = StereoMatrix::migrate(version, &bytes).unwrap();
+            assert_eq!(migrated.layers, stereo.layers);
+            assert_eq!(migrated.l_rgb, stereo.l_rgb);
+        }
+
+        let complete = |mapping: &CompleteMapping| SparseCompleteMapping {
+            x: SparsePass::List(mapping.x.matrix.iter().map(sparse).collect()),
+            size: mapping.size,
+            y: SparsePass::List(mapping.y.matrix.iter().map(sparse).collect()),
+        };
+        let old = SparseLFMatrices {
+            layers: sep.layers.iter().map(complete).collect(),
+            t: complete(&sep.t),
+            target_size: sep.target_size,
+            number_of_view_points: sep.number_of_view_points,
+        };
+        let bytes = bincode::serde::encode_to_vec(&old, config).unwrap();
+        for version in [0, 1] {
+            let migrated = LFMatrices::migrate(version, &bytes).unwrap();
+            assert_eq!(migrated.layers.len(), 2);
+            assert_eq!(migrated.layers[1].y.matrix, sep.layers[1].y.matrix);
+            assert_eq!(migrated.t.x.matrix, sep.t.x.matrix);
+        }
+
+        // One pixel per ray, as version 2 stored them
+        let old = NearestStereoMatrix {
+            l_vec: stereo.l_vec.clone(),
+            l_rgb: stereo.l_rgb.clone(),
+            layers: stereo.layers.iter().map(nearest).collect(),
+            layer_sizes: stereo.layer_sizes.clone(),
+            target_size: stereo.target_size,
+            number_of_view_points: stereo.number_of_view_points,
+        };
+        let bytes = bincode::serde::encode_to_vec(&old, config).unwrap();
+        assert_eq!(
+            StereoMatrix::migrate(2, &bytes).unwrap().layers,
+            stereo.layers
+        );
+
+        let complete = |mapping: &CompleteMapping| NearestCompleteMapping {
+            x: mapping.x.matrix.iter().map(nearest).collect(),
+            size: mapping.size,
+            y: mapping.y.matrix.iter().map(nearest).collect(),
+        };
+        let old = NearestLFMatrices {
+            layers: sep.layers.iter().map(complete).collect(),
+            t: complete(&sep.t),
+            target_size: sep.target_size,
+            number_of_view_points: sep.number_of_view_points,
+        };
+        let bytes = bincode::serde::encode_to_vec(&old, config).unwrap();
+        let migrated = LFMatrices::migrate(2, &bytes).unwrap();
+        assert_eq!(migrated.layers[0].x.matrix, sep.layers[0].x.matrix);
+        assert_eq!(migrated.t.y.matrix, sep.t.y.matrix);
+    }
+
+    fn sparse(mapping: &IndexMapping) -> SparseAsList {
+        SparseAsList {
+            shape: mapping.shape(),
+            triplet_list: mapping.hits().collect(),
+        }
+    }
+
+    fn nearest(mapping: &IndexMapping) -> NearestMapping {
+        NearestMapping {
+            indices: (0..mapping.nrows())
+                .map(|row| mapping.hit(row).map_or(u32::MAX, |col| col as u32))
+                .collect(),
+            ncols: mapping.ncols(),
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let stereo = StereoMatrix::load("Default".to_string()).unwrap();
+        let metadata = CaptureMetadata {
+            camera_positions: vec![Point3::new(0.0, 1.0, 5.0), Point3::new(0.5, 1.0, 5.0)],
+            kernel: true,
+            kernel_size: 0.5,
+            ..Default::default()
+        };
+        let name = "header_round_trip".to_string();
+        stereo.save(name.clone(), &metadata).unwrap();
+        let path = capture_path("stereo", name.clone());
+
+        let loaded = StereoMatrix::load_with_header(name);
+        let wrong_kind = load::<LFMatrices>(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let (header, loaded) = loaded.unwrap();
+        assert_eq!(header.version, CAPTURE_VERSION);
+        assert_eq!(header.kind, CaptureKind::Stereo);
+        assert!(header.created > 0);
+        assert_eq!(header.metadata.camera_positions, metadata.camera_positions);
+        assert!(header.metadata.kernel);
+        assert_eq!(loaded.l_vec, stereo.l_vec);
+        assert_eq!(loaded.layer_sizes, stereo.layer_sizes);
+        assert!(matches!(wrong_kind, Err(Error::WrongCaptureKind { .. })));
+    }
+}
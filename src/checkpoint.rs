@@ -0,0 +1,535 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    hash::Hasher,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use faer::Mat;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    capture::CaptureKind,
+    error::{Error, Result, WithPath},
+    initialization::Initialization,
+    progress::SolveObserver,
+    regularizer::Regularization,
+    report::SolveReport,
+    scalar::{self, Scalar},
+    solver::SolverKind,
+    LFMatrices, LFSettings, Lff, StereoMatrix,
+};
+
+/// First bytes of every checkpoint
+const MAGIC: &[u8; 8] = b"LFCHECKP";
+/// Layout written by `Checkpointer`, changes with `Checkpoint` and `SolveSettings` only
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+/// File of the checkpoint `name` of an approach (`sep` or `stereo`)
+pub(crate) fn checkpoint_path(approach: &str, name: String) -> PathBuf {
+    let name = if !name.ends_with(".ckpt") {
+        format!("{name}.ckpt")
+    } else {
+        name
+    };
+    PathBuf::from(format!("./saves/checkpoints/{approach}/{name}"))
+}
+
+/// What a checkpoint has to be resumed against
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CaptureSummary {
+    pub kind: CaptureKind,
+    pub target_size: (u32, u32),
+    pub number_of_view_points: u32,
+    /// FNV-1a of the encoded capture, and of the target for separable captures. Differs between
+    /// the precisions of a capture.
+    pub fingerprint: u64,
+}
+
+impl fmt::Display for CaptureSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} capture of a {}x{} target from {} view points ({:016x})",
+            self.kind,
+            self.target_size.0,
+            self.target_size.1,
+            self.number_of_view_points,
+            self.fingerprint
+        )
+    }
+}
+
+/// Captures a solve can be checkpointed on
+pub(crate) trait Checkpointed: Serialize {
+    /// Directory of the checkpoints, as for the captures
+    const APPROACH: &'static str;
+
+    fn summary(&self) -> CaptureSummary;
+}
+
+impl<T: Scalar> Checkpointed for LFMatrices<T> {
+    const APPROACH: &'static str = "sep";
+
+    fn summary(&self) -> CaptureSummary {
+        // The target is not part of the capture, but every solve depends on it
+        let mut hasher = fingerprint(self);
+        Hasher::write(&mut hasher, self.c_t.as_bytes());
+        CaptureSummary {
+            kind: CaptureKind::Separable,
+            target_size: self.target_size,
+            number_of_view_points: self.number_of_view_points,
+            fingerprint: hasher.finish(),
+        }
+    }
+}
+
+impl<T: Scalar> Checkpointed for StereoMatrix<T> {
+    const APPROACH: &'static str = "stereo";
+
+    fn summary(&self) -> CaptureSummary {
+        CaptureSummary {
+            kind: CaptureKind::Stereo,
+            target_size: self.target_size,
+            number_of_view_points: self.number_of_view_points,
+            fingerprint: fingerprint(self).finish(),
+        }
+    }
+}
+
+/// FNV-1a, stable across builds unlike the std hasher
+struct Fnv(u64);
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+impl Write for Fnv {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Hasher::write(self, buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Hash of the capture as it is saved, without holding the encoding in memory
+fn fingerprint<T: Serialize>(capture: &T) -> Fnv {
+    let mut hasher = Fnv(0xcbf29ce484222325);
+    bincode::serde::encode_into_std_write(capture, &mut hasher, bincode::config::standard())
+        .expect("Hashing a capture can not fail");
+    hasher
+}
+
+/// Where one channel of a solve was
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ChannelState {
+    /// Iterations done
+    pub iteration: usize,
+    /// Every frame of every layer, indexed by layer then frame. Stereo panels are column vectors.
+    /// Kept in `f64` whatever the precision of the solve, so no panel loses precision.
+    pub layers: Vec<Vec<Mat<f64>>>,
+    /// Residual of every iteration so far
+    pub error: VecDeque<f32>,
+}
+
+/// The part of `LFSettings` a checkpoint records, what its panels depend on. Kept apart so
+/// settings that don't change a solve can come and go without invalidating checkpoints.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SolveSettings {
+    /// Seed of `Initialization::Random`, if the panels started from noise
+    pub seed: Option<u64>,
+    /// Iterations of the solve that wrote the checkpoint
+    pub iter_count: usize,
+    pub solver: SolverKind,
+    pub frames: usize,
+    pub colour: bool,
+    pub filter: bool,
+    pub regularization: Regularization,
+}
+
+impl SolveSettings {
+    pub fn new(settings: &LFSettings) -> Self {
+        SolveSettings {
+            seed: match settings.initialization {
+                Initialization::Random { seed } => Some(seed),
+                _ => None,
+            },
+            iter_count: settings.iter_count,
+            solver: settings.solver,
+            frames: settings.frames.max(1),
+            colour: settings.colour,
+            filter: settings.filter,
+            regularization: settings.regularization,
+        }
+    }
+
+    /// Why a solve with `settings` would not continue the same way, if it would not. The seed
+    /// only decides where a solve starts and the iteration count can be extended.
+    fn mismatch(&self, settings: &LFSettings) -> Option<String> {
+        let current = SolveSettings::new(settings);
+        let differs = |name: &str, written: &dyn fmt::Debug, resumed: &dyn fmt::Debug| {
+            Some(format!(
+                "written with {name} {written:?}, resumed with {resumed:?}"
+            ))
+        };
+        if self.solver != current.solver {
+            return differs("solver", &self.solver, &current.solver);
+        }
+        if self.frames != current.frames {
+            return differs("frames", &self.frames, &current.frames);
+        }
+        if self.colour != current.colour {
+            return differs("colour", &self.colour, &current.colour);
+        }
+        if self.filter != current.filter {
+            return differs("filter", &self.filter, &current.filter);
+        }
+        if self.regularization != current.regularization {
+            return differs(
+                "regularization",
+                &self.regularization,
+                &current.regularization,
+            );
+        }
+        None
+    }
+}
+
+/// State of a solve, written every `LFSettings::checkpoint_every` iterations
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub capture: CaptureSummary,
+    pub settings: SolveSettings,
+    /// Channels are solved independently, so each may be at a different iteration. `None` until
+    /// the channel writes its first checkpoint.
+    pub channels: Vec<Option<ChannelState>>,
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path).with_path(path)?;
+        let Some(rest) = bytes.strip_prefix(MAGIC) else {
+            return Err(Error::CheckpointMismatch {
+                path: path.to_path_buf(),
+                reason: "not a checkpoint".to_string(),
+            });
+        };
+        let version = rest
+            .get(..4)
+            .map(|version| u32::from_le_bytes(version.try_into().unwrap()))
+            .unwrap_or(0);
+        if version != CHECKPOINT_VERSION {
+            return Err(Error::CheckpointMismatch {
+                path: path.to_path_buf(),
+                reason: format!("format version {version}, expected {CHECKPOINT_VERSION}"),
+            });
+        }
+        Ok(
+            bincode::serde::decode_from_slice(&rest[4..], bincode::config::standard())
+                .with_path(path)?
+                .0,
+        )
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory).with_path(directory)?;
+        }
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&CHECKPOINT_VERSION.to_le_bytes());
+        bytes.extend(
+            bincode::serde::encode_to_vec(self, bincode::config::standard()).with_path(path)?,
+        );
+        // Replace the previous checkpoint only once the new one is complete
+        let partial = path.with_extension("ckpt.partial");
+        std::fs::write(&partial, bytes).with_path(&partial)?;
+        std::fs::rename(&partial, path).with_path(path)
+    }
+
+    /// Errors unless the checkpoint was written for `capture` with `settings`, as far as they
+    /// change the solve
+    pub(crate) fn validate<T: Checkpointed>(
+        &self,
+        capture: &T,
+        settings: &LFSettings,
+        path: &Path,
+    ) -> Result<()> {
+        let mismatch = |reason: String| {
+            Err(Error::CheckpointMismatch {
+                path: path.to_path_buf(),
+                reason,
+            })
+        };
+        let summary = capture.summary();
+        if self.capture != summary {
+            return mismatch(format!("written for {}, not {summary}", self.capture));
+        }
+        if let Some(reason) = self.settings.mismatch(settings) {
+            return mismatch(reason);
+        }
+        let frames = self.settings.frames;
+        for state in self.channels.iter().flatten() {
+            if state.layers.iter().any(|layer| layer.len() != frames) {
+                return mismatch(format!("panels are not of {frames} frame(s)"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Continues the solve of the checkpoint `name` of `capture` with `settings`, which have to
+/// agree with the `SolveSettings` it was written with. The iteration count can differ, so a
+/// solve can be extended. Further checkpoints overwrite `name`.
+pub(crate) fn resume<T: Checkpointed + Lff>(
+    capture: &T,
+    name: String,
+    settings: &LFSettings,
+    observer: &dyn SolveObserver,
+) -> Result<Option<SolveReport>> {
+    let path = checkpoint_path(T::APPROACH, name.clone());
+    let checkpoint = Checkpoint::load(&path)?;
+    checkpoint.validate(capture, settings, &path)?;
+    let settings = LFSettings {
+        save_to: name,
+        ..settings.clone()
+    };
+    capture.factorize_from(&settings, observer, Some(checkpoint))
+}
+
+/// Writes the checkpoints of a solve, and holds the state it resumes from
+pub(crate) struct Checkpointer {
+    path: PathBuf,
+    every: usize,
+    checkpoint: Mutex<Checkpoint>,
+    /// First write that failed, the solve carries on without its checkpoints
+    failed: Mutex<Option<Error>>,
+}
+
+impl Checkpointer {
+    /// `None` if the solve neither resumes nor writes checkpoints
+    pub fn new<T: Checkpointed>(
+        capture: &T,
+        settings: &LFSettings,
+        resume: Option<Checkpoint>,
+    ) -> Option<Self> {
+        let checkpoint = match resume {
+            // Started from the same seed, only for more iterations
+            Some(checkpoint) => Checkpoint {
+                settings: SolveSettings {
+                    iter_count: settings.iter_count,
+                    ..checkpoint.settings
+                },
+                ..checkpoint
+            },
+            None if settings.checkpoint_every > 0 => Checkpoint {
+                capture: capture.summary(),
+                settings: SolveSettings::new(settings),
+                channels: Vec::new(),
+            },
+            None => return None,
+        };
+        Some(Checkpointer {
+            path: checkpoint_path(T::APPROACH, settings.save_to.clone()),
+            every: settings.checkpoint_every,
+            checkpoint: Mutex::new(checkpoint),
+            failed: Mutex::new(None),
+        })
+    }
+
+    /// State `channel` resumes from
+    pub fn start(&self, channel: usize) -> Option<ChannelState> {
+        let checkpoint = self.checkpoint.lock().unwrap();
+        checkpoint.channels.get(channel).cloned().flatten()
+    }
+
+    /// Writes a checkpoint if one is due after `iteration`, or if `force`d
+    pub fn record<T: Scalar>(
+        &self,
+        channel: usize,
+        iteration: usize,
+        layers: &[Vec<Mat<T>>],
+        error: &VecDeque<f32>,
+        force: bool,
+    ) {
+        let due = self.every > 0 && (force || iteration.is_multiple_of(self.every));
+        if !due {
+            return;
+        }
+        let mut checkpoint = self.checkpoint.lock().unwrap();
+        if checkpoint.channels.len() <= channel {
+            checkpoint.channels.resize(channel + 1, None);
+        }
+        checkpoint.channels[channel] = Some(ChannelState {
+            iteration,
+            layers: scalar::cast_layers(layers),
+            error: error.clone(),
+        });
+        if let Err(err) = checkpoint.save(&self.path) {
+            self.failed.lock().unwrap().get_or_insert(err);
+        }
+    }
+
+    /// Why a checkpoint of the solve could not be written, if one couldn't
+    pub fn take_error(&self) -> Option<Error> {
+        self.failed.lock().unwrap().take()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::synthetic::gradient_scene;
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let path = std::env::temp_dir().join("lf_checkpoint_round_trip.ckpt");
+        let checkpoint = Checkpoint {
+            capture: CaptureSummary {
+                kind: CaptureKind::Stereo,
+                target_size: (4, 3),
+                number_of_view_points: 2,
+                fingerprint: 7,
+            },
+            settings: SolveSettings::new(&LFSettings::default()),
+            channels: vec![
+                None,
+                Some(ChannelState {
+                    iteration: 3,
+                    layers: vec![vec![Mat::from_fn(5, 1, |x, _| x as f64)]],
+                    error: VecDeque::from([0.5, 0.25]),
+                }),
+            ],
+        };
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.capture, checkpoint.capture);
+        assert!(loaded.channels[0].is_none());
+        let state = loaded.channels[1].as_ref().unwrap();
+        assert_eq!(state.iteration, 3);
+        assert_eq!(
+            state.layers,
+            checkpoint.channels[1].as_ref().unwrap().layers
+        );
+        assert_eq!(state.error, VecDeque::from([0.5, 0.25]));
+    }
+
+    #[test]
+    fn resumes_need_the_settings_that_change_the_solve() {
+        let written = LFSettings {
+            initialization: Initialization::Random { seed: 4 },
+            ..Default::default()
+        };
+        let recorded = SolveSettings::new(&written);
+        assert_eq!(recorded.seed, Some(4));
+
+        // Longer solves, other threads or prints continue the same way
+        let extended = LFSettings {
+            iter_count: 50,
+            checkpoint_every: 5,
+            debug_prints: false,
+            cache_budget: 0.0,
+            ..written.clone()
+        };
+        assert_eq!(recorded.mismatch(&extended), None);
+
+        let other_solver = LFSettings {
+            solver: SolverKind::LeastSquares,
+            ..written.clone()
+        };
+        let reason = recorded.mismatch(&other_solver).unwrap();
+        assert!(reason.contains("LeastSquares"), "{reason}");
+        let more_frames = LFSettings {
+            frames: 2,
+            ..written
+        };
+        assert!(recorded.mismatch(&more_frames).is_some());
+    }
+
+    #[test]
+    fn resumed_solve_matches_uninterrupted() {
+        let (sampler, image) = gradient_scene();
+        let separable = sampler.sample_separable(&image);
+        let stereo = sampler.sample_stereo(&image);
+        let name = "resumed_solve_matches_uninterrupted".to_string();
+        let settings = LFSettings {
+            iter_count: 12,
+            save_error: true,
+            debug_prints: false,
+            colour: true,
+            save_to: name.clone(),
+            ..Default::default()
+        };
+        let first_half = LFSettings {
+            iter_count: 6,
+            checkpoint_every: 3,
+            ..settings.clone()
+        };
+
+        let full = stereo.factorize(&settings, &()).unwrap().unwrap();
+        stereo.factorize(&first_half, &()).unwrap().unwrap();
+        let resumed = stereo
+            .resume(name.clone(), &settings, &())
+            .unwrap()
+            .unwrap();
+        assert_eq!(resumed.frames, full.frames);
+        assert_eq!(resumed.residuals, full.residuals);
+        assert_eq!(resumed.iterations, 12);
+        // Only the resumed iterations were timed
+        assert_eq!(resumed.times.len(), 6);
+
+        // The checkpoint belongs to the stereo capture of this target only
+        let other = sampler.sample_stereo(&image::DynamicImage::new_rgb8(16, 16));
+        let Err(err) = other.resume(name.clone(), &settings, &()) else {
+            panic!("resumed against a different capture");
+        };
+        assert!(matches!(err, Error::CheckpointMismatch { .. }));
+
+        separable.factorize(&first_half, &()).unwrap().unwrap();
+        let resumed = separable.resume(name.clone(), &settings, &()).unwrap();
+        assert_eq!(
+            resumed.unwrap().frames,
+            separable.factorize(&settings, &()).unwrap().unwrap().frames
+        );
+
+        for approach in ["sep", "stereo"] {
+            std::fs::remove_file(checkpoint_path(approach, name.clone())).ok();
+        }
+    }
+
+    #[test]
+    fn failed_writes_are_reported() {
+        let (sampler, image) = gradient_scene();
+        let stereo = sampler.sample_stereo(&image);
+        let name = "failed_writes_are_reported".to_string();
+        // A directory where the partial checkpoint goes can't be written over
+        let partial = checkpoint_path("stereo", name.clone()).with_extension("ckpt.partial");
+        std::fs::create_dir_all(&partial).unwrap();
+        let settings = LFSettings {
+            iter_count: 4,
+            checkpoint_every: 2,
+            debug_prints: false,
+            save_to: name,
+            ..Default::default()
+        };
+        let report = stereo.factorize(&settings, &()).unwrap().unwrap();
+        std::fs::remove_dir(&partial).ok();
+        assert_eq!(report.iterations, 4);
+        assert!(matches!(
+            report.checkpoint_error.as_deref(),
+            Some(Error::Io { .. })
+        ));
+    }
+}
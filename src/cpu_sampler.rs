@@ -0,0 +1,782 @@
+use std::collections::VecDeque;
+
+use cgmath::{EuclideanSpace, InnerSpace, Vector2, Vector3};
+use faer::Mat;
+use image::DynamicImage;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    camera::Camera,
+    error::Result,
+    mapping::{IndexMapping, MappingMode, SamplePattern, NO_HIT},
+    save::Save,
+    scene::{Scene, ScenePanel, Target},
+    shape::{Quad, VWPanel},
+    utils, CompleteMapping, LFMatrices, MappingMatrix, StereoMatrix,
+};
+
+/// Taps of a ray along one axis of a panel
+type Taps = [(u32, f32); 2];
+
+/// Taps of a target pixel on every layer, one layer after the other, and its colour
+type PixelSample = (Vec<(u32, f32)>, [f32; 3]);
+
+/// Rays that miss a panel keep index 0, like the zeroed GPU buffers
+const MISS: Taps = [(0, 1.0), (NO_HIT, 0.0)];
+
+/// Most rays per target pixel along one axis
+pub const MAX_SAMPLES: u32 = 16;
+
+/// Adds the taps of one ray of a pixel to the row of the pixel, merging pixels hit twice
+fn accumulate(row: &mut Vec<(u32, f32)>, taps: impl IntoIterator<Item = (u32, f32)>, weight: f32) {
+    for (index, tap_weight) in taps {
+        if index == NO_HIT || tap_weight == 0.0 {
+            continue;
+        }
+        match row.iter_mut().find(|entry| entry.0 == index) {
+            Some(entry) => entry.1 += weight * tap_weight,
+            None => row.push((index, weight * tap_weight)),
+        }
+    }
+}
+
+/// CPU version of the ray casting done by `diagonal.wgsl` and `reverse_projection.wgsl`.
+/// Builds the same `LFMatrices` and `StereoMatrix` as `LFBuffers` and `StereoscopeBuffer`,
+/// without needing a GPU.
+pub struct CpuSampler {
+    target: Quad,
+    target_pixel_count: Vector2<u32>,
+    /// Closest panel to the camera first, one layer each
+    panels: Vec<VWPanel>,
+    observers: Vec<Vector3<f32>>,
+    mode: MappingMode,
+    /// Rays per target pixel along each axis
+    samples: u32,
+    pattern: SamplePattern,
+}
+
+impl CpuSampler {
+    pub fn new(
+        world: &Target,
+        panels: &[ScenePanel],
+        camera: &Camera,
+        history: &VecDeque<Camera>,
+    ) -> Self {
+        Self::from_parts(
+            world.placed_quad(),
+            world.pixel_count,
+            Scene::placed_panels(panels, camera),
+            history.iter().map(|x| x.position.to_vec()).collect(),
+        )
+    }
+
+    /// Placed geometry, panels have to be sorted closest to the observers first
+    pub fn from_parts(
+        target: Quad,
+        target_pixel_count: Vector2<u32>,
+        panels: Vec<VWPanel>,
+        observers: Vec<Vector3<f32>>,
+    ) -> Self {
+        assert!(panels.len() >= 2, "Sampling needs two panels");
+        CpuSampler {
+            target,
+            target_pixel_count,
+            panels,
+            observers,
+            mode: MappingMode::Nearest,
+            samples: 1,
+            pattern: SamplePattern::Stratified,
+        }
+    }
+
+    /// Spread every ray over the panel pixels around it, `Nearest` by default like the GPU
+    pub fn with_mapping_mode(mut self, mode: MappingMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Cast `samples` x `samples` rays through every target pixel and average what they hit,
+    /// a single ray through the pixel center by default like the GPU
+    pub fn with_supersampling(mut self, samples: u32, pattern: SamplePattern) -> Self {
+        assert!(
+            (1..=MAX_SAMPLES).contains(&samples),
+            "Supersampling takes 1 to {MAX_SAMPLES} rays per axis"
+        );
+        self.samples = samples;
+        self.pattern = pattern;
+        self
+    }
+
+    /// Sampler and target image of a scene capture in `./saves/scene_capture/{name}/`.
+    /// Panels are sorted relative to the first camera of the capture.
+    pub fn from_capture(name: &str) -> Result<(Self, DynamicImage)> {
+        let save = Save::load(name)?;
+        let camera = save.first_camera()?;
+        let panels: Vec<ScenePanel> = save.panels().into_iter().cloned().collect();
+        let sampler = Self::new(&save.target, &panels, camera, &save.cameras);
+        Ok((sampler, save.to_cache()?.target_image))
+    }
+
+    pub fn number_of_view_points(&self) -> u32 {
+        self.observers.len() as u32
+    }
+
+    /// Panel sizes as (rows, columns), as `AppState` passes them to the GPU samplers
+    fn panel_size(&self, index: usize) -> (u32, u32) {
+        let pixel_count = self.panels[index].pixel_count;
+        (pixel_count.y, pixel_count.x)
+    }
+
+    /// Ray from a point of the target, in pixels from its corner, towards the observer, then the
+    /// pixels it crosses on every panel as taps along x and y. The footprint of the ray is where
+    /// the rays of the next target pixels cross the panel, shared by the rays of a pixel.
+    fn cast_taps(&self, position: Vector2<f32>, observer: Vector3<f32>) -> Vec<[Taps; 2]> {
+        let ray = |position: Vector2<f32>| {
+            let origin = self
+                .target
+                .position_to_world(self.target_pixel_count, position);
+            (origin, (observer - origin).normalize())
+        };
+        let (origin, direction) = ray(position);
+        let steps = [
+            position + Vector2::new(1.0, 0.0),
+            position + Vector2::new(0.0, 1.0),
+        ]
+        .map(ray);
+
+        self.panels
+            .iter()
+            .map(|panel| {
+                let Some(hit) = panel.texture_hit(origin, direction) else {
+                    return [MISS, MISS];
+                };
+                let footprint = if self.mode == MappingMode::AreaCoverage {
+                    let [x, y] = steps.map(|(origin, direction)| {
+                        panel
+                            .texture_hit(origin, direction)
+                            .map_or(Vector2::new(f32::NAN, f32::NAN), |step| step - hit)
+                    });
+                    Vector2::new(x.x.abs() + y.x.abs(), x.y.abs() + y.y.abs()) / self.samples as f32
+                } else {
+                    Vector2::new(1.0, 1.0)
+                };
+                let pixels = panel.pixel_count;
+                [
+                    self.mode.taps(
+                        hit.x * pixels.x as f32,
+                        pixels.x,
+                        footprint.x * pixels.x as f32,
+                    ),
+                    self.mode.taps(
+                        hit.y * pixels.y as f32,
+                        pixels.y,
+                        footprint.y * pixels.y as f32,
+                    ),
+                ]
+            })
+            .collect()
+    }
+
+    /// Position of the ray in `cell` of a target pixel along one axis, `seed` has to be unique
+    /// for every ray and axis
+    fn sub_pixel(&self, pixel: u32, cell: u32, seed: u64) -> f32 {
+        pixel as f32 + self.pattern.offset(cell, self.samples, seed)
+    }
+
+    /// Mirror of `diagonal.wgsl`: the first row of the target records the x mappings,
+    /// the first column the y mappings.
+    /// Rays that miss a panel keep index 0, like the zeroed GPU buffers.
+    pub fn sample_separable(&self, c_t: &DynamicImage) -> LFMatrices {
+        let pixel_count = self.target_pixel_count;
+        let number_of_view_points = self.number_of_view_points();
+        let target_size = (pixel_count.y, pixel_count.x);
+
+        let number_of_rays = (
+            target_size.1 * number_of_view_points,
+            target_size.0 * number_of_view_points,
+        );
+        let rays_per_view_point = (target_size.1, target_size.0);
+
+        // The shader strides x with the y pixel count (and the other way around),
+        // so the buffers can be longer than the rays read back
+        let length_x = number_of_rays
+            .0
+            .max(pixel_count.x + pixel_count.y * number_of_view_points);
+        let length_y = number_of_rays
+            .1
+            .max(pixel_count.y + pixel_count.x * number_of_view_points);
+        // Every ray of a pixel can hit two pixels of the panel
+        let taps = 2 * self.samples as usize;
+        let mut miss = MISS.to_vec();
+        miss.resize(taps, (NO_HIT, 0.0));
+        let mut t_x = vec![0u32; length_x as usize];
+        let mut t_y = vec![0u32; length_y as usize];
+        let mut layers_x = vec![vec![miss.clone(); length_x as usize]; self.panels.len()];
+        let mut layers_y = vec![vec![miss; length_y as usize]; self.panels.len()];
+
+        // Taps of every panel along `axis`, averaged over the rays of the pixel
+        let pixel_taps = |pixel: Vector2<u32>, axis: usize, observer: Vector3<f32>, seed: u64| {
+            let mut rows = vec![Vec::with_capacity(taps); self.panels.len()];
+            for cell in 0..self.samples {
+                let mut position = Vector2::new(pixel.x as f32 + 0.5, pixel.y as f32 + 0.5);
+                let seed = seed * MAX_SAMPLES as u64 + cell as u64;
+                position[axis] = self.sub_pixel(pixel[axis], cell, seed);
+                for (row, hits) in rows.iter_mut().zip(self.cast_taps(position, observer)) {
+                    accumulate(row, hits[axis], 1.0 / self.samples as f32);
+                }
+            }
+            for row in rows.iter_mut() {
+                row.resize(taps, (NO_HIT, 0.0));
+            }
+            rows
+        };
+
+        for (observer_index, observer) in self.observers.iter().enumerate() {
+            let observer_index = observer_index as u32;
+            for x in 0..pixel_count.x {
+                let ray_index = (x + pixel_count.y * observer_index) as usize;
+                let rows = pixel_taps(Vector2::new(x, 0), 0, *observer, 2 * ray_index as u64);
+                t_x[ray_index] = x;
+                for (layer_x, row) in layers_x.iter_mut().zip(rows) {
+                    layer_x[ray_index] = row;
+                }
+            }
+            for y in 0..pixel_count.y {
+                let ray_index = (y + pixel_count.x * observer_index) as usize;
+                let rows = pixel_taps(Vector2::new(0, y), 1, *observer, 2 * ray_index as u64 + 1);
+                t_y[ray_index] = y;
+                for (layer_y, row) in layers_y.iter_mut().zip(rows) {
+                    layer_y[ray_index] = row;
+                }
+            }
+        }
+        for buffer in layers_x.iter_mut() {
+            buffer.truncate(number_of_rays.0 as usize);
+        }
+        for buffer in layers_y.iter_mut() {
+            buffer.truncate(number_of_rays.1 as usize);
+        }
+        t_x.truncate(number_of_rays.0 as usize);
+        t_y.truncate(number_of_rays.1 as usize);
+
+        let layers = layers_x
+            .into_iter()
+            .zip(layers_y)
+            .enumerate()
+            .map(|(index, (x, y))| {
+                let size = self.panel_size(index);
+                CompleteMapping::new(
+                    Self::view_mappings(&x, rays_per_view_point.0, size.1),
+                    Self::view_mappings(&y, rays_per_view_point.1, size.0),
+                    size,
+                )
+            })
+            .collect();
+        // The rays average over their own pixel, so they only ever hit that pixel of the target
+        let t =
+            utils::build_complete_mapping(t_x, t_y, rays_per_view_point, rays_per_view_point, true);
+
+        LFMatrices::new(layers, t, c_t.clone(), target_size, number_of_view_points)
+    }
+
+    /// One mapping per view point from the taps of every pixel
+    fn view_mappings(
+        rows: &[Vec<(u32, f32)>],
+        rays_per_view_point: u32,
+        columns: u32,
+    ) -> MappingMatrix {
+        let taps = rows.first().map_or(1, Vec::len);
+        let matrix = rows
+            .chunks(rays_per_view_point as usize)
+            .map(|view| IndexMapping::from_weighted_hits(&view.concat(), taps, columns as usize))
+            .collect();
+        MappingMatrix::new(matrix)
+    }
+
+    /// Colour of the target at a point in pixels from its corner. The nearest texel is taken
+    /// half a pixel before the point, the corner of the pixel for a ray through its center like
+    /// the GPU samplers. Weighted mappings read the texels around the point.
+    fn target_colour(&self, image: &image::RgbaImage, position: Vector2<f32>) -> [f32; 3] {
+        let pixel_count = self.target_pixel_count;
+        let (width, height) = image.dimensions();
+        let texel = |x: u32, y: u32| image.get_pixel(x, y).0.map(|x| x as f32 / 255.0);
+        if self.mode == MappingMode::Nearest {
+            let nearest = |position: f32, pixels: u32, texels: u32| {
+                let texel = (position as f64 - 0.5) * texels as f64 / pixels as f64;
+                (texel.max(0.0) as u32).min(texels.saturating_sub(1))
+            };
+            let colour = texel(
+                nearest(position.x, pixel_count.x, width),
+                nearest(position.y, pixel_count.y, height),
+            );
+            return [colour[0], colour[1], colour[2]];
+        }
+        let taps = |position: f32, pixels: u32, texels: u32| {
+            let scale = texels as f32 / pixels as f32;
+            self.mode
+                .taps(position * scale, texels, scale / self.samples as f32)
+        };
+        let mut colour = [0.0; 3];
+        for (x, weight_x) in taps(position.x, pixel_count.x, width) {
+            for (y, weight_y) in taps(position.y, pixel_count.y, height) {
+                if x == NO_HIT || y == NO_HIT {
+                    continue;
+                }
+                let texel = texel(x, y);
+                for channel in 0..3 {
+                    colour[channel] += weight_x * weight_y * texel[channel];
+                }
+            }
+        }
+        colour
+    }
+
+    /// Mirror of `reverse_projection.wgsl`, one ray per target pixel and observer.
+    /// The target colour is the nearest texel of `c_t`, or the texels around it when the rays
+    /// are spread over several pixels.
+    /// With supersampling every pixel averages the panel pixels and colours of all its rays.
+    pub fn sample_stereo(&self, c_t: &DynamicImage) -> StereoMatrix {
+        let pixel_count = self.target_pixel_count;
+        let number_of_view_points = self.number_of_view_points();
+        let rays_per_view_point = pixel_count.x * pixel_count.y;
+        let rays_cast = rays_per_view_point * number_of_view_points;
+        let layer_sizes: Vec<(u32, u32)> =
+            (0..self.panels.len()).map(|x| self.panel_size(x)).collect();
+        let image = c_t.to_rgba8();
+        // Every ray of a pixel can hit 2x2 pixels of a panel
+        let taps = 4 * (self.samples * self.samples) as usize;
+        let weight = 1.0 / (self.samples * self.samples) as f32;
+
+        // ray_index = x + width * y + observer * width * height
+        let rays: Vec<PixelSample> = (0..rays_cast)
+            .into_par_iter()
+            .map(|ray_index| {
+                let observer = self.observers[(ray_index / rays_per_view_point) as usize];
+                let pixel_index = ray_index % rays_per_view_point;
+                let pixel = Vector2::new(pixel_index % pixel_count.x, pixel_index / pixel_count.x);
+
+                let mut rows = vec![Vec::with_capacity(taps); self.panels.len()];
+                let mut colour = [0.0; 3];
+                for cell in 0..self.samples * self.samples {
+                    let cells = (MAX_SAMPLES * MAX_SAMPLES) as u64;
+                    let seed = 2 * (ray_index as u64 * cells + cell as u64);
+                    let position = Vector2::new(
+                        self.sub_pixel(pixel.x, cell % self.samples, seed),
+                        self.sub_pixel(pixel.y, cell / self.samples, seed + 1),
+                    );
+                    let hits = self.cast_taps(position, observer);
+                    for ((row, [x, y]), panel) in rows.iter_mut().zip(hits).zip(&self.panels) {
+                        let combined = x.into_iter().flat_map(|(x, weight_x)| {
+                            y.map(|(y, weight_y)| match x == NO_HIT || y == NO_HIT {
+                                true => (NO_HIT, 0.0),
+                                false => (x + y * panel.pixel_count.x, weight_x * weight_y),
+                            })
+                        });
+                        accumulate(row, combined, weight);
+                    }
+                    let sample = self.target_colour(&image, position);
+                    for channel in 0..3 {
+                        colour[channel] += weight * sample[channel];
+                    }
+                }
+                for row in rows.iter_mut() {
+                    row.resize(taps, (NO_HIT, 0.0));
+                }
+                (rows.concat(), colour)
+            })
+            .collect();
+
+        let l_rgb = Mat::from_fn(rays_cast as usize, 3, |x, y| rays[x].1[y]);
+        let l_vec = Mat::from_fn(rays_cast as usize, 1, |x, _y| utils::luma(rays[x].1));
+        let layers = layer_sizes
+            .iter()
+            .enumerate()
+            .map(|(layer, size)| {
+                let hits: Vec<(u32, f32)> = rays
+                    .iter()
+                    .flat_map(|x| &x.0[layer * taps..(layer + 1) * taps])
+                    .copied()
+                    .collect();
+                IndexMapping::from_weighted_hits(&hits, taps, (size.0 * size.1) as usize)
+            })
+            .collect();
+
+        StereoMatrix {
+            l_vec,
+            l_rgb,
+            layers,
+            layer_sizes,
+            target_size: (pixel_count.y, pixel_count.x),
+            number_of_view_points,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::Vector3;
+
+    use super::*;
+    use crate::report::SolveReport;
+    use crate::{
+        initialization::Initialization,
+        mapping::IndexMapping,
+        solver::SolverKind,
+        synthetic::{gradient_scene, stacked_sampler},
+        LFSettings, Lff,
+    };
+
+    /// Target at the origin, panels straight in front of it
+    fn aligned_sampler(observers: Vec<Vector3<f32>>) -> CpuSampler {
+        stacked_sampler(&[2.0, 1.0], 300, 300, observers)
+    }
+
+    #[test]
+    fn head_on_rays_hit_same_pixel() {
+        // Observer far away, rays are (almost) parallel to the z axis
+        let sampler = aligned_sampler(vec![Vector3::new(0.0, 0.0, 100000.0)]);
+        for pixel in [
+            Vector2::new(0, 0),
+            Vector2::new(150, 20),
+            Vector2::new(299, 299),
+        ] {
+            let center = Vector2::new(pixel.x as f32 + 0.5, pixel.y as f32 + 0.5);
+            let hits = sampler.cast_taps(center, sampler.observers[0]);
+            let taps = |x| [(x, 1.0), (NO_HIT, 0.0)];
+            assert_eq!(hits, vec![[taps(pixel.x), taps(pixel.y)]; 2]);
+        }
+    }
+
+    #[test]
+    fn separable_matches_stereo() {
+        let sampler = aligned_sampler(vec![
+            Vector3::new(0.1, -0.2, 4.0),
+            Vector3::new(-0.3, 0.1, 5.0),
+        ]);
+        let image = DynamicImage::new_rgb8(300, 300);
+        let separable = sampler.sample_separable(&image);
+        let stereo = sampler.sample_stereo(&image);
+
+        let mapping = |mapping: &IndexMapping| -> Vec<usize> {
+            (0..mapping.nrows())
+                .map(|row| mapping.hit(row).unwrap_or(0))
+                .collect()
+        };
+        let stereo_hits = mapping(&stereo.layers[0]);
+
+        // Parallel panels make the mapping separable: the ray through (x, y) crosses panel A at
+        // the column of ray (x, 0) and the row of ray (0, y), up to rounding at pixel borders
+        let mut mismatch = 0;
+        for view in 0..2 {
+            let columns = mapping(&separable.layers[0].x.matrix[view]);
+            let rows = mapping(&separable.layers[0].y.matrix[view]);
+            for (y, row) in rows.iter().enumerate() {
+                for (x, column) in columns.iter().enumerate() {
+                    let ray = x + 300 * y + view * 300 * 300;
+                    if stereo_hits[ray] != column + row * 300 {
+                        mismatch += 1;
+                    }
+                }
+            }
+        }
+        assert!(mismatch < 300 * 300 / 100, "{mismatch} rays disagree");
+    }
+
+    #[test]
+    fn expanded_separable_capture_matches_stereo_capture() {
+        let sampler = aligned_sampler(vec![
+            Vector3::new(0.13, -0.21, 4.3),
+            Vector3::new(-0.31, 0.17, 5.7),
+        ]);
+        let image = DynamicImage::new_rgb8(300, 300);
+        let expanded = sampler.sample_separable(&image).to_stereo();
+        let stereo = sampler.sample_stereo(&image);
+        assert_eq!(expanded.l_vec.nrows(), stereo.l_vec.nrows());
+        assert_eq!(expanded.layer_sizes, stereo.layer_sizes);
+        for (expanded, stereo) in expanded.layers.iter().zip(&stereo.layers) {
+            assert_eq!(expanded.shape(), stereo.shape());
+            // Observers at round distances put crossings on pixel borders, where the two
+            // samplers may round to different pixels
+            let mismatch = (0..stereo.nrows())
+                .filter(|ray| expanded.hit(*ray) != stereo.hit(*ray))
+                .count();
+            assert!(mismatch < stereo.nrows() / 1000, "{mismatch} rays disagree");
+        }
+    }
+
+    /// Solves `separable` and its stereo form, both have to reach the same panels
+    fn assert_solves_match(separable: &LFMatrices<f64>, settings: &LFSettings) {
+        let mut stereo = separable.to_stereo();
+        // The stereo solver updates its layers back to front, the separable one front to back.
+        // Listing them the other way around updates them in the same order, every layer starts
+        // from the same value.
+        stereo.layers.reverse();
+        stereo.layer_sizes.reverse();
+
+        let separable = separable.factorize(settings, &()).unwrap().unwrap();
+        let stereo = stereo.factorize(settings, &()).unwrap().unwrap();
+        let context = format!("{} with {} frame(s)", settings.solver, settings.frames);
+        assert_eq!(separable.residuals.len(), settings.iter_count, "{context}");
+        for (a, b) in separable.residuals.iter().zip(&stereo.residuals) {
+            assert!(
+                (a - b).abs() <= 1e-4 * b.max(1e-3),
+                "{context}: {a} against {b}"
+            );
+        }
+        let (separable, mut stereo) = (separable.layers(), stereo.layers().to_vec());
+        stereo.reverse();
+        assert_eq!(separable.len(), stereo.len());
+        for (a, b) in separable.iter().zip(&stereo) {
+            let (a, b) = (a.to_rgb8(), b.to_rgb8());
+            assert_eq!(a.dimensions(), b.dimensions(), "{context}");
+            let worst = a
+                .as_raw()
+                .iter()
+                .zip(b.as_raw())
+                .map(|(a, b)| a.abs_diff(*b))
+                .max();
+            assert!(worst <= Some(1), "{context}: panels differ by {worst:?}");
+        }
+    }
+
+    #[test]
+    fn separable_and_stereo_solves_match() {
+        let observers = vec![Vector3::new(0.1, -0.2, 4.0), Vector3::new(-0.3, 0.1, 5.0)];
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 16, |x, y| {
+            image::Rgb([(x * 16) as u8, (y * 16) as u8, 128])
+        }));
+        let settings = LFSettings {
+            iter_count: 15,
+            save_error: true,
+            debug_prints: false,
+            // Only the separable solver fills the pixels no ray sees
+            filter: false,
+            colour: true,
+            ..Default::default()
+        };
+
+        for mode in MappingMode::ALL {
+            let sampler =
+                stacked_sampler(&[2.0, 1.0], 16, 12, observers.clone()).with_mapping_mode(mode);
+            let separable = sampler.sample_separable(&image).cast::<f64>();
+            for solver in SolverKind::ALL {
+                assert_solves_match(
+                    &separable,
+                    &LFSettings {
+                        solver,
+                        ..settings.clone()
+                    },
+                );
+            }
+        }
+
+        let sampler = stacked_sampler(&[2.0, 1.5, 1.0], 16, 12, observers);
+        let separable = sampler.sample_separable(&image).cast::<f64>();
+        assert_solves_match(
+            &separable,
+            &LFSettings {
+                frames: 2,
+                ..settings
+            },
+        );
+    }
+
+    #[test]
+    fn separable_and_stereo_start_from_the_same_panels() {
+        let sampler = stacked_sampler(&[2.0, 1.0], 16, 12, vec![Vector3::new(0.13, -0.21, 4.3)]);
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 16, |x, y| {
+            image::Rgb([(x * 16) as u8, (y * 16) as u8, 128])
+        }));
+        let separable = sampler.sample_separable(&image);
+        let stereo = separable.to_stereo();
+        for initialization in [
+            Initialization::Random { seed: 3 },
+            Initialization::SquareRoot,
+            Initialization::LogLeastSquares,
+        ] {
+            // Without iterations the panels are where the solves start
+            let settings = LFSettings {
+                iter_count: 0,
+                filter: false,
+                colour: true,
+                debug_prints: false,
+                initialization,
+                ..Default::default()
+            };
+            let separable = separable.factorize(&settings, &()).unwrap().unwrap();
+            let stereo = stereo.factorize(&settings, &()).unwrap().unwrap();
+            assert_eq!(separable.iterations, 0);
+            assert_eq!(
+                separable.frames, stereo.frames,
+                "{}",
+                settings.initialization
+            );
+        }
+    }
+
+    #[test]
+    fn weighted_mappings_reduce_panel_resolution_error() {
+        // Every panel pixel covers 4x4 target pixels
+        let observers = vec![Vector3::new(0.1, -0.2, 4.0), Vector3::new(-0.3, 0.1, 5.0)];
+        let image = DynamicImage::new_rgb8(32, 32);
+        // Smooth content over the texture coordinates of the closest panel
+        let content = |x: f32, y: f32| (x * 2.0).sin() * (y * 3.0).cos();
+        let pixels = Mat::from_fn(64, 1, |index, _| {
+            content(
+                ((index % 8) as f32 + 0.5) / 8.0,
+                ((index / 8) as f32 + 0.5) / 8.0,
+            )
+        });
+
+        // Squared error of the panel seen through the mapping, against the exact crossings
+        let errors = MappingMode::ALL.map(|mode| {
+            let sampler =
+                stacked_sampler(&[2.0, 1.0], 32, 8, observers.clone()).with_mapping_mode(mode);
+            let sampled = sampler.sample_stereo(&image).layers[0].gather(&pixels);
+            let mut error = 0.0;
+            for (ray, sample) in sampled.col_as_slice(0).iter().enumerate() {
+                let observer = sampler.observers[ray / (32 * 32)];
+                let pixel = Vector2::new((ray % 32) as u32, (ray % (32 * 32) / 32) as u32);
+                let origin = sampler.target.pixel_to_world(Vector2::new(32, 32), pixel);
+                let direction = (observer - origin).normalize();
+                if let Some(hit) = sampler.panels[0].texture_hit(origin, direction) {
+                    error += (sample - content(hit.x, hit.y)).powi(2);
+                }
+            }
+            error
+        });
+        let [nearest, bilinear, area] = errors;
+        assert!(bilinear < nearest / 4.0, "{errors:?}");
+        assert!(area < nearest, "{errors:?}");
+    }
+
+    #[test]
+    fn supersampled_pixels_average_their_rays() {
+        // Head on, every target pixel covers 4x4 panel pixels
+        let far = vec![Vector3::new(0.0, 0.0, 100000.0)];
+        let image = DynamicImage::new_rgb8(16, 16);
+        let sampler = stacked_sampler(&[2.0, 1.0], 16, 64, far.clone())
+            .with_supersampling(4, SamplePattern::Stratified);
+        let row = |mapping: &IndexMapping, row: usize| -> Vec<(usize, f32)> {
+            let mut hits: Vec<(usize, f32)> = mapping
+                .hits()
+                .filter(|hit| hit.0 == row)
+                .map(|(_, col, weight)| (col, weight))
+                .collect();
+            hits.sort_by_key(|hit| hit.0);
+            hits
+        };
+
+        let stereo = sampler.sample_stereo(&image);
+        assert_eq!(stereo.l_vec.nrows(), 16 * 16);
+        let (x, y) = (5, 9);
+        let mut expected: Vec<(usize, f32)> = (0..16)
+            .map(|cell| (4 * x + cell % 4 + (4 * y + cell / 4) * 64, 1.0 / 16.0))
+            .collect();
+        expected.sort_by_key(|hit| hit.0);
+        assert_eq!(row(&stereo.layers[1], x + y * 16), expected);
+
+        let separable = sampler.sample_separable(&image);
+        let expected: Vec<(usize, f32)> = (0..4).map(|cell| (4 * x + cell, 0.25)).collect();
+        assert_eq!(row(&separable.layers[0].x.matrix[0], x), expected);
+
+        // Jittered rays stay inside their pixel, and every sampling jitters the same way
+        let jittered = stacked_sampler(&[2.0, 1.0], 16, 64, far)
+            .with_supersampling(3, SamplePattern::Jittered);
+        let stereo = jittered.sample_stereo(&image);
+        assert_eq!(stereo.layers, jittered.sample_stereo(&image).layers);
+        for pixel in 0..16 * 16 {
+            let hits = row(&stereo.layers[0], pixel);
+            let total: f32 = hits.iter().map(|hit| hit.1).sum();
+            assert!((total - 1.0).abs() < 1e-5);
+            let (x, y) = (pixel % 16, pixel / 16);
+            assert!(hits
+                .iter()
+                .all(|(col, _)| col % 64 / 4 == x && col / 64 / 4 == y));
+        }
+    }
+
+    #[test]
+    fn three_layers_against_two_layer_baseline() {
+        let observers = vec![
+            Vector3::new(0.1, -0.2, 4.0),
+            Vector3::new(-0.3, 0.1, 5.0),
+            Vector3::new(0.2, 0.3, 4.5),
+        ];
+        let two = stacked_sampler(&[2.0, 1.0], 32, 24, observers.clone());
+        let three = stacked_sampler(&[2.0, 1.5, 1.0], 32, 24, observers);
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(32, 32, |x, y| {
+            image::Rgb([(x * 8) as u8, (y * 8) as u8, 128])
+        }));
+        let settings = LFSettings {
+            iter_count: 60,
+            // Deeper layers start transparent, so both stacks start from the same light field
+            starting_values: (0.5, 1.0),
+            save_error: true,
+            debug_prints: false,
+            ..Default::default()
+        };
+
+        let solutions: [[_; 2]; 2] = [&two, &three].map(|sampler| {
+            [
+                sampler
+                    .sample_separable(&image)
+                    .factorize(&settings, &())
+                    .unwrap(),
+                sampler
+                    .sample_stereo(&image)
+                    .factorize(&settings, &())
+                    .unwrap(),
+            ]
+        });
+        for (layers, solutions) in [2, 3].into_iter().zip(&solutions) {
+            for report in solutions.iter().flatten() {
+                assert_eq!(report.layers().len(), layers);
+                let error = &report.residuals;
+                assert!(error.last() < error.first(), "{layers} layers: {error:?}");
+            }
+        }
+        // Once converged, the extra layer only adds freedom to the baseline
+        for (baseline, stack) in solutions[0].iter().zip(&solutions[1]) {
+            let final_error = |x: &Option<SolveReport>| x.as_ref().unwrap().residual().unwrap();
+            let (baseline, stack) = (final_error(baseline), final_error(stack));
+            assert!(stack <= baseline * 1.05, "{stack} against {baseline}");
+        }
+    }
+
+    #[test]
+    fn double_precision_solve_matches_single() {
+        let (sampler, image) = gradient_scene();
+        let settings = LFSettings {
+            iter_count: 20,
+            save_error: true,
+            debug_prints: false,
+            ..Default::default()
+        };
+        let separable = sampler.sample_separable(&image);
+        let stereo = sampler.sample_stereo(&image);
+        let solves: [[&dyn Lff; 2]; 2] = [
+            [&separable, &separable.cast::<f64>()],
+            [&stereo, &stereo.cast::<f64>()],
+        ];
+        for [single, double] in solves {
+            let single = single.factorize(&settings, &()).unwrap().unwrap();
+            let double = double.factorize(&settings, &()).unwrap().unwrap();
+            for (single, double) in single.residuals.iter().zip(&double.residuals) {
+                assert!(
+                    (single - double).abs() <= 1e-3 * double,
+                    "{single} against {double}"
+                );
+            }
+            // Both export to the same 8 bit images, up to rounding
+            for (single, double) in single.layers().iter().zip(double.layers()) {
+                let (single, double) = (single.to_luma8(), double.to_luma8());
+                assert_eq!(single.dimensions(), double.dimensions());
+                assert!(single
+                    .pixels()
+                    .zip(double.pixels())
+                    .all(|(a, b)| a.0[0].abs_diff(b.0[0]) <= 1));
+            }
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::{fmt, path::PathBuf};
+
+use crate::{
+    capture::{CaptureKind, CAPTURE_VERSION},
+    initialization::Initialization,
+};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Failures of the persistence paths, every variant but `RayBuffers` and
+/// `UnsupportedInitialization` names the file it happened on
+#[derive(Debug)]
+pub enum Error {
+    /// Creating, opening, reading or writing a file or directory
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Serializing a matrix capture
+    Encode {
+        path: PathBuf,
+        source: bincode::error::EncodeError,
+    },
+    /// Deserializing a matrix capture
+    Decode {
+        path: PathBuf,
+        source: bincode::error::DecodeError,
+    },
+    /// Reading or writing an image
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    /// Writing the settings of a scene capture
+    SettingsEncode { path: PathBuf, source: ron::Error },
+    /// Reading the settings of a scene capture
+    SettingsDecode {
+        path: PathBuf,
+        source: ron::error::SpannedError,
+    },
+    /// A scene capture without any camera to sample from
+    NoCameras { path: PathBuf },
+    /// A matrix capture written by a newer version of the format
+    UnsupportedVersion { path: PathBuf, version: u32 },
+    /// A separable capture loaded as a stereo one, or the other way around
+    WrongCaptureKind {
+        path: PathBuf,
+        expected: CaptureKind,
+        found: CaptureKind,
+    },
+    /// A checkpoint that can't be resumed against the capture it was given
+    CheckpointMismatch { path: PathBuf, reason: String },
+    /// Saved panels that don't fit the layers of the capture a solve starts from
+    PanelsMismatch { path: PathBuf, reason: String },
+    /// A GPU capture casting more rays than its buffers can hold
+    RayBuffers { rays: u64, bytes: u64, limit: u64 },
+    /// A starting point the solver can't start from
+    UnsupportedInitialization {
+        solver: &'static str,
+        initialization: Initialization,
+    },
+}
+
+impl Error {
+    /// The file the error happened on, `None` for the GPU samplers
+    pub fn path(&self) -> Option<&PathBuf> {
+        Some(match self {
+            Error::Io { path, .. }
+            | Error::Encode { path, .. }
+            | Error::Decode { path, .. }
+            | Error::Image { path, .. }
+            | Error::SettingsEncode { path, .. }
+            | Error::SettingsDecode { path, .. }
+            | Error::NoCameras { path }
+            | Error::UnsupportedVersion { path, .. }
+            | Error::WrongCaptureKind { path, .. }
+            | Error::CheckpointMismatch { path, .. }
+            | Error::PanelsMismatch { path, .. } => path,
+            Error::RayBuffers { .. } | Error::UnsupportedInitialization { .. } => return None,
+        })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self
+            .path()
+            .map(|x| x.display().to_string())
+            .unwrap_or_default();
+        match self {
+            Error::Io { source, .. } => write!(f, "{path}: {source}"),
+            Error::Encode { source, .. } => write!(f, "could not encode {path}: {source}"),
+            Error::Decode { source, .. } => write!(f, "could not decode {path}: {source}"),
+            Error::Image { source, .. } => write!(f, "image {path}: {source}"),
+            Error::SettingsEncode { source, .. } => {
+                write!(f, "could not write settings {path}: {source}")
+            }
+            Error::SettingsDecode { source, .. } => {
+                write!(f, "could not read settings {path}: {source}")
+            }
+            Error::NoCameras { .. } => write!(f, "scene capture {path} has no cameras"),
+            Error::UnsupportedVersion { version, .. } => write!(
+                f,
+                "capture {path} has format version {version}, newest supported is {CAPTURE_VERSION}"
+            ),
+            Error::WrongCaptureKind {
+                expected, found, ..
+            } => write!(f, "capture {path} is {found:?}, expected {expected:?}"),
+            Error::CheckpointMismatch { reason, .. } => {
+                write!(f, "checkpoint {path} does not match: {reason}")
+            }
+            Error::PanelsMismatch { reason, .. } => {
+                write!(f, "panels {path} do not match: {reason}")
+            }
+            Error::RayBuffers { rays, bytes, limit } => write!(
+                f,
+                "{rays} rays need {bytes} bytes of GPU buffers, at most {limit} fit"
+            ),
+            Error::UnsupportedInitialization {
+                solver,
+                initialization,
+            } => write!(f, "the {solver} solver can't start from {initialization}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Encode { source, .. } => Some(source),
+            Error::Decode { source, .. } => Some(source),
+            Error::Image { source, .. } => Some(source),
+            Error::SettingsEncode { source, .. } => Some(source),
+            Error::SettingsDecode { source, .. } => Some(source),
+            Error::NoCameras { .. }
+            | Error::UnsupportedVersion { .. }
+            | Error::WrongCaptureKind { .. }
+            | Error::CheckpointMismatch { .. }
+            | Error::PanelsMismatch { .. }
+            | Error::RayBuffers { .. }
+            | Error::UnsupportedInitialization { .. } => None,
+        }
+    }
+}
+
+/// Attaches the path to the error of a persistence call
+pub(crate) trait WithPath<T> {
+    fn with_path(self, path: &std::path::Path) -> Result<T>;
+}
+
+macro_rules! with_path {
+    ($source:ty, $variant:ident) => {
+        impl<T> WithPath<T> for std::result::Result<T, $source> {
+            fn with_path(self, path: &std::path::Path) -> Result<T> {
+                self.map_err(|source| Error::$variant {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        }
+    };
+}
+
+with_path!(std::io::Error, Io);
+with_path!(bincode::error::EncodeError, Encode);
+with_path!(bincode::error::DecodeError, Decode);
+with_path!(image::ImageError, Image);
+with_path!(ron::Error, SettingsEncode);
+with_path!(ron::error::SpannedError, SettingsDecode);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{LFMatrices, StereoMatrix};
+
+    #[test]
+    fn missing_capture_names_the_path() {
+        let Err(err) = LFMatrices::load("does_not_exist".to_string()) else {
+            panic!("loaded a capture that does not exist");
+        };
+        assert!(matches!(err, Error::Io { .. }));
+        assert!(err
+            .to_string()
+            .contains("saves/matrix_capture/sep/does_not_exist.ro"));
+
+        let Err(err) = StereoMatrix::load("does_not_exist".to_string()) else {
+            panic!("loaded a capture that does not exist");
+        };
+        assert!(err
+            .to_string()
+            .contains("saves/matrix_capture/stereo/does_not_exist.ro"));
+    }
+}
@@ -5,21 +5,32 @@ use egui::Ui;
 use image::DynamicImage;
 
 pub struct GifPlayer {
-    gif: Vec<(DynamicImage, DynamicImage)>,
+    /// Every layer of each frame
+    gif: Vec<Vec<DynamicImage>>,
     pub animation_duration: f32,
     pub frames: Option<usize>,
     pub animate: bool,
     pub animation_start: Option<Instant>,
+    /// Next frame of a time multiplexed solution, these advance every redraw instead of by time
+    pub multiplexed: Option<usize>,
 }
 
 impl GifPlayer {
-    pub fn create(frames: Vec<(DynamicImage, DynamicImage)>) -> Self {
+    pub fn create(frames: Vec<Vec<DynamicImage>>) -> Self {
         GifPlayer {
             gif: frames,
             animation_duration: 0.5,
             animation_start: None,
             animate: false,
             frames: None,
+            multiplexed: None,
+        }
+    }
+    /// Cycle the layers of a time multiplexed solution at display rate
+    pub fn multiplexed(frames: Vec<Vec<DynamicImage>>) -> Self {
+        GifPlayer {
+            multiplexed: Some(0),
+            ..Self::create(frames)
         }
     }
     pub fn start_animation(&mut self) {
@@ -27,6 +38,14 @@ impl GifPlayer {
     }
 
     pub fn animate_gif(&mut self, cache: &mut ImageCache) -> Option<()> {
+        if let Some(next) = self.multiplexed.as_mut() {
+            self.animation_start?;
+            for (entry, layer) in self.gif[*next].iter().enumerate() {
+                cache.cache_panel(entry, layer.clone());
+            }
+            *next = (*next + 1) % self.gif.len();
+            return Some(());
+        }
         let time = self.animation_start?.elapsed().as_secs_f32();
         let duration = self.animation_duration;
         let keyframes = &self.gif;
@@ -35,9 +54,9 @@ impl GifPlayer {
         let total_time = duration * num_segments as f32;
         let i = ((time % total_time) / duration).floor() as usize;
 
-        let next = &keyframes[i + 1];
-        let out = Some((next.0.clone(), next.1.clone(), None));
-        cache.cache_output(false, out);
+        for (entry, layer) in keyframes[i + 1].iter().enumerate() {
+            cache.cache_panel(entry, layer.clone());
+        }
         Some(())
     }
 }
@@ -52,6 +71,21 @@ impl DrawUI for GifPlayer {
             .default_open(false)
             .default_size([150.0, 125.0])
             .show(ctx, |ui| {
+                if self.multiplexed.is_some() {
+                    ui.label(format!("{} time multiplexed frames", self.gif.len()));
+                    let label = if self.animation_start.is_some() {
+                        "Stop cycling"
+                    } else {
+                        "Cycle frames"
+                    };
+                    if ui.button(label).clicked() {
+                        self.animation_start = match self.animation_start {
+                            Some(_) => None,
+                            None => Some(Instant::now()),
+                        };
+                    }
+                    return;
+                }
                 self.animate = ui.button("Play Gif").clicked();
                 if self.animate {
                     self.animation_start = Some(Instant::now());
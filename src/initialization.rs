@@ -0,0 +1,488 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use faer::{
+    stats::prelude::{Rng, SeedableRng, StdRng},
+    unzip, zip, Mat,
+};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    capture::CaptureKind,
+    error::{Error, Result, WithPath},
+    mapping::{self, IndexMapping},
+    scalar::{self, Scalar},
+    utils, Frames, LFSettings, LayerFrames,
+};
+
+/// Where the panels of a solve start. Saved with the panels of the solve, see `save_panels`, so
+/// a run can be reproduced exactly.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Initialization {
+    /// `LFSettings::starting_values`
+    #[default]
+    Constant,
+    /// Uniform noise in [0, 1), the same for every run with the same seed
+    Random { seed: u64 },
+    /// Root of the target averaged over the rays of every pixel, so the layers multiply back to
+    /// the target where the rays agree. The square root for two layers.
+    SquareRoot,
+    /// Fit of `LFSettings::log_least_squares`
+    LogLeastSquares,
+    /// Panels saved by an earlier solve
+    Saved { kind: CaptureKind, name: String },
+    /// Panels the other approach saved under `LFSettings::save_to`, stereo ones for a separable
+    /// solve and the other way around
+    OtherApproach,
+}
+
+impl Initialization {
+    /// Every strategy, with the parameters of `self` where they have some
+    pub fn choices(&self) -> [Initialization; 6] {
+        let seed = match self {
+            Initialization::Random { seed } => *seed,
+            _ => 0,
+        };
+        let saved = match self {
+            Initialization::Saved { .. } => self.clone(),
+            _ => Initialization::Saved {
+                kind: CaptureKind::Stereo,
+                name: String::new(),
+            },
+        };
+        [
+            Initialization::Constant,
+            Initialization::Random { seed },
+            Initialization::SquareRoot,
+            Initialization::LogLeastSquares,
+            saved,
+            Initialization::OtherApproach,
+        ]
+    }
+
+    /// Name in the UI
+    pub fn label(&self) -> &'static str {
+        match self {
+            Initialization::Constant => "Constant",
+            Initialization::Random { .. } => "Seeded noise",
+            Initialization::SquareRoot => "Root of the target",
+            Initialization::LogLeastSquares => "Log domain least squares",
+            Initialization::Saved { .. } => "Saved panels",
+            Initialization::OtherApproach => "Other approach",
+        }
+    }
+
+    /// If the samples of every ray are needed, separable captures are expanded for these
+    pub(crate) fn needs_rays(&self) -> bool {
+        matches!(
+            self,
+            Initialization::SquareRoot | Initialization::LogLeastSquares
+        )
+    }
+
+    /// Saved panels a solve of a `kind` capture starts from, checked against the `(rows, cols)`
+    /// of its layers. `None` unless starting from saved panels.
+    pub(crate) fn load(
+        &self,
+        kind: CaptureKind,
+        settings: &LFSettings,
+        sizes: &[(u32, u32)],
+    ) -> Result<Option<Frames>> {
+        let (kind, name) = match self {
+            Initialization::Saved { kind, name } => (*kind, name.clone()),
+            Initialization::OtherApproach => (kind.other(), settings.save_to.clone()),
+            _ => return Ok(None),
+        };
+        let (_, frames) = load_panels(kind, name.clone())?;
+        let path = panels_path(kind, name);
+        for layers in &frames {
+            if layers.len() != sizes.len() {
+                return Err(Error::PanelsMismatch {
+                    path,
+                    reason: format!("{} layer(s), expected {}", layers.len(), sizes.len()),
+                });
+            }
+            for (image, size) in layers.iter().zip(sizes) {
+                if (image.height(), image.width()) != *size {
+                    return Err(Error::PanelsMismatch {
+                        path,
+                        reason: format!(
+                            "{}x{} panel, expected {}x{}",
+                            image.height(),
+                            image.width(),
+                            size.0,
+                            size.1
+                        ),
+                    });
+                }
+            }
+        }
+        Ok(Some(frames))
+    }
+}
+
+impl fmt::Display for Initialization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Initialization::Constant => write!(f, "constant"),
+            Initialization::Random { seed } => write!(f, "random:{seed}"),
+            Initialization::SquareRoot => write!(f, "square-root"),
+            Initialization::LogLeastSquares => write!(f, "log-least-squares"),
+            Initialization::Saved { kind, name } => write!(f, "saved:{}:{name}", kind.approach()),
+            Initialization::OtherApproach => write!(f, "other-approach"),
+        }
+    }
+}
+
+impl FromStr for Initialization {
+    type Err = String;
+
+    /// As written by `Display`: `constant`, `random:<seed>`, `square-root`,
+    /// `log-least-squares`, `saved:<sep|stereo>:<name>` or `other-approach`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        let (strategy, parameters) = s.split_once(':').unwrap_or((s, ""));
+        match (strategy.to_lowercase().as_str(), parameters) {
+            ("constant", "") => Ok(Initialization::Constant),
+            ("random", seed) => seed
+                .parse()
+                .map(|seed| Initialization::Random { seed })
+                .map_err(|_| format!("Expected random:<seed>, got {s}")),
+            ("square-root", "") => Ok(Initialization::SquareRoot),
+            ("log-least-squares", "") => Ok(Initialization::LogLeastSquares),
+            ("saved", parameters) => match parameters.split_once(':') {
+                Some(("sep", name)) => Ok(Initialization::Saved {
+                    kind: CaptureKind::Separable,
+                    name: name.to_string(),
+                }),
+                Some(("stereo", name)) => Ok(Initialization::Saved {
+                    kind: CaptureKind::Stereo,
+                    name: name.to_string(),
+                }),
+                _ => Err(format!("Expected saved:<sep|stereo>:<name>, got {s}")),
+            },
+            ("other-approach", "") => Ok(Initialization::OtherApproach),
+            _ => Err(format!(
+                "Expected constant, random:<seed>, square-root, log-least-squares, \
+                 saved:<sep|stereo>:<name> or other-approach, got {s}"
+            )),
+        }
+    }
+}
+
+/// Panels of one channel to start from as column vectors, vectorized row by row, indexed by
+/// layer then frame. `sizes` are the `(rows, cols)` of every layer, `saved` the panels from
+/// `Initialization::load` and `rays` the mappings and samples of every ray when
+/// `Initialization::needs_rays`.
+pub(crate) fn initial_panels<T: Scalar>(
+    settings: &LFSettings,
+    (channel, channels): (usize, usize),
+    sizes: &[(u32, u32)],
+    saved: Option<&Frames>,
+    rays: Option<(&[IndexMapping<T>], &Mat<T>)>,
+) -> LayerFrames<T> {
+    let frames = settings.frames.max(1);
+    let pixels = |size: &(u32, u32)| (size.0 * size.1) as usize;
+    let repeat = |panels: Vec<Mat<T>>| -> LayerFrames<T> {
+        panels.into_iter().map(|x| vec![x; frames]).collect()
+    };
+    match (&settings.initialization, saved, rays) {
+        (Initialization::Random { seed }, _, _) => {
+            // Channels are solved in parallel, each one draws from its own stream
+            let mut rng = StdRng::seed_from_u64(seed.wrapping_add(channel as u64));
+            sizes
+                .iter()
+                .map(|size| {
+                    (0..frames)
+                        .map(|_| {
+                            Mat::from_fn(pixels(size), 1, |_, _| {
+                                T::from_f32(rng.gen_range(0f32..1.0f32))
+                            })
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+        (Initialization::SquareRoot, _, Some((layers, l_vec))) => {
+            let power = 1.0 / layers.len() as f64;
+            let root = zip!(l_vec)
+                .map(|unzip!(l)| T::from_f64((*l).clamp(T::ZERO, T::ONE).to_f64().powf(power)));
+            let ones = Mat::from_fn(l_vec.nrows(), 1, |_, _| T::ONE);
+            repeat(
+                layers
+                    .iter()
+                    .map(|layer| {
+                        // Pixels no ray sees are left transparent
+                        zip!(&layer.scatter(&root), &layer.scatter(&ones)).map(
+                            |unzip!(sum, count)| {
+                                if *count > T::ZERO {
+                                    *sum / *count
+                                } else {
+                                    T::ONE
+                                }
+                            },
+                        )
+                    })
+                    .collect(),
+            )
+        }
+        (Initialization::LogLeastSquares, _, Some((layers, l_vec))) => {
+            repeat(settings.log_least_squares.solve(layers, l_vec))
+        }
+        (Initialization::Saved { .. } | Initialization::OtherApproach, Some(saved), _) => sizes
+            .iter()
+            .enumerate()
+            .map(|(layer, size)| {
+                (0..frames)
+                    .map(|frame| {
+                        let image = &saved[frame % saved.len()][layer];
+                        let panel = if channels == 3 {
+                            utils::image_to_channels(image)[channel].clone()
+                        } else {
+                            utils::image_to_matrix(image)
+                        };
+                        debug_assert_eq!(panel.nrows() * panel.ncols(), pixels(size));
+                        mapping::vectorize(&scalar::cast(&panel))
+                    })
+                    .collect()
+            })
+            .collect(),
+        _ => sizes
+            .iter()
+            .enumerate()
+            .map(|(layer, size)| {
+                let value = T::from_f32(crate::starting_value(settings, layer));
+                vec![Mat::from_fn(pixels(size), 1, |_, _| value); frames]
+            })
+            .collect(),
+    }
+}
+
+/// Directory of the panels `name` of a solve of a `kind` capture
+pub fn panels_path(kind: CaptureKind, name: String) -> PathBuf {
+    PathBuf::from(format!("./saves/panels/{}/{name}", kind.approach()))
+}
+
+fn panel_file(frame: usize, layer: usize) -> String {
+    format!("frame_{frame}_layer_{layer}.png")
+}
+
+/// Writes every frame of every layer of a solution, with the settings that solved it
+pub fn save_panels(
+    kind: CaptureKind,
+    name: String,
+    frames: &Frames,
+    settings: &LFSettings,
+) -> Result<()> {
+    let directory = panels_path(kind, name);
+    std::fs::create_dir_all(&directory).with_path(&directory)?;
+    for (frame, layers) in frames.iter().enumerate() {
+        for (layer, image) in layers.iter().enumerate() {
+            let path = directory.join(panel_file(frame, layer));
+            image.save(&path).with_path(&path)?;
+        }
+    }
+    let path = directory.join("settings.ron");
+    let content =
+        ron::ser::to_string_pretty(settings, ron::ser::PrettyConfig::default()).with_path(&path)?;
+    std::fs::write(&path, content).with_path(&path)
+}
+
+/// Panels written by `save_panels`, and the settings of the solve that wrote them
+pub fn load_panels(kind: CaptureKind, name: String) -> Result<(LFSettings, Frames)> {
+    let directory = panels_path(kind, name);
+    let path = directory.join("settings.ron");
+    let content = std::fs::read_to_string(&path).with_path(&path)?;
+    let settings: LFSettings = ron::from_str(&content).with_path(&path)?;
+
+    let mut frames = Vec::new();
+    for frame in 0..settings.frames.max(1) {
+        let mut layers: Vec<DynamicImage> = Vec::new();
+        loop {
+            let path = directory.join(panel_file(frame, layers.len()));
+            if !layers.is_empty() && !path.exists() {
+                break;
+            }
+            layers.push(image::open(&path).with_path(&path)?);
+        }
+        frames.push(layers);
+    }
+    Ok((settings, frames))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{synthetic::Synthetic, Lff};
+
+    fn solve(problem: &Synthetic, settings: &LFSettings) -> Frames {
+        problem
+            .capture
+            .factorize(settings, &())
+            .unwrap()
+            .unwrap()
+            .frames
+    }
+
+    #[test]
+    fn strategies_round_trip_through_strings() {
+        let saved = Initialization::Saved {
+            kind: CaptureKind::Separable,
+            name: "run:1".to_string(),
+        };
+        for initialization in saved.choices() {
+            assert_eq!(initialization.to_string().parse(), Ok(initialization));
+        }
+        assert!("random".parse::<Initialization>().is_err());
+        assert!("saved:both:x".parse::<Initialization>().is_err());
+    }
+
+    #[test]
+    fn seeded_noise_is_reproducible() {
+        let problem = Synthetic::<f32>::new(1, &[4, 3], 3, 20, 0.2);
+        let settings = |seed| LFSettings {
+            iter_count: 3,
+            debug_prints: false,
+            initialization: Initialization::Random { seed },
+            ..Default::default()
+        };
+        assert_eq!(solve(&problem, &settings(4)), solve(&problem, &settings(4)));
+        assert_ne!(solve(&problem, &settings(4)), solve(&problem, &settings(5)));
+    }
+
+    #[test]
+    fn square_root_of_two_layers_matches_target() {
+        let problem = Synthetic::<f64>::new(2, &[4, 4], 3, 20, 0.2);
+        let capture = &problem.capture;
+        let settings = LFSettings {
+            initialization: Initialization::SquareRoot,
+            ..Default::default()
+        };
+        let panels = initial_panels(
+            &settings,
+            (0, 1),
+            &capture.layer_sizes,
+            None,
+            Some((&capture.layers, &capture.l_vec)),
+        );
+        // A ray alone on both of its pixels is reproduced exactly
+        let alone = |layer: &IndexMapping<f64>, row| {
+            let pixel = layer.hit(row);
+            (0..layer.nrows())
+                .filter(|other| layer.hit(*other) == pixel)
+                .count()
+                == 1
+        };
+        for row in 0..capture.l_vec.nrows() {
+            if capture.layers.iter().all(|layer| alone(layer, row)) {
+                let product: f64 = capture
+                    .layers
+                    .iter()
+                    .zip(&panels)
+                    .map(|(layer, frames)| frames[0][(layer.hit(row).unwrap(), 0)])
+                    .product();
+                assert!((product - capture.l_vec[(row, 0)]).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn warm_start_from_saved_panels() {
+        let problem = Synthetic::<f32>::new(3, &[4, 3], 3, 20, 0.2);
+        let name = "warm_start_from_saved_panels".to_string();
+        let settings = LFSettings {
+            iter_count: 20,
+            save_error: true,
+            debug_prints: false,
+            save_to: name.clone(),
+            ..Default::default()
+        };
+        let first = problem.capture.factorize(&settings, &()).unwrap().unwrap();
+        let frames = first.frames;
+        save_panels(CaptureKind::Stereo, name.clone(), &frames, &settings).unwrap();
+        let (loaded_settings, loaded) = load_panels(CaptureKind::Stereo, name.clone()).unwrap();
+        assert_eq!(loaded, frames);
+        assert_eq!(loaded_settings.initialization, settings.initialization);
+
+        // The 8 bit panels are where the first solve ended, up to rounding
+        let resumed = LFSettings {
+            iter_count: 1,
+            initialization: Initialization::Saved {
+                kind: CaptureKind::Stereo,
+                name: name.clone(),
+            },
+            ..settings.clone()
+        };
+        let error = problem
+            .capture
+            .factorize(&resumed, &())
+            .unwrap()
+            .unwrap()
+            .residuals;
+        let first = first.residuals;
+        assert!(error[0] < first[0]);
+        assert!((error[0] - first.last().unwrap()).abs() < 0.05);
+
+        // Saved by the other approach, so a separable solve would find them
+        assert_eq!(
+            Initialization::OtherApproach
+                .load(
+                    CaptureKind::Separable,
+                    &settings,
+                    &problem.capture.layer_sizes
+                )
+                .unwrap(),
+            Some(frames)
+        );
+        let Err(err) = Initialization::OtherApproach.load(
+            CaptureKind::Separable,
+            &settings,
+            &[(4, 4), (3, 4)],
+        ) else {
+            panic!("loaded panels of the wrong size");
+        };
+        assert!(matches!(err, Error::PanelsMismatch { .. }));
+
+        std::fs::remove_dir_all(panels_path(CaptureKind::Stereo, name)).ok();
+    }
+
+    #[test]
+    fn solves_fail_without_their_warm_start() {
+        let problem = Synthetic::<f32>::new(3, &[4, 3], 3, 20, 0.2);
+        let settings = LFSettings {
+            debug_prints: false,
+            initialization: Initialization::Saved {
+                kind: CaptureKind::Stereo,
+                name: "solves_fail_without_their_warm_start".to_string(),
+            },
+            ..Default::default()
+        };
+        let Err(err) = problem.capture.factorize(&settings, &()) else {
+            panic!("solved without the saved panels");
+        };
+        assert!(matches!(err, Error::Io { .. }));
+    }
+
+    #[test]
+    fn stacked_solve_rejects_other_strategies() {
+        let capture = crate::synthetic::random_separable::<f32>(4, &[(4, 3), (3, 3)], 2, (6, 5));
+        let stacked = capture.stack();
+        for initialization in Initialization::Constant.choices() {
+            if matches!(
+                initialization,
+                Initialization::Constant | Initialization::Random { .. }
+            ) {
+                continue;
+            }
+            let settings = LFSettings {
+                initialization,
+                debug_prints: false,
+                ..Default::default()
+            };
+            let Err(err) = capture.old_factorize(&settings, &stacked) else {
+                panic!("stacked solve started from {}", settings.initialization);
+            };
+            assert!(matches!(err, Error::UnsupportedInitialization { .. }));
+        }
+    }
+}
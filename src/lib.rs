@@ -1,147 +1,158 @@
 pub mod app;
 mod camera;
+pub mod capture;
+pub mod checkpoint;
 mod compute_pass;
+pub mod cpu_sampler;
 mod egui_tools;
+pub mod error;
 mod file_picker;
 mod gif;
 mod headless;
+pub mod initialization;
 mod light_factor;
+pub mod log_domain;
+pub mod mapping;
+pub mod observer;
+pub mod parallelism;
+pub mod progress;
+mod projection_cache;
+pub mod quality;
 mod raytracer;
+pub mod regularizer;
+pub mod report;
 mod save;
+pub mod scalar;
 mod scene;
 mod shape;
+pub mod solver;
 mod stereoscope;
+#[cfg(test)]
+mod synthetic;
 mod texture;
 pub mod vertex;
 
 pub mod utils;
-use std::{num::NonZero, path::PathBuf};
+use std::path::PathBuf;
 
 // Library File that exposes and will be used to import as well
 //
-use faer::sparse::{SparseColMat, SparseColMatRef, Triplet};
+use faer::sparse::SparseColMat;
+use mapping::IndexMapping;
+use parallelism::Parallelism;
+use progress::{ChannelReporter, SolveObserver};
+use projection_cache::ProjectionCache;
 use utils::DrawUI;
 
 use std::{
+    borrow::Borrow,
     collections::VecDeque,
+    ops::Range,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
+use capture::{CaptureHeader, CaptureKind, CaptureMetadata};
+use checkpoint::{Checkpoint, Checkpointer};
 use faer::{
-    stats::prelude::{thread_rng, Rng},
+    stats::prelude::{Rng, SeedableRng, StdRng},
     unzip, zip, Mat,
 };
 use image::DynamicImage;
+use initialization::Initialization;
+use log_domain::LogLeastSquares;
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator,
+    IntoParallelRefMutIterator, ParallelIterator,
+};
+use regularizer::Regularization;
+use report::{Convergence, SolveReport, StopCriteria, StopReason};
+use scalar::Scalar;
 use serde::{Deserialize, Serialize};
-
-#[derive(Deserialize, Serialize)]
-enum SparsePass {
-    List(Vec<SparseAsList>),
-}
-
-#[derive(Deserialize, Serialize)]
-pub struct SparseAsList {
-    shape: (usize, usize),
-    triplet_list: Vec<(usize, usize, f32)>,
-}
-
-impl Into<SparseColMat<u32, f32>> for &SparseAsList {
-    fn into(self) -> SparseColMat<u32, f32> {
-        let entries: Vec<Triplet<u32, u32, f32>> = self
-            .triplet_list
-            .iter()
-            .map(|(row, col, val)| Triplet::new(*row as u32, *col as u32, *val))
-            .collect();
-        SparseColMat::<u32, f32>::try_new_from_triplets(self.shape.0, self.shape.1, &entries)
-            .unwrap()
-    }
-}
-
-impl From<SparseColMatRef<'_, u32, f32>> for SparseAsList {
-    fn from(value: SparseColMatRef<u32, f32>) -> Self {
-        let shape = value.shape();
-        let triplet_list = value
-            .triplet_iter()
-            .map(|triplet| (triplet.row, triplet.col, *triplet.val))
-            .collect();
-        SparseAsList {
-            triplet_list,
-            shape,
-        }
-    }
+use solver::{SolverKind, UpdateTerms};
+
+/// Bytes in a MiB, the unit of `LFSettings::cache_budget`
+const MIB: f32 = 1024.0 * 1024.0;
+
+/// File of the matrix capture `name` of an approach (`sep` or `stereo`)
+fn capture_path(approach: &str, name: String) -> PathBuf {
+    let name = if !name.ends_with(".ro") {
+        format!("{name}.ro")
+    } else {
+        name
+    };
+    PathBuf::from(format!("./saves/matrix_capture/{approach}/{name}"))
 }
 
-#[derive(Clone)]
-pub struct MappingMatrix {
-    pub matrix: Vec<SparseColMat<u32, f32>>,
-}
-impl MappingMatrix {
-    pub fn stack(&self) -> SparseColMat<u32, f32> {
-        let n_views = self.matrix.len();
-        let (rays_per_view, columns) = self.matrix[0].shape();
-        let mut triplet_list: Vec<Triplet<u32, u32, f32>> = Vec::new();
-        for (index, matrix) in self.matrix.iter().enumerate() {
-            let list: SparseAsList = matrix.as_ref().into();
-            let triplet = list.triplet_list.iter().map(|(old_row, col, val)| {
-                let row = old_row + index * rays_per_view;
-
-                Triplet::new(row as u32, *col as u32, *val)
-            });
-            triplet_list.extend(triplet);
-        }
-        SparseColMat::try_new_from_triplets(rays_per_view * n_views, columns, &triplet_list)
-            .unwrap()
-    }
+/// Mapping of every view point
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MappingMatrix<T: Scalar = f32> {
+    pub matrix: Vec<IndexMapping<T>>,
 }
-
-impl Serialize for MappingMatrix {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        SparsePass::List(self.matrix.iter().map(|x| x.as_ref().into()).collect())
-            .serialize(serializer)
+impl<T: Scalar> MappingMatrix<T> {
+    pub fn new(matrix: Vec<IndexMapping<T>>) -> Self {
+        MappingMatrix { matrix }
     }
-}
-impl<'de> Deserialize<'de> for MappingMatrix {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        match SparsePass::deserialize(deserializer)? {
-            SparsePass::List(x) => Ok(MappingMatrix::new(x.iter().map(|x| x.into()).collect())),
-        }
+    /// Every view point in a single sparse matrix, for the stacked approach
+    pub fn stack(&self) -> SparseColMat<u32, T> {
+        IndexMapping::stack(&self.matrix).to_sparse()
     }
-}
-impl MappingMatrix {
-    pub fn new(matrix: Vec<SparseColMat<u32, f32>>) -> Self {
-        MappingMatrix { matrix }
+    pub fn cast<U: Scalar>(&self) -> MappingMatrix<U> {
+        MappingMatrix::new(self.matrix.iter().map(IndexMapping::cast).collect())
     }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
-pub struct CompleteMapping {
-    pub x: MappingMatrix,
+pub struct CompleteMapping<T: Scalar = f32> {
+    pub x: MappingMatrix<T>,
     pub size: (u32, u32),
-    pub y: MappingMatrix,
+    pub y: MappingMatrix<T>,
 }
-impl CompleteMapping {
-    pub fn new(x: MappingMatrix, y: MappingMatrix, size: (u32, u32)) -> Self {
+impl<T: Scalar> CompleteMapping<T> {
+    pub fn new(x: MappingMatrix<T>, y: MappingMatrix<T>, size: (u32, u32)) -> Self {
         CompleteMapping { x, y, size }
     }
+    pub fn cast<U: Scalar>(&self) -> CompleteMapping<U> {
+        CompleteMapping::new(self.x.cast(), self.y.cast(), self.size)
+    }
     pub fn debug_print(&self, name: String) {
         println!("{}_X size: {:?}", name, self.x.matrix[0].shape());
         println!("{}_Y size: {:?}", name, self.y.matrix[0].shape());
     }
+    /// `M_y c M_x^T` for one view point
+    pub fn project(&self, view_point: usize, c: &Mat<T>) -> Mat<T> {
+        mapping::project(&self.y.matrix[view_point], c, &self.x.matrix[view_point])
+    }
+    /// Every view point in the row form of the stereo approach, see `IndexMapping::kronecker`.
+    /// Views follow each other like the observers of a stereo capture.
+    pub fn expand(&self) -> IndexMapping<T> {
+        let views: Vec<IndexMapping<T>> = self
+            .x
+            .matrix
+            .iter()
+            .zip(&self.y.matrix)
+            .map(|(m_x, m_y)| IndexMapping::kronecker(m_y, m_x))
+            .collect();
+        IndexMapping::stack(&views)
+    }
+    /// Adds `M_y^T rays M_x` of one view point to `out`
+    pub fn back_project_into(&self, view_point: usize, rays: &Mat<T>, out: &mut Mat<T>) {
+        mapping::back_project_into(
+            &self.y.matrix[view_point],
+            rays,
+            &self.x.matrix[view_point],
+            out,
+        );
+    }
 }
 
 /// Struct to hold the matrices that we will build.
 /// Observations will be
 #[derive(Clone, Serialize, Deserialize)]
-pub struct LFMatrices {
-    pub a: CompleteMapping,
-    pub b: CompleteMapping,
-    pub t: CompleteMapping,
+pub struct LFMatrices<T: Scalar = f32> {
+    /// Mapping of every attenuation layer, closest to the observers first
+    pub layers: Vec<CompleteMapping<T>>,
+    pub t: CompleteMapping<T>,
     #[serde(skip)]
     pub c_t: DynamicImage,
     pub target_size: (u32, u32),
@@ -157,55 +168,78 @@ pub struct OldLFMatrices {
     m_t_y: SparseColMat<u32, f32>,
 }
 
-impl LFMatrices {
+impl<T: Scalar> LFMatrices<T> {
     pub fn new(
-        a: CompleteMapping,
-        b: CompleteMapping,
-        t: CompleteMapping,
+        layers: Vec<CompleteMapping<T>>,
+        t: CompleteMapping<T>,
         c_t: DynamicImage,
         target_size: (u32, u32),
         number_of_view_points: u32,
     ) -> Self {
+        assert!(layers.len() >= 2, "A light field needs at least two layers");
         LFMatrices {
-            a,
-            b,
+            layers,
             t,
             c_t,
             target_size,
             number_of_view_points,
         }
     }
-    pub fn save(&self, path: String) {
-        let path = {
-            if !path.ends_with(".ro") {
-                format!("{path}.ro")
-            } else {
-                path
-            }
-        };
-        let path_core = PathBuf::from(format!("./saves/matrix_capture/sep/{path}"));
-        let mut file = std::fs::File::create(&path_core).unwrap();
-        let config = bincode::config::standard();
-        bincode::serde::encode_into_std_write(self, &mut file, config).unwrap();
-    }
-    pub fn load(path: String) -> Self {
-        let path = {
-            if !path.ends_with(".ro") {
-                format!("{path}.ro")
-            } else {
-                path
-            }
-        };
-        let path_core = PathBuf::from(format!("./saves/matrix_capture/sep/{path}"));
-        let mut file = std::fs::File::open(path_core).unwrap();
-        let config = bincode::config::standard();
-        bincode::serde::decode_from_std_read(&mut file, config).unwrap()
+    /// The same capture in another precision, `f64` for reference solves
+    pub fn cast<U: Scalar>(&self) -> LFMatrices<U> {
+        LFMatrices {
+            layers: self.layers.iter().map(CompleteMapping::cast).collect(),
+            t: self.t.cast(),
+            c_t: self.c_t.clone(),
+            target_size: self.target_size,
+            number_of_view_points: self.number_of_view_points,
+        }
+    }
+    /// The same light field as a stereo capture, every ray as a row of its own. The target is
+    /// sampled through `t`, so solving either capture solves the same problem.
+    pub fn to_stereo(&self) -> StereoMatrix<T> {
+        let target = self.t.expand();
+        let project = |c_t: &Mat<f32>| target.gather(&mapping::vectorize(&scalar::cast(c_t)));
+        let l_vec = project(&utils::image_to_matrix(&self.c_t));
+        let l_rgb = utils::image_to_channels(&self.c_t).map(|channel| project(&channel));
+        StereoMatrix {
+            l_rgb: Mat::from_fn(l_vec.nrows(), 3, |ray, channel| l_rgb[channel][(ray, 0)]),
+            l_vec,
+            layers: self.layers.iter().map(CompleteMapping::expand).collect(),
+            layer_sizes: self.layers.iter().map(|layer| layer.size).collect(),
+            target_size: self.target_size,
+            number_of_view_points: self.number_of_view_points,
+        }
     }
+    /// Continues the solve of the checkpoint `name`, see `checkpoint::resume`
+    pub fn resume(
+        &self,
+        name: String,
+        settings: &LFSettings,
+        observer: &dyn SolveObserver,
+    ) -> error::Result<Option<SolveReport>> {
+        checkpoint::resume(self, name, settings, observer)
+    }
+}
+
+impl LFMatrices {
+    /// Writes the capture with a header describing the scene it was sampled from
+    pub fn save(&self, path: String, metadata: &CaptureMetadata) -> error::Result<()> {
+        capture::save(self, &capture_path("sep", path), metadata)
+    }
+    pub fn load(path: String) -> error::Result<Self> {
+        Ok(Self::load_with_header(path)?.1)
+    }
+    /// Loads a capture of any format version, migrating older layouts
+    pub fn load_with_header(path: String) -> error::Result<(CaptureHeader, Self)> {
+        capture::load(&capture_path("sep", path))
+    }
+    /// The stacked approach only covers the first two layers
     pub fn stack(&self) -> OldLFMatrices {
-        let m_a_x = self.a.x.stack();
-        let m_a_y = self.a.y.stack();
-        let m_b_x = self.b.x.stack();
-        let m_b_y = self.b.y.stack();
+        let m_a_x = self.layers[0].x.stack();
+        let m_a_y = self.layers[0].y.stack();
+        let m_b_x = self.layers[1].x.stack();
+        let m_b_y = self.layers[1].y.stack();
         let m_t_x = self.t.x.stack();
         let m_t_y = self.t.y.stack();
         OldLFMatrices {
@@ -217,29 +251,28 @@ impl LFMatrices {
             m_t_y,
         }
     }
+    /// Solve of the stacked matrices, only from constant or seeded random panels
     pub fn old_factorize(
         &self,
         settings: &LFSettings,
         matrices: &OldLFMatrices,
-    ) -> Option<(DynamicImage, DynamicImage, Option<L2Norm>)> {
-        faer::set_global_parallelism(faer::Par::Rayon(NonZero::new(10).unwrap()));
+    ) -> error::Result<Option<SolveReport>> {
+        match settings.initialization {
+            Initialization::Constant | Initialization::Random { .. } => {}
+            _ => {
+                return Err(error::Error::UnsupportedInitialization {
+                    solver: "stacked",
+                    initialization: settings.initialization.clone(),
+                })
+            }
+        }
+        let started = Instant::now();
         let target_size = self.target_size;
         let number_of_view_points = self.number_of_view_points;
-        let c_t = &self.c_t;
         if settings.debug_prints {
-            println!(
-                "Global Parallelism is: {:?}",
-                faer::get_global_parallelism()
-            );
+            println!("Solving on {} thread(s)", settings.parallelism.threads());
         }
 
-        let m_a_x = matrices.m_a_x.as_ref();
-        let m_a_y = matrices.m_a_y.as_ref();
-        let m_b_x = matrices.m_b_x.as_ref();
-        let m_b_y = matrices.m_b_y.as_ref();
-        let m_t_x = matrices.m_t_x.as_ref();
-        let m_t_y = matrices.m_t_y.as_ref();
-
         let rays_cast = (
             target_size.1 * number_of_view_points,
             target_size.0 * number_of_view_points,
@@ -248,43 +281,105 @@ impl LFMatrices {
             println!("Rays Cast is: {rays_cast:?}");
         }
 
-        let c_t = utils::image_to_matrix(c_t);
+        let channels = target_channels(&self.c_t, settings.colour);
         if settings.debug_prints {
-            println!("A_y shape: {:?}", m_a_y.shape());
-            println!("A_x shape: {:?}", m_a_x.shape());
-            println!("b_y shape: {:?}", m_b_y.shape());
-            println!("b_x shape: {:?}", m_b_x.shape());
-            println!("t_y shape: {:?}", m_t_y.shape());
-            println!("t_x shape: {:?}", m_t_x.shape());
-            println!("C_T shape: {:?}", c_t.shape());
+            println!("A_y shape: {:?}", matrices.m_a_y.shape());
+            println!("A_x shape: {:?}", matrices.m_a_x.shape());
+            println!("b_y shape: {:?}", matrices.m_b_y.shape());
+            println!("b_x shape: {:?}", matrices.m_b_x.shape());
+            println!("t_y shape: {:?}", matrices.m_t_y.shape());
+            println!("t_x shape: {:?}", matrices.m_t_x.shape());
+            println!("C_T shape: {:?}", channels[0].shape());
         }
 
-        utils::verify_matrix(&c_t);
-        utils::matrix_to_image(&c_t)
+        for c_t in channels.iter() {
+            utils::verify_matrix(c_t);
+        }
+        channels_into_image(channels.clone())
             .save_with_format(
                 "./resources/panel_compute/intermediate/C_T.png",
                 image::ImageFormat::Png,
             )
             .unwrap();
 
+        let solutions =
+            solve_channels(settings, &channels, &(), None, started, |c_t, _reporter| {
+                self.old_solve_channel(settings, matrices, c_t, rays_cast)
+            });
+
+        // The stacked approach solves a single frame of two layers
+        let mut report = split_solutions(solutions, None, started);
+        let Some([panels_a, panels_b]) = report.panels.first_mut().map(Vec::as_mut_slice) else {
+            return Ok(None);
+        };
+
+        if settings.filter {
+            for c_a in panels_a.iter_mut() {
+                utils::filter_zeroes(c_a, &self.layers[0]);
+            }
+            for c_b in panels_b.iter_mut() {
+                utils::filter_zeroes(c_b, &self.layers[1]);
+            }
+        }
+        for panel in panels_a.iter().chain(panels_b.iter()) {
+            utils::verify_matrix(panel);
+        }
+
+        let image_a = channels_into_image(panels_a.clone());
+        image_a
+            .save_with_format(
+                "./resources/panel_compute/panel_1.png",
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+
+        let image_b = channels_into_image(panels_b.clone());
+
+        image_b
+            .save_with_format(
+                "./resources/panel_compute/panel_2.png",
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+
+        report.frames = vec![vec![image_a, image_b]];
+        if settings.debug_prints {
+            println!("Stacked solve: {report}");
+        }
+        Ok(Some(report))
+    }
+
+    /// Stacked multiplicative update for a single channel of the target
+    fn old_solve_channel(
+        &self,
+        settings: &LFSettings,
+        matrices: &OldLFMatrices,
+        c_t: &Mat<f32>,
+        rays_cast: (u32, u32),
+    ) -> ChannelSolution {
+        let m_a_x = matrices.m_a_x.as_ref();
+        let m_a_y = matrices.m_a_y.as_ref();
+        let m_b_x = matrices.m_b_x.as_ref();
+        let m_b_y = matrices.m_b_y.as_ref();
+        let m_t_x = matrices.m_t_x.as_ref();
+        let m_t_y = matrices.m_t_y.as_ref();
+
         let h_a = m_a_y.shape().1;
         let w_a = m_a_x.shape().1;
-        let mut c_a = Mat::from_fn(h_a, w_a, |_x, _y| {
-            if settings.rng {
-                thread_rng().gen_range(0f32..1.0f32)
-            } else {
-                settings.starting_values.0
-            }
+        let mut rng = match settings.initialization {
+            Initialization::Random { seed } => Some(StdRng::seed_from_u64(seed)),
+            _ => None,
+        };
+        let mut c_a = Mat::from_fn(h_a, w_a, |_x, _y| match rng.as_mut() {
+            Some(rng) => rng.gen_range(0f32..1.0f32),
+            None => settings.starting_values.0,
         });
 
         let h_b = m_b_y.shape().1;
         let w_b = m_b_x.shape().1;
-        let mut c_b = Mat::from_fn(h_b, w_b, |_x, _y| {
-            if settings.rng {
-                thread_rng().gen_range(0f32..1.0f32)
-            } else {
-                settings.starting_values.1
-            }
+        let mut c_b = Mat::from_fn(h_b, w_b, |_x, _y| match rng.as_mut() {
+            Some(rng) => rng.gen_range(0f32..1.0f32),
+            None => settings.starting_values.1,
         });
 
         let mut upper = Mat::<f32>::zeros(rays_cast.0 as usize, rays_cast.1 as usize);
@@ -366,150 +461,125 @@ impl LFMatrices {
             time_taken_total.push(time_taken);
         }
 
-        let total_time: Duration = time_taken_total.iter().sum();
-        let average_time = total_time / settings.iter_count as u32;
-        if settings.debug_prints {
-            println!("Average time per iteration: {average_time:?}");
+        ChannelSolution {
+            layers: vec![vec![c_a], vec![c_b]],
+            error,
+            times: time_taken_total,
+            iterations: settings.iter_count,
+            stop: StopReason::Iterations,
         }
-
-        if settings.filter {
-            utils::filter_zeroes(&mut c_a, &self.a);
-            utils::filter_zeroes(&mut c_b, &self.b);
-        }
-        utils::verify_matrix(&c_a);
-        utils::verify_matrix(&c_b);
-
-        let image_a = utils::matrix_to_image(&c_a);
-        image_a
-            .save_with_format(
-                "./resources/panel_compute/panel_1.png",
-                image::ImageFormat::Png,
-            )
-            .unwrap();
-
-        let image_b = utils::matrix_to_image(&c_b);
-
-        image_b
-            .save_with_format(
-                "./resources/panel_compute/panel_2.png",
-                image::ImageFormat::Png,
-            )
-            .unwrap();
-
-        if settings.debug_prints {
-            println!("Errors is: {error:?}");
-        }
-        let error = {
-            if settings.save_error {
-                Some(error.into())
-            } else {
-                None
-            }
-        };
-
-        Some((image_a, image_b, error))
-    }
-}
-
-#[derive(Clone)]
-pub struct StereoSparseWrapper {
-    pub matrix: SparseColMat<u32, f32>,
-}
-
-impl From<SparseColMat<u32, f32>> for StereoSparseWrapper {
-    fn from(value: SparseColMat<u32, f32>) -> Self {
-        StereoSparseWrapper { matrix: value }
-    }
-}
-
-impl Serialize for StereoSparseWrapper {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        SparseAsList::from(self.matrix.as_ref()).serialize(serializer)
-    }
-}
-impl<'de> Deserialize<'de> for StereoSparseWrapper {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let matrix = &SparseAsList::deserialize(deserializer)?;
-        Ok(StereoSparseWrapper {
-            matrix: matrix.into(),
-        })
     }
 }
 
 #[derive(Clone, Deserialize, Serialize)]
-pub struct StereoMatrix {
-    pub l_vec: Mat<f32>,
-    pub a_matrix: StereoSparseWrapper,
-    pub b_matrix: StereoSparseWrapper,
-
-    pub panel_a_size: (u32, u32),
-    pub panel_b_size: (u32, u32),
+pub struct StereoMatrix<T: Scalar = f32> {
+    pub l_vec: Mat<T>,
+    /// Red, green and blue sample of every ray, one column per channel
+    pub l_rgb: Mat<T>,
+    /// Ray to pixel mapping of every attenuation layer, closest to the observers first
+    pub layers: Vec<IndexMapping<T>>,
+    /// (rows, columns) of every layer
+    pub layer_sizes: Vec<(u32, u32)>,
 
     pub target_size: (u32, u32),
     pub number_of_view_points: u32,
 }
 impl StereoMatrix {
-    pub fn save(&self, path: String) {
-        let path = {
-            if !path.ends_with(".ro") {
-                format!("{path}.ro")
-            } else {
-                path
-            }
-        };
-
-        let path_core = PathBuf::from(format!("./saves/matrix_capture/stereo/{path}"));
-        let mut file = std::fs::File::create(&path_core).unwrap();
-        let config = bincode::config::standard();
-        bincode::serde::encode_into_std_write(self, &mut file, config).unwrap();
+    /// Writes the capture with a header describing the scene it was sampled from
+    pub fn save(&self, path: String, metadata: &CaptureMetadata) -> error::Result<()> {
+        capture::save(self, &capture_path("stereo", path), metadata)
     }
-    pub fn load(path: String) -> Self {
-        let path = {
-            if !path.ends_with(".ro") {
-                format!("{path}.ro")
-            } else {
-                path
-            }
-        };
+    pub fn load(path: String) -> error::Result<Self> {
+        Ok(Self::load_with_header(path)?.1)
+    }
+    /// Loads a capture of any format version, migrating older layouts
+    pub fn load_with_header(path: String) -> error::Result<(CaptureHeader, Self)> {
+        capture::load(&capture_path("stereo", path))
+    }
+}
 
-        let path_core = PathBuf::from(format!("./saves/matrix_capture/stereo/{path}"));
-        let mut file = std::fs::File::open(path_core).unwrap();
-        let config = bincode::config::standard();
-        bincode::serde::decode_from_std_read(&mut file, config).unwrap()
+impl<T: Scalar> StereoMatrix<T> {
+    /// The same capture in another precision, `f64` for reference solves
+    pub fn cast<U: Scalar>(&self) -> StereoMatrix<U> {
+        StereoMatrix {
+            l_vec: scalar::cast(&self.l_vec),
+            l_rgb: scalar::cast(&self.l_rgb),
+            layers: self.layers.iter().map(IndexMapping::cast).collect(),
+            layer_sizes: self.layer_sizes.clone(),
+            target_size: self.target_size,
+            number_of_view_points: self.number_of_view_points,
+        }
+    }
+    /// Continues the solve of the checkpoint `name`, see `checkpoint::resume`
+    pub fn resume(
+        &self,
+        name: String,
+        settings: &LFSettings,
+        observer: &dyn SolveObserver,
+    ) -> error::Result<Option<SolveReport>> {
+        checkpoint::resume(self, name, settings, observer)
     }
 }
 
+/// Everything but the UI flags decides the result of a solve, checkpoints record what a resumed
+/// solve depends on, see `checkpoint::SolveSettings`
+#[derive(Clone, Serialize, Deserialize)]
 pub struct LFSettings {
     pub iter_count: usize,
     pub show_steps: bool,
+    /// Initial value of the closest layer, then of every other layer
     pub starting_values: (f32, f32),
-    pub rng: bool,
+    /// Where the panels start, `starting_values` unless another strategy is picked
+    pub initialization: Initialization,
     pub solve_next_redraw_flag: bool,
-    pub early_stop: bool,
+    /// When the solve stops before `iter_count`
+    pub stopping: StopCriteria,
     pub filter: bool,
     pub save_error: bool,
     pub debug_prints: bool,
     pub save_to: String,
+    /// Solve red, green and blue separately instead of luma
+    pub colour: bool,
+    /// Solve the colour channels at the same time
+    pub parallel_channels: bool,
+    /// Update rule for the panels
+    pub solver: SolverKind,
+    /// Panel pairs shown in quick succession, the eye averages them
+    pub frames: usize,
+    /// Priors on the panels, traded against fidelity at the sampled viewpoints
+    pub regularization: Regularization,
+    /// Convex fit of the panels for `Initialization::LogLeastSquares`
+    pub log_least_squares: LogLeastSquares,
+    /// Threads of the pool every solve runs in
+    pub parallelism: Parallelism,
+    /// Write a checkpoint named `save_to` every this many iterations, 0 never writes one
+    pub checkpoint_every: usize,
+    /// MiB the separable solver may keep per view projections in, 0 recomputes them for every
+    /// update
+    pub cache_budget: f32,
 }
 impl Default for LFSettings {
     fn default() -> Self {
         LFSettings {
-            rng: false,
+            initialization: Initialization::default(),
             iter_count: 5,
             show_steps: false,
             starting_values: (0.5, 0.5),
             solve_next_redraw_flag: false,
-            early_stop: false,
+            stopping: StopCriteria::default(),
             filter: true,
             save_error: false,
             debug_prints: true,
             save_to: "Default".to_string(),
+            colour: false,
+            parallel_channels: true,
+            solver: SolverKind::default(),
+            frames: 1,
+            regularization: Regularization::default(),
+            log_least_squares: LogLeastSquares::default(),
+            parallelism: Parallelism::default(),
+            checkpoint_every: 0,
+            cache_budget: 1024.0,
         }
     }
 }
@@ -520,17 +590,135 @@ impl DrawUI for LFSettings {
         let _ = ctx;
         if let Some(ui) = ui {
             ui.label("Iteration count");
-            ui.add(egui::Slider::new(&mut self.iter_count, 1..=1000));
+            ui.add(egui::Slider::new(&mut self.iter_count, 0..=1000));
             ui.checkbox(&mut self.show_steps, "Print steps");
-            ui.checkbox(&mut self.early_stop, "Early stop?");
             ui.checkbox(&mut self.filter, "Filter Columns");
             ui.checkbox(&mut self.save_error, "Save Error");
+            ui.checkbox(&mut self.colour, "Full colour (RGB)");
+            ui.add_enabled(
+                self.colour,
+                egui::Checkbox::new(&mut self.parallel_channels, "Solve channels in parallel"),
+            );
+
+            egui::ComboBox::from_label("Solver")
+                .selected_text(self.solver.to_string())
+                .show_ui(ui, |ui| {
+                    for kind in SolverKind::ALL {
+                        ui.selectable_value(&mut self.solver, kind, kind.to_string());
+                    }
+                });
+
+            let mut single = self.parallelism == Parallelism::Single;
+            if ui.checkbox(&mut single, "Single threaded").changed() {
+                self.parallelism = if single {
+                    Parallelism::Single
+                } else {
+                    Parallelism::default()
+                };
+            }
+
+            ui.add(
+                egui::DragValue::new(&mut self.checkpoint_every)
+                    .range(0..=10000)
+                    .prefix("Checkpoint every: ")
+                    .suffix(" iterations"),
+            );
+
+            ui.add(
+                egui::DragValue::new(&mut self.cache_budget)
+                    .speed(16.0)
+                    .range(0.0..=f32::MAX)
+                    .prefix("Projection cache: ")
+                    .suffix(" MiB"),
+            );
+
+            ui.label("Time multiplexed frames");
+            ui.add(egui::Slider::new(&mut self.frames, 1..=8));
+
+            ui.collapsing("Stopping", |ui| {
+                let stopping = &mut self.stopping;
+                ui.add(
+                    egui::DragValue::new(&mut stopping.relative_tolerance)
+                        .speed(0.0001)
+                        .range(0.0..=1.0)
+                        .prefix("Relative tolerance: "),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut stopping.patience)
+                        .range(1..=1000)
+                        .prefix("Patience: ")
+                        .suffix(" iterations"),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut stopping.time_budget)
+                        .speed(0.1)
+                        .range(0.0..=f32::MAX)
+                        .prefix("Time budget: ")
+                        .suffix(" s"),
+                );
+            });
+
+            ui.collapsing("Log domain least squares", |ui| {
+                let log_least_squares = &mut self.log_least_squares;
+                ui.add(
+                    egui::DragValue::new(&mut log_least_squares.floor)
+                        .speed(0.0001)
+                        .range(1e-6..=1.0)
+                        .prefix("Floor: "),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut log_least_squares.iterations)
+                        .range(1..=10000)
+                        .prefix("CGLS iterations: "),
+                );
+            });
+
+            ui.collapsing("Regularization", |ui| {
+                let regularization = &mut self.regularization;
+                for (weight, label) in [
+                    (&mut regularization.total_variation, "Total variation"),
+                    (&mut regularization.smoothness, "Smoothness"),
+                    (&mut regularization.sparsity, "Sparsity (L1)"),
+                    (&mut regularization.brightness, "Brightness"),
+                ] {
+                    ui.add(
+                        egui::DragValue::new(weight)
+                            .speed(0.01)
+                            .range(0.0..=f32::MAX)
+                            .prefix(format!("{label}: ")),
+                    );
+                }
+                ui.add(
+                    egui::Slider::new(&mut regularization.brightness_target, 0.0..=1.0)
+                        .text("Brightness target"),
+                );
+            });
 
             if ui.button("Solve").clicked() {
                 self.solve_next_redraw_flag = true;
             }
 
-            ui.checkbox(&mut self.rng, "Random starting values");
+            egui::ComboBox::from_label("Initialization")
+                .selected_text(self.initialization.label())
+                .show_ui(ui, |ui| {
+                    for initialization in self.initialization.choices() {
+                        let label = initialization.label();
+                        ui.selectable_value(&mut self.initialization, initialization, label);
+                    }
+                });
+            match &mut self.initialization {
+                Initialization::Random { seed } => {
+                    ui.add(egui::DragValue::new(seed).prefix("Seed: "));
+                }
+                Initialization::Saved { kind, name } => {
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(kind, CaptureKind::Separable, "Separable");
+                        ui.selectable_value(kind, CaptureKind::Stereo, "Stereo");
+                    });
+                    ui.text_edit_singleline(name);
+                }
+                _ => {}
+            }
             ui.label("Initial guesses");
             ui.add(egui::Slider::new(
                 &mut self.starting_values.0,
@@ -546,35 +734,352 @@ impl DrawUI for LFSettings {
     }
 }
 
-type L2Norm = Vec<f32>;
+/// Layers of a time multiplexed solution, indexed by frame then by layer
+pub type Frames = Vec<Vec<DynamicImage>>;
+/// Every frame of every layer of a single channel, indexed by layer then by frame
+type LayerFrames<T = f32> = Vec<Vec<Mat<T>>>;
+/// Panels indexed by frame, then by layer, then by channel
+pub type FramePanels = Vec<Vec<Vec<Mat<f32>>>>;
+
+/// Solution for a single channel and how the solve got there
+struct ChannelSolution<T = f32> {
+    layers: LayerFrames<T>,
+    /// Residual of every iteration, including those of a resumed checkpoint
+    error: VecDeque<f32>,
+    /// Time of every iteration of this run
+    times: Vec<Duration>,
+    /// Iterations done, including those of a resumed checkpoint
+    iterations: usize,
+    stop: StopReason,
+}
+
+/// Target as a single luma matrix, or as red, green and blue when solving in colour
+fn target_channels(image: &DynamicImage, colour: bool) -> Vec<Mat<f32>> {
+    if colour {
+        utils::image_to_channels(image).into()
+    } else {
+        vec![utils::image_to_matrix(image)]
+    }
+}
+
+/// Channels share the mapping matrices, so each one is an independent solve.
+/// Everything runs in a pool sized by `settings.parallelism`, the time budget runs from `started`.
+fn solve_channels<T: Scalar, F>(
+    settings: &LFSettings,
+    channels: &[Mat<T>],
+    observer: &dyn SolveObserver,
+    checkpointer: Option<&Checkpointer>,
+    started: Instant,
+    solve: F,
+) -> Vec<ChannelSolution<T>>
+where
+    F: Fn(&Mat<T>, &ChannelReporter) -> ChannelSolution<T> + Sync + Send,
+{
+    let solve = |(channel, c_t): (usize, &Mat<T>)| {
+        let reporter = ChannelReporter {
+            observer,
+            checkpointer,
+            channel,
+            channels: channels.len(),
+            iterations: settings.iter_count,
+            started,
+        };
+        solve(c_t, &reporter)
+    };
+    settings.parallelism.install(|| {
+        if settings.parallel_channels && channels.len() > 1 {
+            channels.par_iter().enumerate().map(solve).collect()
+        } else {
+            channels.iter().enumerate().map(solve).collect()
+        }
+    })
+}
+
+/// Collect the panels of every channel per frame and layer, errors are combined as the L2 norm
+/// over channels. Panels are brought back to `f32` for the image outputs, which are left to the
+/// caller. A checkpoint that could not be written is reported along with them.
+fn split_solutions<T: Scalar>(
+    solutions: Vec<ChannelSolution<T>>,
+    checkpointer: Option<&Checkpointer>,
+    started: Instant,
+) -> SolveReport {
+    let iterations = solutions.iter().map(|x| x.error.len()).min().unwrap_or(0);
+    let residuals = (0..iterations)
+        .map(|index| {
+            solutions
+                .iter()
+                .map(|x| x.error[index] * x.error[index])
+                .sum::<f32>()
+                .sqrt()
+        })
+        .collect();
+    let run = solutions.iter().map(|x| x.times.len()).max().unwrap_or(0);
+    let times = (0..run)
+        .map(|index| solutions.iter().filter_map(|x| x.times.get(index)).sum())
+        .collect();
+    let (stop, iterations) = solutions
+        .iter()
+        .max_by_key(|x| x.iterations)
+        .map(|x| (x.stop, x.iterations))
+        .unwrap_or_default();
+    let (layers, frames) = solutions
+        .first()
+        .map(|x| (x.layers.len(), x.layers.first().map(Vec::len).unwrap_or(0)))
+        .unwrap_or((0, 0));
+    let mut panels: FramePanels = vec![vec![Vec::new(); layers]; frames];
+    for channel in solutions {
+        for (layer, frames) in channel.layers.into_iter().enumerate() {
+            for (frame, panel) in frames.into_iter().enumerate() {
+                panels[frame][layer].push(scalar::cast(&panel));
+            }
+        }
+    }
+    SolveReport {
+        frames: Vec::new(),
+        panels,
+        residuals,
+        times,
+        elapsed: started.elapsed(),
+        stop,
+        iterations,
+        checkpoint_error: checkpointer
+            .and_then(Checkpointer::take_error)
+            .map(Arc::new),
+    }
+}
+
+/// Panels, error history and iterations done a channel starts from. Either the state of a
+/// resumed checkpoint or `initial` panels.
+fn start_channel<T: Scalar>(
+    settings: &LFSettings,
+    reporter: &ChannelReporter,
+    initial: impl FnOnce() -> LayerFrames<T>,
+) -> (LayerFrames<T>, VecDeque<f32>, usize) {
+    match reporter.start() {
+        Some(state) => (
+            scalar::cast_layers(&state.layers),
+            state.error,
+            state.iteration,
+        ),
+        None => (initial(), VecDeque::with_capacity(settings.iter_count), 0),
+    }
+}
+
+/// Panels `settings.initialization` loads for a solve of a `kind` capture, nothing when resuming
+/// a checkpoint
+fn saved_panels(
+    settings: &LFSettings,
+    kind: CaptureKind,
+    sizes: &[(u32, u32)],
+    resuming: bool,
+) -> error::Result<Option<Frames>> {
+    if resuming {
+        return Ok(None);
+    }
+    settings.initialization.load(kind, settings, sizes)
+}
+
+/// Starting value of a layer, the closest layer has its own
+fn starting_value(settings: &LFSettings, layer: usize) -> f32 {
+    if layer == 0 {
+        settings.starting_values.0
+    } else {
+        settings.starting_values.1
+    }
+}
+
+/// Every layer with `candidate` in place of one frame
+fn with_candidate<'a, T: Scalar>(
+    layers: &'a [Vec<Mat<T>>],
+    (layer, frame): (usize, usize),
+    candidate: &'a Mat<T>,
+) -> Vec<Vec<&'a Mat<T>>> {
+    layers
+        .iter()
+        .enumerate()
+        .map(|(layer_index, frames)| {
+            frames
+                .iter()
+                .enumerate()
+                .map(|(frame_index, x)| {
+                    if (layer_index, frame_index) == (layer, frame) {
+                        candidate
+                    } else {
+                        x
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Element wise product over the layers of one frame, leaving out `skip`.
+/// With every layer this is the light field of the frame, without one it is the attenuation
+/// the skipped layer sees.
+fn product_of_layers<T: Scalar, M: Borrow<Mat<T>>>(
+    products: &[Vec<M>],
+    frame: usize,
+    skip: Option<usize>,
+) -> Mat<T> {
+    let first = products[0][frame].borrow();
+    let mut product = Mat::from_fn(first.nrows(), first.ncols(), |_, _| T::ONE);
+    for (layer, frames) in products.iter().enumerate() {
+        if Some(layer) == skip {
+            continue;
+        }
+        zip!(&mut product, frames[frame].borrow()).for_each(|unzip!(product, x)| *product *= *x);
+    }
+    product
+}
+
+/// Average over frames of the light field every layer produces together
+fn estimate<T: Scalar, M: Borrow<Mat<T>>>(products: &[Vec<M>]) -> Mat<T> {
+    let frames = products[0].len();
+    let scale = T::ONE / T::from_usize(frames);
+    let first = products[0][0].borrow();
+    let mut estimate = Mat::zeros(first.nrows(), first.ncols());
+    for frame in 0..frames {
+        let product = product_of_layers(products, frame, None);
+        zip!(&mut estimate, &product).for_each(|unzip!(e, p)| *e += *p * scale);
+    }
+    estimate
+}
+
+/// Buffers for the update terms of one panel, see `UpdateTerms`
+struct TermAccumulator<T: Scalar> {
+    numerator: Mat<T>,
+    denominator: Mat<T>,
+    hessian: Mat<T>,
+    needs_hessian: bool,
+}
+
+impl<T: Scalar> TermAccumulator<T> {
+    fn new(rows: usize, cols: usize, needs_hessian: bool) -> Self {
+        // Only allocate the Hessian diagonal when the strategy uses it
+        let (hessian_rows, hessian_cols) = if needs_hessian { (rows, cols) } else { (0, 0) };
+        TermAccumulator {
+            numerator: Mat::zeros(rows, cols),
+            denominator: Mat::zeros(rows, cols),
+            hessian: Mat::zeros(hessian_rows, hessian_cols),
+            needs_hessian,
+        }
+    }
+    fn terms(&self, layer: usize) -> UpdateTerms<'_, T> {
+        UpdateTerms {
+            layer,
+            numerator: &self.numerator,
+            denominator: &self.denominator,
+            hessian: self.needs_hessian.then_some(&self.hessian),
+        }
+    }
+    /// Adds the priors on `panel` to the terms
+    fn regularize(&mut self, regularization: &Regularization, panel: &Mat<T>) {
+        regularization.add_terms(
+            panel,
+            panel.shape(),
+            &mut self.numerator,
+            &mut self.denominator,
+            self.needs_hessian.then_some(&mut self.hessian),
+        );
+    }
+    /// Adds the partial sums of `other`
+    fn add(&mut self, other: &Self) {
+        for (sum, partial) in [
+            (&mut self.numerator, &other.numerator),
+            (&mut self.denominator, &other.denominator),
+            (&mut self.hessian, &other.hessian),
+        ] {
+            zip!(sum, partial).for_each(|unzip!(sum, x)| *sum += *x);
+        }
+    }
+    fn clear(&mut self) {
+        for accumulator in [
+            &mut self.numerator,
+            &mut self.denominator,
+            &mut self.hessian,
+        ] {
+            accumulator.fill(T::ZERO);
+        }
+    }
+}
+
+/// The views one thread of the separable solver sums the update terms of. Each chunk has
+/// buffers and partial sums of its own, so threads never share one.
+struct ViewChunk<T: Scalar> {
+    views: Range<usize>,
+    /// `upper` and `lower` terms of one view
+    scratch: [Mat<T>; 2],
+    /// Partial terms of every layer
+    terms: Vec<TermAccumulator<T>>,
+}
+
+/// `views` split into at most `chunks` contiguous ranges, the first ones a view longer
+fn view_chunks(views: usize, chunks: usize) -> Vec<Range<usize>> {
+    let chunks = chunks.clamp(1, views.max(1));
+    let (size, longer) = (views / chunks, views % chunks);
+    let mut start = 0;
+    (0..chunks)
+        .map(|chunk| {
+            let end = start + size + usize::from(chunk < longer);
+            let range = start..end;
+            start = end;
+            range
+        })
+        .collect()
+}
+
+fn channels_into_image(channels: Vec<Mat<f32>>) -> DynamicImage {
+    match <[Mat<f32>; 3]>::try_from(channels) {
+        Ok(rgb) => utils::channels_to_image(&rgb),
+        Err(grey) => utils::matrix_to_image(&grey[0]),
+    }
+}
+
+fn vectors_into_image(channels: Vec<Mat<f32>>, size: (u32, u32)) -> DynamicImage {
+    match <[Mat<f32>; 3]>::try_from(channels) {
+        Ok(rgb) => utils::vectors_to_image(&rgb, size.0, size.1),
+        Err(grey) => utils::vector_to_image(&grey[0], size.0, size.1),
+    }
+}
+
+/// Every solve reports to a `SolveObserver` after each iteration, pass `&()` to not observe it.
+/// A stopped solve returns the panels it had reached, one that can't start fails with the
+/// reason, such as saved panels that don't fit the capture.
 pub trait Lff {
+    /// Every layer of a time multiplexed solution, `settings.frames` of them. Continues from the
+    /// panels of `resume`, which has to be a checkpoint of this capture. `LFMatrices::resume`
+    /// and `StereoMatrix::resume` load and validate one.
+    fn factorize_from(
+        &self,
+        settings: &LFSettings,
+        observer: &dyn SolveObserver,
+        resume: Option<Checkpoint>,
+    ) -> error::Result<Option<SolveReport>>;
+    /// Every layer of a time multiplexed solution, `settings.frames` of them
     fn factorize(
         &self,
         settings: &LFSettings,
-    ) -> Option<(DynamicImage, DynamicImage, Option<L2Norm>)> {
-        let _ = settings;
-        let _ = self;
-        None
+        observer: &dyn SolveObserver,
+    ) -> error::Result<Option<SolveReport>> {
+        self.factorize_from(settings, observer, None)
     }
 }
 
-impl Lff for LFMatrices {
-    fn factorize(
+impl<T: Scalar> Lff for LFMatrices<T> {
+    fn factorize_from(
         &self,
         settings: &LFSettings,
-    ) -> Option<(DynamicImage, DynamicImage, Option<L2Norm>)> {
-        faer::set_global_parallelism(faer::Par::Rayon(NonZero::new(10).unwrap()));
+        observer: &dyn SolveObserver,
+        resume: Option<Checkpoint>,
+    ) -> error::Result<Option<SolveReport>> {
+        let started = Instant::now();
         let target_size = self.target_size;
         let number_of_view_points = self.number_of_view_points;
         if number_of_view_points == 0 {
-            return None;
+            return Ok(None);
         }
-        let c_t = &self.c_t;
         if settings.debug_prints {
-            println!(
-                "Global Parallelism is: {:?}",
-                faer::get_global_parallelism()
-            );
+            println!("Solving on {} thread(s)", settings.parallelism.threads());
         }
 
         let rays_cast = (
@@ -587,42 +1092,16 @@ impl Lff for LFMatrices {
 
         let matrices = self;
 
-        let c_t = utils::image_to_matrix(c_t);
-        if settings.debug_prints {
-            println!("C_T shape: {:?}", c_t.shape());
-        }
-
-        let h_a = matrices.a.size.0 as usize;
-        let w_a = matrices.a.size.1 as usize;
-        if settings.debug_prints {
-            println!("H_a is : {h_a}");
-            println!("w_a is : {w_a}");
-        }
-        let mut c_a = Mat::from_fn(h_a, w_a, |_x, _y| {
-            if settings.rng {
-                thread_rng().gen_range(0f32..1.0f32)
-            } else {
-                settings.starting_values.0
-            }
-        });
-
-        let h_b = matrices.b.size.0 as usize;
-        let w_b = matrices.b.size.1 as usize;
-
+        let channels: Vec<Mat<T>> = target_channels(&self.c_t, settings.colour)
+            .iter()
+            .map(scalar::cast)
+            .collect();
         if settings.debug_prints {
-            println!("H_b is : {h_b}");
-            println!("w_b is : {w_b}");
-        }
-        let mut c_b = Mat::from_fn(h_b, w_b, |_x, _y| {
-            if settings.rng {
-                thread_rng().gen_range(0f32..1.0f32)
-            } else {
-                settings.starting_values.1
+            println!("C_T shape: {:?}", channels[0].shape());
+            println!("Solving {} channel(s)", channels.len());
+            for (index, layer) in self.layers.iter().enumerate() {
+                layer.debug_print(format!("M_{index}"));
             }
-        });
-        if settings.debug_prints {
-            self.a.debug_print("M_A".to_string());
-            self.b.debug_print("M_B".to_string());
             self.t.debug_print("M_T".to_string());
         }
 
@@ -631,9 +1110,85 @@ impl Lff for LFMatrices {
             rays_cast.1 / number_of_view_points,
         );
 
-        let mut upper = Mat::<f32>::zeros(single_pass_size.0 as usize, single_pass_size.1 as usize);
+        let sizes: Vec<(u32, u32)> = self.layers.iter().map(|layer| layer.size).collect();
+        let saved = saved_panels(settings, CaptureKind::Separable, &sizes, resume.is_some())?;
+        let checkpointer = Checkpointer::new(self, settings, resume);
+        let solutions = solve_channels(
+            settings,
+            &channels,
+            observer,
+            checkpointer.as_ref(),
+            started,
+            |c_t, reporter| {
+                self.solve_channel(settings, c_t, single_pass_size, saved.as_ref(), reporter)
+            },
+        );
+        let mut report = split_solutions(solutions, checkpointer.as_ref(), started);
 
-        let mut lower = Mat::<f32>::zeros(single_pass_size.0 as usize, single_pass_size.1 as usize);
+        if settings.filter {
+            for (layer, mapping) in matrices.layers.iter().enumerate() {
+                if settings.debug_prints {
+                    println!("Filtering layer {layer}");
+                }
+                for panel in report.panels.iter_mut().flat_map(|x| x[layer].iter_mut()) {
+                    utils::filter_zeroes(panel, mapping);
+                }
+            }
+        }
+        for panel in report.panels.iter().flatten().flatten() {
+            utils::verify_matrix(panel);
+        }
+
+        report.frames = report
+            .panels
+            .iter()
+            .map(|layers| layers.iter().cloned().map(channels_into_image).collect())
+            .collect();
+
+        if settings.debug_prints {
+            println!("Separable solve: {report}");
+        }
+        Ok(Some(report))
+    }
+}
+
+impl<T: Scalar> LFMatrices<T> {
+    /// Per view update for a single channel of the target. Layers are updated front to back,
+    /// with several frames each one is updated in turn against the average of all of them.
+    fn solve_channel(
+        &self,
+        settings: &LFSettings,
+        c_t: &Mat<T>,
+        single_pass_size: (u32, u32),
+        saved: Option<&Frames>,
+        reporter: &ChannelReporter,
+    ) -> ChannelSolution<T> {
+        let (mut layers, mut error, done) = start_channel(settings, reporter, || {
+            let sizes: Vec<(u32, u32)> = self.layers.iter().map(|layer| layer.size).collect();
+            if settings.debug_prints {
+                for (index, (rows, cols)) in sizes.iter().enumerate() {
+                    println!("Layer {index} is: {rows} x {cols}");
+                }
+            }
+            // Strategies working on rays see the capture in the row form of the stereo approach
+            let rays = settings.initialization.needs_rays().then(|| {
+                let layers: Vec<IndexMapping<T>> =
+                    self.layers.iter().map(CompleteMapping::expand).collect();
+                (layers, self.t.expand().gather(&mapping::vectorize(c_t)))
+            });
+            let rays = rays.as_ref().map(|(layers, l_vec)| (&layers[..], l_vec));
+            let channel = (reporter.channel, reporter.channels);
+            initialization::initial_panels(settings, channel, &sizes, saved, rays)
+                .into_iter()
+                .zip(&sizes)
+                .map(|(frames, (rows, cols))| {
+                    frames
+                        .iter()
+                        .map(|x| mapping::unvectorize(x, *rows as usize, *cols as usize))
+                        .collect()
+                })
+                .collect()
+        });
 
         // Move IO out of loop and into dedicated thread
 
@@ -646,172 +1201,358 @@ impl Lff for LFMatrices {
                 None
             }
         };
-        let mut error = VecDeque::with_capacity(settings.iter_count);
-
-        let mut numerator_a = Mat::zeros(c_a.nrows(), c_a.ncols());
-        let mut denominator_a = Mat::zeros(c_a.nrows(), c_a.ncols());
-
-        let mut numerator_b = Mat::zeros(c_b.nrows(), c_b.ncols());
-        let mut denominator_b = Mat::zeros(c_b.nrows(), c_b.ncols());
-        for _x in 0..settings.iter_count {
+        let strategy = settings.solver.strategy(capture::CaptureKind::Separable);
+        let needs_hessian = strategy.needs_hessian();
+        let regularization = &settings.regularization;
+
+        let new_terms = || -> Vec<TermAccumulator<T>> {
+            self.layers
+                .iter()
+                .map(|x| TermAccumulator::new(x.size.0 as usize, x.size.1 as usize, needs_hessian))
+                .collect()
+        };
+        let mut terms = new_terms();
+        // One chunk of views per thread of the pool, summed in view order afterwards so the
+        // result only depends on the number of threads
+        let (rows, cols) = (single_pass_size.0 as usize, single_pass_size.1 as usize);
+        let views = self.number_of_view_points as usize;
+        let mut chunks: Vec<ViewChunk<T>> = view_chunks(views, rayon::current_num_threads())
+            .into_iter()
+            .map(|views| ViewChunk {
+                views,
+                scratch: [Mat::zeros(rows, cols), Mat::zeros(rows, cols)],
+                terms: new_terms(),
+            })
+            .collect();
+        // Channels solved at the same time share the budget
+        let concurrent = if settings.parallel_channels {
+            reporter.channels
+        } else {
+            1
+        };
+        let budget = (settings.cache_budget.max(0.0) * MIB) as usize / concurrent.max(1);
+        let mut cache = ProjectionCache::new(self, c_t, &layers, budget);
+        if settings.debug_prints {
+            let (targets, panels) = cache.views();
+            println!(
+                "Caching the target of {targets} and the panels of {panels} of {} views",
+                self.number_of_view_points
+            );
+        }
+        let mut convergence =
+            Convergence::new(settings.stopping, reporter.started, error.back().copied());
+        let mut times = Vec::with_capacity(settings.iter_count.saturating_sub(done));
+        let (mut iterations, mut stop) = (done, StopReason::Iterations);
+        for iteration in done + 1..=settings.iter_count {
             progress_bar.as_mut().inspect(|x| x.inc(1));
 
+            let start = Instant::now();
+            for layer in 0..layers.len() {
+                for frame in 0..layers[layer].len() {
+                    let accumulator = &mut terms[layer];
+                    let index = (layer, frame);
+                    self.accumulate_terms(c_t, &layers, &cache, index, accumulator, &mut chunks);
+                    accumulator.regularize(regularization, &layers[layer][frame]);
+
+                    let mut panel = std::mem::replace(&mut layers[layer][frame], Mat::new());
+                    strategy.update(&mut panel, &accumulator.terms(layer), &|candidate| {
+                        self.objective(c_t, &layers, &cache, Some((index, candidate)))
+                            + regularization.value(candidate, candidate.shape())
+                    });
+                    cache.update(&self.layers[layer], index, &panel);
+                    layers[layer][frame] = panel;
+                    accumulator.clear();
+                }
+            }
+            let mut residual = None;
+            if settings.save_error
+                || settings.stopping.needs_residual()
+                || reporter.wants_residual()
             {
-                for view_point in 0..number_of_view_points as usize {
-                    let m_a_x = matrices.a.x.matrix[view_point].as_ref();
-                    let m_a_y = matrices.a.y.matrix[view_point].as_ref();
-
-                    let m_b_x = matrices.b.x.matrix[view_point].as_ref();
-                    let m_b_y = matrices.b.y.matrix[view_point].as_ref();
-
-                    let m_t_x = matrices.t.x.matrix[view_point].as_ref();
-                    let m_t_y = matrices.t.y.matrix[view_point].as_ref();
+                let norm = self.residual(c_t, &layers, &cache);
+                residual = Some(norm);
+                error.push_back(norm.to_f32());
+            }
+            times.push(start.elapsed());
+            iterations = iteration;
 
-                    let c_t_m_product = (m_t_y * &c_t) * m_t_x.transpose();
-                    let c_b_m_product = m_b_y * &c_b * m_b_x.transpose();
-                    let c_a_m_product = m_a_y * &c_a * m_a_x.transpose();
+            // Compared in the precision of the solve, not of the error history
+            if let Some(reason) =
+                reporter.report(&mut convergence, iteration, residual, &layers, &error)
+            {
+                stop = reason;
+                break;
+            }
+        }
 
-                    zip!(&mut upper, &c_b_m_product, &c_t_m_product).for_each(
-                        |unzip!(upper, c_b, c_t)| {
-                            *upper = *c_b * *c_t;
-                        },
-                    );
+        ChannelSolution {
+            layers,
+            error,
+            times,
+            iterations,
+            stop,
+        }
+    }
 
-                    zip!(&mut lower, &c_b_m_product, &c_a_m_product).for_each(
-                        |unzip!(lower, c_b, c_a)| {
-                            *lower = *c_a * *c_b * *c_b;
-                        },
-                    );
+    /// Light field of every frame of every layer for one view, `M_y c M_x^T`
+    fn view_products(&self, view_point: usize, layers: &[Vec<&Mat<T>>]) -> LayerFrames<T> {
+        layers
+            .iter()
+            .zip(&self.layers)
+            .map(|(frames, mapping)| {
+                frames
+                    .iter()
+                    .map(|c| mapping.project(view_point, c))
+                    .collect()
+            })
+            .collect()
+    }
 
-                    numerator_a += m_a_y.transpose() * &upper * m_a_x;
-                    denominator_a += m_a_y.transpose() * &lower * m_a_x;
+    /// Hands `f` the target and panel projections of one view, from the cache when it holds
+    /// them. `candidate` stands in for one frame of one layer.
+    fn with_view<R>(
+        &self,
+        view_point: usize,
+        c_t: &Mat<T>,
+        layers: &[Vec<Mat<T>>],
+        cache: &ProjectionCache<T>,
+        candidate: Option<((usize, usize), &Mat<T>)>,
+        f: impl FnOnce(&Mat<T>, &[Vec<&Mat<T>>]) -> R,
+    ) -> R {
+        let projected;
+        let target = match cache.target(view_point) {
+            Some(target) => target,
+            None => {
+                projected = self.t.project(view_point, c_t);
+                &projected
+            }
+        };
+        let (computed, swapped);
+        let products = match cache.panels(view_point) {
+            Some(cached) => {
+                let mut products = as_refs(cached);
+                if let Some(((layer, frame), candidate)) = candidate {
+                    swapped = self.layers[layer].project(view_point, candidate);
+                    products[layer][frame] = &swapped;
                 }
-
-                zip!(&mut c_a, &mut numerator_a, &mut denominator_a).for_each(
-                    |unzip!(c_a, n, d)| {
-                        *c_a = 1.0_f32.min(*c_a * *n / (*d + 0.0000001f32));
-                        *n = 0.0;
-                        *d = 0.0;
-                    },
-                );
+                products
+            }
+            None => {
+                let layers = match candidate {
+                    Some((index, candidate)) => with_candidate(layers, index, candidate),
+                    None => as_refs(layers),
+                };
+                computed = self.view_products(view_point, &layers);
+                as_refs(&computed)
             }
+        };
+        f(target, &products)
+    }
 
-            {
-                for view_point in 0..number_of_view_points as usize {
-                    let m_a_x = matrices.a.x.matrix[view_point].as_ref();
-                    let m_a_y = matrices.a.y.matrix[view_point].as_ref();
-
-                    let m_b_x = matrices.b.x.matrix[view_point].as_ref();
-                    let m_b_y = matrices.b.y.matrix[view_point].as_ref();
-
-                    let m_t_x = matrices.t.x.matrix[view_point].as_ref();
-                    let m_t_y = matrices.t.y.matrix[view_point].as_ref();
-                    let c_t_m_product = (m_t_y * &c_t) * m_t_x.transpose();
-                    let c_b_m_product = m_b_y * &c_b * m_b_x.transpose();
-                    let c_a_m_product = m_a_y * &c_a * m_a_x.transpose();
-
-                    zip!(&mut upper, &c_a_m_product, &c_t_m_product).for_each(
-                        |unzip!(upper, c_a, c_t)| {
-                            *upper = *c_a * *c_t;
+    /// Sums the update terms of one frame of one layer over every view, each chunk of views on
+    /// a thread of its own. The estimate of a ray is the average over frames of the product of
+    /// every layer.
+    fn accumulate_terms(
+        &self,
+        c_t: &Mat<T>,
+        layers: &[Vec<Mat<T>>],
+        cache: &ProjectionCache<T>,
+        (layer, frame): (usize, usize),
+        accumulator: &mut TermAccumulator<T>,
+        chunks: &mut [ViewChunk<T>],
+    ) {
+        let scale = T::ONE / T::from_usize(layers[0].len());
+        let own = &self.layers[layer];
+        chunks.par_iter_mut().for_each(|chunk| {
+            let [upper, lower] = &mut chunk.scratch;
+            let partial = &mut chunk.terms[layer];
+            for view_point in chunk.views.clone() {
+                self.with_view(view_point, c_t, layers, cache, None, |target, products| {
+                    let estimate = estimate(products);
+                    let other = product_of_layers(products, frame, Some(layer));
+
+                    zip!(&mut *upper, &other, target).for_each(|unzip!(upper, other, c_t)| {
+                        *upper = *other * *c_t * scale;
+                    });
+
+                    zip!(&mut *lower, &other, &estimate).for_each(
+                        |unzip!(lower, other, estimate)| {
+                            *lower = *other * *estimate * scale;
                         },
                     );
 
-                    zip!(&mut lower, &c_b_m_product, &c_a_m_product).for_each(
-                        |unzip!(lower, c_b, c_a)| {
-                            *lower = *c_b * *c_a * *c_a;
-                        },
-                    );
+                    own.back_project_into(view_point, upper, &mut partial.numerator);
+                    own.back_project_into(view_point, lower, &mut partial.denominator);
 
-                    numerator_b += m_b_y.transpose() * &upper * m_b_x;
-                    denominator_b += m_b_y.transpose() * &lower * m_b_x;
-                }
-                zip!(&mut c_b, &mut numerator_b, &mut denominator_b).for_each(
-                    |unzip!(c_b, n, d)| {
-                        *c_b = 1.0_f32.min(*c_b * *n / (*d + 0.000000001f32));
-                        *n = 0.0;
-                        *d = 0.0;
-                    },
-                );
+                    if partial.needs_hessian {
+                        zip!(&mut *lower, &other).for_each(|unzip!(lower, other)| {
+                            *lower = *other * *other * scale * scale
+                        });
+                        own.back_project_into(view_point, lower, &mut partial.hessian);
+                    }
+                });
             }
+        });
+        for chunk in chunks {
+            accumulator.add(&chunk.terms[layer]);
+            chunk.terms[layer].clear();
         }
+    }
 
-        if settings.filter {
-            if settings.debug_prints {
-                println!("Filtering C_a");
-            }
-            utils::filter_zeroes(&mut c_a, &matrices.a);
+    /// L2 norm of the reprojection residual over every view, the separable version of
+    /// `l - (M_1 c_1) * ... * (M_k c_k)`
+    fn residual(&self, c_t: &Mat<T>, layers: &[Vec<Mat<T>>], cache: &ProjectionCache<T>) -> T {
+        self.squared_residual(c_t, layers, cache, None).sqrt()
+    }
 
-            if settings.debug_prints {
-                println!("Filtering C_b");
-            }
-            utils::filter_zeroes(&mut c_b, &matrices.b);
-        }
-        utils::verify_matrix(&c_a);
-        utils::verify_matrix(&c_b);
+    /// `0.5 * |residual|^2`, what the solver strategies minimize
+    fn objective(
+        &self,
+        c_t: &Mat<T>,
+        layers: &[Vec<Mat<T>>],
+        cache: &ProjectionCache<T>,
+        candidate: Option<((usize, usize), &Mat<T>)>,
+    ) -> T {
+        T::from_f32(0.5) * self.squared_residual(c_t, layers, cache, candidate)
+    }
 
-        let image_a = utils::matrix_to_image(&c_a);
+    fn squared_residual(
+        &self,
+        c_t: &Mat<T>,
+        layers: &[Vec<Mat<T>>],
+        cache: &ProjectionCache<T>,
+        candidate: Option<((usize, usize), &Mat<T>)>,
+    ) -> T {
+        // Every view on any thread, summed in view order
+        let views: Vec<T> = (0..self.number_of_view_points as usize)
+            .into_par_iter()
+            .map(|view_point| {
+                self.with_view(
+                    view_point,
+                    c_t,
+                    layers,
+                    cache,
+                    candidate,
+                    |target, products| {
+                        let estimate = estimate(products);
+                        let total =
+                            zip!(target, &estimate).map(|unzip!(t, estimate)| *t - *estimate);
+                        total.squared_norm_l2()
+                    },
+                )
+            })
+            .collect();
+        let mut squared = T::ZERO;
+        for view in views {
+            squared += view;
+        }
+        squared
+    }
+}
 
-        let image_b = utils::matrix_to_image(&c_b);
+/// Borrows every frame of every layer
+fn as_refs<T>(layers: &[Vec<Mat<T>>]) -> Vec<Vec<&Mat<T>>> {
+    layers.iter().map(|x| x.iter().collect()).collect()
+}
 
+impl<T: Scalar> Lff for StereoMatrix<T> {
+    fn factorize_from(
+        &self,
+        settings: &LFSettings,
+        observer: &dyn SolveObserver,
+        resume: Option<Checkpoint>,
+    ) -> error::Result<Option<SolveReport>> {
+        let started = Instant::now();
+        let matrices = self;
         if settings.debug_prints {
-            println!("Errors is: {error:?}");
+            for (index, layer) in matrices.layers.iter().enumerate() {
+                println!(
+                    "Size of layer {index} Stereo Matrix is: {:?}",
+                    layer.shape()
+                );
+            }
         }
-        let error = {
-            if settings.save_error {
-                Some(error.into())
-            } else {
-                None
+        let channels = if settings.colour && self.l_rgb.nrows() == self.l_vec.nrows() {
+            (0..3)
+                .map(|channel| {
+                    Mat::from_fn(self.l_rgb.nrows(), 1, |x, _y| self.l_rgb[(x, channel)])
+                })
+                .collect()
+        } else {
+            if settings.colour && settings.debug_prints {
+                println!("No colour samples in this capture, solving for luma");
             }
+            vec![self.l_vec.clone()]
         };
 
-        Some((image_a, image_b, error))
+        if settings.debug_prints {
+            println!("Computing Stereo Approach");
+        }
+        let saved = saved_panels(
+            settings,
+            CaptureKind::Stereo,
+            &self.layer_sizes,
+            resume.is_some(),
+        )?;
+        let checkpointer = Checkpointer::new(self, settings, resume);
+        let solutions = solve_channels(
+            settings,
+            &channels,
+            observer,
+            checkpointer.as_ref(),
+            started,
+            |l_vec, reporter| self.solve_channel(settings, l_vec, saved.as_ref(), reporter),
+        );
+        let mut report = split_solutions(solutions, checkpointer.as_ref(), started);
+
+        for vec in report.panels.iter().flatten().flatten() {
+            utils::verify_matrix(vec);
+        }
+        report.frames = report
+            .panels
+            .iter()
+            .map(|layers| {
+                layers
+                    .iter()
+                    .zip(&self.layer_sizes)
+                    .map(|(layer, size)| vectors_into_image(layer.clone(), *size))
+                    .collect()
+            })
+            .collect();
+        if settings.debug_prints {
+            println!("Stereo solve: {report}");
+        }
+        Ok(Some(report))
     }
 }
 
-impl Lff for StereoMatrix {
-    fn factorize(
+impl<T: Scalar> StereoMatrix<T> {
+    /// Update for a single channel of the ray samples. Layers are updated back to front, with
+    /// several frames each one is updated in turn against the average of all of them.
+    fn solve_channel(
         &self,
         settings: &LFSettings,
-    ) -> Option<(DynamicImage, DynamicImage, Option<L2Norm>)> {
-        faer::set_global_parallelism(faer::Par::Rayon(NonZero::new(10).unwrap()));
-
-        let matrices = self;
-        if settings.debug_prints {
-            println!(
-                "Size of A Stereo Matrix is: {:?}",
-                matrices.a_matrix.matrix.shape()
-            );
-            println!(
-                "Size of B Stereo Matrix is: {:?}",
-                matrices.b_matrix.matrix.shape()
-            );
-        }
-        let rows_a = self.panel_a_size.0 * self.panel_a_size.1;
-        let rows_b = self.panel_b_size.0 * self.panel_b_size.1;
-        let mut vec_a = Mat::from_fn(rows_a as usize, 1, |_x, _y| {
-            if settings.rng {
-                thread_rng().gen_range(0f32..1.0f32)
-            } else {
-                settings.starting_values.0
-            }
+        l_vec: &Mat<T>,
+        saved: Option<&Frames>,
+        reporter: &ChannelReporter,
+    ) -> ChannelSolution<T> {
+        let (mut layers, mut error, done) = start_channel(settings, reporter, || {
+            let channel = (reporter.channel, reporter.channels);
+            let rays = Some((&self.layers[..], l_vec));
+            initialization::initial_panels(settings, channel, &self.layer_sizes, saved, rays)
         });
+        let scale = T::ONE / T::from_usize(settings.frames.max(1));
 
-        let mut vec_b = Mat::from_fn(rows_b as usize, 1, |_x, _y| {
-            if settings.rng {
-                thread_rng().gen_range(0f32..1.0f32)
-            } else {
-                settings.starting_values.1
-            }
-        });
-        // Precompute the transpose
-        let m_a_trans = matrices.a_matrix.matrix.transpose();
-        let m_b_trans = matrices.b_matrix.matrix.transpose();
-        let mut time_taken_total: Vec<Duration> = Vec::with_capacity(settings.iter_count);
+        // Rays through each frame of every layer, kept up to date after every update
+        let mut rays: LayerFrames<T> = layers
+            .iter()
+            .zip(&self.layers)
+            .map(|(frames, mapping)| frames.iter().map(|x| mapping.gather(x)).collect())
+            .collect();
 
-        let mut error = VecDeque::with_capacity(settings.iter_count);
-        if settings.debug_prints {
-            println!("Computing Stereo Approach");
-        }
+        let strategy = settings.solver.strategy(capture::CaptureKind::Stereo);
+        let needs_hessian = strategy.needs_hessian();
+        let regularization = &settings.regularization;
         let mut progress_bar = {
             if settings.debug_prints {
                 Some(indicatif::ProgressBar::new(settings.iter_count as u64))
@@ -819,83 +1560,93 @@ impl Lff for StereoMatrix {
                 None
             }
         };
-        for _x in 0..settings.iter_count {
+        let mut convergence =
+            Convergence::new(settings.stopping, reporter.started, error.back().copied());
+        let mut times = Vec::with_capacity(settings.iter_count.saturating_sub(done));
+        let (mut iterations, mut stop) = (done, StopReason::Iterations);
+        for iteration in done + 1..=settings.iter_count {
             progress_bar.as_mut().inspect(|x| x.inc(1));
 
             let start = Instant::now();
-            {
-                let t2_rays = &matrices.b_matrix.matrix * &vec_b;
-                let t1_rays = &matrices.a_matrix.matrix * &vec_a;
-
-                let upper = zip!(&t1_rays, &matrices.l_vec).map(|unzip!(u, l)| *u * *l);
-                let numerator = m_b_trans * upper;
-
-                let lower = zip!(&t2_rays, &t1_rays).map(|unzip!(t2, t1)| *t2 * *t1 * *t1);
-                let denominator = m_b_trans * lower;
-
-                zip!(&mut vec_b, &numerator, &denominator)
-                    .for_each(|unzip!(b, n, d)| *b = 1.0_f32.min(*b * *n / (*d + 0.0000001f32)));
+            for layer in (0..layers.len()).rev() {
+                let mapping = &self.layers[layer];
+                let size = (
+                    self.layer_sizes[layer].0 as usize,
+                    self.layer_sizes[layer].1 as usize,
+                );
+                for frame in 0..layers[layer].len() {
+                    let estimate = estimate(&rays);
+                    let other = product_of_layers(&rays, frame, Some(layer));
+
+                    let upper = zip!(&other, l_vec).map(|unzip!(u, l)| *u * *l * scale);
+                    let mut numerator = mapping.scatter(&upper);
+
+                    let lower = zip!(&other, &estimate).map(|unzip!(o, e)| *o * *e * scale);
+                    let mut denominator = mapping.scatter(&lower);
+
+                    let mut hessian = needs_hessian.then(|| {
+                        let curvature = zip!(&other).map(|unzip!(o)| *o * *o * scale * scale);
+                        mapping.scatter(&curvature)
+                    });
+                    regularization.add_terms(
+                        &layers[layer][frame],
+                        size,
+                        &mut numerator,
+                        &mut denominator,
+                        hessian.as_mut(),
+                    );
+                    let terms = UpdateTerms {
+                        layer,
+                        numerator: &numerator,
+                        denominator: &denominator,
+                        hessian: hessian.as_ref(),
+                    };
+                    let own = &rays[layer][frame];
+                    strategy.update(&mut layers[layer][frame], &terms, &|candidate| {
+                        let own_new = mapping.gather(candidate);
+                        let estimate = zip!(&estimate, &other, own, &own_new)
+                            .map(|unzip!(e, o, own, new)| *e + *o * (*new - *own) * scale);
+                        Self::objective(&estimate, l_vec) + regularization.value(candidate, size)
+                    });
+                    rays[layer][frame] = mapping.gather(&layers[layer][frame]);
+                }
             }
-
-            // Step for A
+            let mut residual = None;
+            if settings.save_error
+                || settings.stopping.needs_residual()
+                || reporter.wants_residual()
             {
-                // Upper area
-                let t2_rays = &matrices.b_matrix.matrix * &vec_b;
-                let t1_rays = &matrices.a_matrix.matrix * &vec_a;
-
-                let upper = zip!(&t2_rays, &matrices.l_vec).map(|unzip!(u, l)| *u * *l);
-                let numerator = m_a_trans * upper;
-
-                let lower = zip!(&t2_rays, &t1_rays).map(|unzip!(t2, t1)| *t2 * *t2 * *t1);
-                let denominator = m_a_trans * lower;
-                zip!(&mut vec_a, &numerator, &denominator)
-                    .for_each(|unzip!(a, n, d)| *a = 1.0_f32.min(*a * *n / (*d + 0.0000001f32)));
-
-                // Denominator
+                let estimate = estimate(&rays);
+                let norm = zip!(&estimate, l_vec).map(|unzip!(e, l)| *l - *e).norm_l2();
+                residual = Some(norm);
+                error.push_back(norm.to_f32());
             }
+            times.push(start.elapsed());
+            iterations = iteration;
+
+            if let Some(reason) =
+                reporter.report(&mut convergence, iteration, residual, &layers, &error)
             {
-                // Compute error
-                if settings.save_error {
-                    let t2_rays = &matrices.b_matrix.matrix * &vec_b;
-                    let t1_rays = &matrices.a_matrix.matrix * &vec_a;
-                    let total = zip!(&t1_rays, &t2_rays, &matrices.l_vec)
-                        .map(|unzip!(t1, t2, l)| *l - (*t1 * *t2));
-                    let norm = total.norm_l2();
-
-                    if let Some(previous) = error.back() {
-                        let diff: f32 = norm - previous;
-                        if settings.early_stop && diff.abs() < 0.0000001f32 {
-                            break;
-                        }
-                    }
-                    error.push_back(norm);
-                }
+                stop = reason;
+                break;
             }
-
-            let end = Instant::now();
-            let time_taken = end.duration_since(start);
-            time_taken_total.push(time_taken);
         }
 
-        utils::verify_matrix(&vec_a);
-        utils::verify_matrix(&vec_b);
-        let a = utils::vector_to_image(&vec_a, self.panel_a_size.0, self.panel_a_size.1);
-        let b = utils::vector_to_image(&vec_b, self.panel_b_size.0, self.panel_b_size.1);
-        let total_time: Duration = time_taken_total.iter().sum();
-        let average_time = total_time / settings.iter_count as u32;
-        if settings.debug_prints {
-            println!("Average time per iteration: {average_time:?}");
-
-            println!("Errors is: {error:?}");
+        ChannelSolution {
+            layers,
+            error,
+            times,
+            iterations,
+            stop,
         }
-        let error = {
-            if settings.save_error {
-                Some(error.into())
-            } else {
-                None
-            }
-        };
-        Some((a, b, error))
+    }
+
+    /// `0.5 * |l - estimate|^2` for the rays through every layer
+    fn objective(estimate: &Mat<T>, l_vec: &Mat<T>) -> T {
+        T::from_f32(0.5)
+            * zip!(estimate, l_vec)
+                .map(|unzip!(e, l)| *l - *e)
+                .squared_norm_l2()
     }
 }
 
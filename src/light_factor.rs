@@ -5,6 +5,10 @@ use image::DynamicImage;
 use wgpu::{util::DeviceExt, Buffer};
 use winit::event_loop::EventLoopProxy;
 
+use crate::capture::CaptureKind;
+use crate::initialization::save_panels;
+use crate::progress::BackgroundSolve;
+use crate::report::SolveReport;
 use crate::utils::buffer_to_sparse_triplet;
 use crate::utils::DrawUI;
 use crate::*;
@@ -18,7 +22,15 @@ pub struct LFBuffers {
     m_t_x_buffer: Buffer,
     m_t_y_buffer: Buffer,
 
+    /// Scene of the last sampling, written into the header of saved captures
+    pub capture_metadata: crate::capture::CaptureMetadata,
+    /// Failed save of the matrix capture, shown by the app as a toast
+    pub save_error: Option<crate::error::Error>,
+    /// Solve that could not start, shown by the app as a toast
+    pub solve_error: Option<crate::error::Error>,
     pub matrix_rep: Option<LFMatrices>,
+    /// Solve started from the UI, running on its own thread
+    pub background_solve: Option<BackgroundSolve>,
 
     pub bind_group_layout: wgpu::BindGroupLayout,
     pub bind_group: wgpu::BindGroup,
@@ -165,7 +177,11 @@ impl LFBuffers {
             ..Default::default()
         };
         Self {
+            capture_metadata: Default::default(),
+            save_error: None,
+            solve_error: None,
             matrix_rep: None,
+            background_solve: None,
             m_a_y_buffer,
             m_a_x_buffer,
             m_b_y_buffer,
@@ -198,6 +214,9 @@ impl LFBuffers {
     pub fn debug_off(&mut self) {
         self.settings.debug_prints = false;
     }
+    pub fn set_parallelism(&mut self, parallelism: Parallelism) {
+        self.settings.parallelism = parallelism;
+    }
 
     pub fn build_sparse_matrix(
         triplets: Vec<Triplet<u32, u32, f32>>,
@@ -215,52 +234,10 @@ impl LFBuffers {
         rays_cast_per_viewpoint: (u32, u32),
         target_size: (u32, u32),
     ) -> CompleteMapping {
-        let m_t_x = {
-            let vec_t_x = buffer_to_sparse_triplet(&self.m_t_x_buffer, device, rays_cast.0);
-            let columns = target_size.0;
-
-            let triplets = utils::build_tripltes(vec_t_x, rays_cast_per_viewpoint.0 as usize);
-
-            let matrix = triplets
-                .iter()
-                .map(|triplet_list| {
-                    SparseColMat::try_new_from_triplets(
-                        rays_cast_per_viewpoint.0 as usize,
-                        columns as usize,
-                        triplet_list,
-                    )
-                    .unwrap()
-                })
-                .collect();
-
-            MappingMatrix { matrix }
-        };
-
-        let m_t_y = {
-            let vec_t_y = buffer_to_sparse_triplet(&self.m_t_y_buffer, device, rays_cast.1);
-            let columns = target_size.1;
-
-            let triplets = utils::build_tripltes(vec_t_y, rays_cast_per_viewpoint.1 as usize);
-
-            let matrix = triplets
-                .iter()
-                .map(|triplet_list| {
-                    SparseColMat::try_new_from_triplets(
-                        rays_cast_per_viewpoint.1 as usize,
-                        columns as usize,
-                        triplet_list,
-                    )
-                    .unwrap()
-                })
-                .collect();
-            MappingMatrix { matrix }
-        };
-
-        CompleteMapping {
-            x: m_t_x,
-            y: m_t_y,
-            size: target_size,
-        }
+        let _ = number_of_view_points;
+        let vec_t_x = buffer_to_sparse_triplet(&self.m_t_x_buffer, device, rays_cast.0);
+        let vec_t_y = buffer_to_sparse_triplet(&self.m_t_y_buffer, device, rays_cast.1);
+        utils::build_complete_mapping(vec_t_x, vec_t_y, rays_cast_per_viewpoint, target_size, true)
     }
 
     pub fn build_m_a(
@@ -270,52 +247,9 @@ impl LFBuffers {
         rays_cast_per_viewpoint: (u32, u32),
         panel_size: (u32, u32),
     ) -> CompleteMapping {
-        let m_a_y = {
-            let vec_a_y = buffer_to_sparse_triplet(&self.m_a_y_buffer, device, rays_cast.1);
-
-            let columns = panel_size.0;
-
-            let triplets = utils::build_tripltes(vec_a_y, rays_cast_per_viewpoint.1 as usize);
-
-            let matrix = triplets
-                .iter()
-                .map(|triplet_list| {
-                    SparseColMat::try_new_from_triplets(
-                        rays_cast_per_viewpoint.1 as usize,
-                        columns as usize,
-                        triplet_list,
-                    )
-                    .unwrap()
-                })
-                .collect();
-            MappingMatrix { matrix }
-        };
-
-        let m_a_x = {
-            let vec_a_x = buffer_to_sparse_triplet(&self.m_a_x_buffer, device, rays_cast.0);
-            let columns = panel_size.1;
-
-            let triplets = utils::build_tripltes(vec_a_x, rays_cast_per_viewpoint.0 as usize);
-
-            let matrix = triplets
-                .iter()
-                .map(|triplet_list| {
-                    SparseColMat::try_new_from_triplets(
-                        rays_cast_per_viewpoint.0 as usize,
-                        columns as usize,
-                        triplet_list,
-                    )
-                    .unwrap()
-                })
-                .collect();
-            MappingMatrix { matrix }
-        };
-
-        CompleteMapping {
-            x: m_a_x,
-            y: m_a_y,
-            size: panel_size,
-        }
+        let vec_a_x = buffer_to_sparse_triplet(&self.m_a_x_buffer, device, rays_cast.0);
+        let vec_a_y = buffer_to_sparse_triplet(&self.m_a_y_buffer, device, rays_cast.1);
+        utils::build_complete_mapping(vec_a_x, vec_a_y, rays_cast_per_viewpoint, panel_size, false)
     }
 
     pub fn build_m_b(
@@ -325,51 +259,9 @@ impl LFBuffers {
         rays_cast_per_viewpoint: (u32, u32),
         panel_size: (u32, u32),
     ) -> CompleteMapping {
-        let m_b_y = {
-            let vec_b_y = buffer_to_sparse_triplet(&self.m_b_y_buffer, device, rays_cast.1);
-
-            let columns = panel_size.0;
-
-            let triplets = utils::build_tripltes(vec_b_y, rays_cast_per_viewpoint.1 as usize);
-
-            let matrix = triplets
-                .iter()
-                .map(|triplet_list| {
-                    SparseColMat::try_new_from_triplets(
-                        rays_cast_per_viewpoint.1 as usize,
-                        columns as usize,
-                        triplet_list,
-                    )
-                    .unwrap()
-                })
-                .collect();
-            MappingMatrix { matrix }
-        };
-
-        let m_b_x = {
-            let vec_b_x = buffer_to_sparse_triplet(&self.m_b_x_buffer, device, rays_cast.0);
-            let columns = panel_size.1;
-
-            let triplets = utils::build_tripltes(vec_b_x, rays_cast_per_viewpoint.0 as usize);
-
-            let matrix = triplets
-                .iter()
-                .map(|triplet_list| {
-                    SparseColMat::try_new_from_triplets(
-                        rays_cast_per_viewpoint.0 as usize,
-                        columns as usize,
-                        triplet_list,
-                    )
-                    .unwrap()
-                })
-                .collect();
-            MappingMatrix { matrix }
-        };
-        CompleteMapping {
-            x: m_b_x,
-            y: m_b_y,
-            size: panel_size,
-        }
+        let vec_b_x = buffer_to_sparse_triplet(&self.m_b_x_buffer, device, rays_cast.0);
+        let vec_b_y = buffer_to_sparse_triplet(&self.m_b_y_buffer, device, rays_cast.1);
+        utils::build_complete_mapping(vec_b_x, vec_b_y, rays_cast_per_viewpoint, panel_size, false)
     }
 
     pub fn sample_light_field(
@@ -403,32 +295,58 @@ impl LFBuffers {
             rays_per_view_point,
         );
 
-        let matrices = LFMatrices {
-            a,
-            b,
+        // The shader samples the two closest panels
+        let matrices = LFMatrices::new(
+            vec![a, b],
             t,
-            c_t: c_t.clone(),
+            c_t.clone(),
             target_size,
             number_of_view_points,
-        };
+        );
 
         self.matrix_rep = Some(matrices);
     }
 
-    pub fn alternative_factorization(
-        &self,
-    ) -> Option<(DynamicImage, DynamicImage, Option<Vec<f32>>)> {
-        if let Some(rep) = &self.matrix_rep {
-            rep.factorize(&self.settings)
-        } else {
-            None
+    /// Every layer of every frame when solving for more than one frame
+    pub fn alternative_factorization(&self) -> error::Result<Option<SolveReport>> {
+        match &self.matrix_rep {
+            Some(rep) => rep.factorize(&self.settings, &()),
+            None => Ok(None),
         }
     }
-    pub fn old_factorization(&self) -> Option<(DynamicImage, DynamicImage, Option<Vec<f32>>)> {
+    /// Starts `alternative_factorization` on its own thread, replacing any running solve
+    pub fn start_background_solve(&mut self) {
         if let Some(rep) = &self.matrix_rep {
-            rep.old_factorize(&self.settings, &rep.stack())
-        } else {
-            None
+            self.background_solve =
+                Some(BackgroundSolve::spawn(rep.clone(), self.settings.clone()));
+        }
+    }
+    /// Solution of the background solve once it is done. A solve that could not start leaves
+    /// its error in `solve_error`.
+    pub fn finished_background_solve(&mut self) -> Option<Option<SolveReport>> {
+        let solution = self.background_solve.as_mut()?.try_finish()?;
+        self.background_solve = None;
+        let solution = match solution {
+            Ok(solution) => solution,
+            Err(err) => {
+                self.solve_error = Some(err);
+                return None;
+            }
+        };
+        // Kept with the settings that solved them, the other approach can start from them
+        if let Some(report) = &solution {
+            let name = self.settings.save_to.clone();
+            let frames = &report.frames;
+            if let Err(err) = save_panels(CaptureKind::Separable, name, frames, &self.settings) {
+                self.save_error = Some(err);
+            }
+        }
+        Some(solution)
+    }
+    pub fn old_factorization(&self) -> error::Result<Option<SolveReport>> {
+        match &self.matrix_rep {
+            Some(rep) => rep.old_factorize(&self.settings, &rep.stack()),
+            None => Ok(None),
         }
     }
 }
@@ -449,9 +367,16 @@ impl DrawUI for LFBuffers {
                 }
                 if ui.button("Save").clicked() {
                     if let Some(rep) = self.matrix_rep.as_ref() {
-                        rep.save(self.settings.save_to.clone());
+                        if let Err(err) =
+                            rep.save(self.settings.save_to.clone(), &self.capture_metadata)
+                        {
+                            self.save_error = Some(err);
+                        }
                     }
                 }
+                if let Some(solve) = &self.background_solve {
+                    solve.draw_ui(ui);
+                }
                 self.settings.draw_ui(ctx, Some(title), Some(ui));
             });
     }
@@ -0,0 +1,251 @@
+use faer::{unzip, zip, Mat};
+use serde::{Deserialize, Serialize};
+
+use crate::{mapping::IndexMapping, scalar::Scalar};
+
+/// Least squares fit of the panels in the log domain. The layers multiply, so
+/// `log l = sum_k M_k log x_k` is a linear system over the stacked columns `[M_1 | M_2 | ...]`.
+/// It is solved with CGLS, the box `floor <= x <= 1` is kept by an active set: pixels leaving
+/// it are fixed to their bound, and the others solved for again.
+///
+/// The problem is convex, so it is a quick baseline to compare the convergence of the
+/// multiplicative updates against, or a warm start for them with
+/// `Initialization::LogLeastSquares`. It fits `log l` rather than `l`, so dark rays weigh as much
+/// as bright ones.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LogLeastSquares {
+    /// Darkest pixel, samples are raised to it as `log 0` is unbounded
+    pub floor: f32,
+    /// CGLS iterations of every pass
+    pub iterations: usize,
+    /// A pass stops once the gradient fell by this factor
+    pub tolerance: f32,
+    /// Most CGLS solves of the active set
+    pub passes: usize,
+}
+
+impl Default for LogLeastSquares {
+    fn default() -> Self {
+        LogLeastSquares {
+            floor: 1e-3,
+            iterations: 200,
+            tolerance: 1e-6,
+            passes: 20,
+        }
+    }
+}
+
+/// `log x` of every layer, or a gradient over them
+type Stacked<T> = Vec<Mat<T>>;
+
+impl LogLeastSquares {
+    /// Every layer as a column vector in `[floor, 1]`, from the samples `l_vec` of the rays of
+    /// `layers`. Rays missing any layer can't be fitted and are left out, pixels no fitted ray
+    /// sees stay at 1.
+    pub fn solve<T: Scalar>(&self, layers: &[IndexMapping<T>], l_vec: &Mat<T>) -> Vec<Mat<T>> {
+        let lower = T::from_f32(self.floor).ln();
+        let seen = Self::seen(layers, l_vec.nrows());
+        let b = zip!(l_vec, &seen)
+            .map(|unzip!(l, seen)| *seen * (*l).clamp(T::from_f32(self.floor), T::ONE).ln());
+
+        let mut u: Stacked<T> = layers.iter().map(|x| Mat::zeros(x.ncols(), 1)).collect();
+        let mut free: Stacked<T> = layers
+            .iter()
+            .map(|x| Mat::from_fn(x.ncols(), 1, |_, _| T::ONE))
+            .collect();
+        for _ in 0..self.passes.max(1) {
+            self.cgls(layers, &seen, &free, &b, &mut u);
+
+            // Pixels leaving the box are fixed to their bound, fixed pixels the gradient pulls
+            // back into the box are freed again
+            let residual = zip!(&Self::apply(layers, &seen, &u), &b).map(|unzip!(a, b)| *a - *b);
+            let mut changed = false;
+            for ((u, free), layer) in u.iter_mut().zip(&mut free).zip(layers) {
+                let gradient = layer.scatter(&residual);
+                zip!(u, free, &gradient).for_each(|unzip!(u, free, g)| {
+                    if *free != T::ZERO {
+                        if *u > T::ZERO || *u < lower {
+                            *u = (*u).clamp(lower, T::ZERO);
+                            *free = T::ZERO;
+                            changed = true;
+                        }
+                    } else if (*u == T::ZERO && *g > T::ZERO) || (*u == lower && *g < T::ZERO) {
+                        *free = T::ONE;
+                        changed = true;
+                    }
+                });
+            }
+            if !changed {
+                break;
+            }
+        }
+        u.iter()
+            .map(|u| zip!(u).map(|unzip!(u)| (*u).clamp(lower, T::ZERO).exp()))
+            .collect()
+    }
+
+    /// `A u`, for the seen rays only
+    fn apply<T: Scalar>(layers: &[IndexMapping<T>], seen: &Mat<T>, u: &[Mat<T>]) -> Mat<T> {
+        let mut rays = Mat::zeros(seen.nrows(), 1);
+        for (layer, u) in layers.iter().zip(u) {
+            rays += layer.gather(u);
+        }
+        zip!(&mut rays, seen).for_each(|unzip!(r, s)| *r *= *s);
+        rays
+    }
+
+    /// 1 for the rays hitting every layer, 0 for the others
+    fn seen<T: Scalar>(layers: &[IndexMapping<T>], rays: usize) -> Mat<T> {
+        let mut seen = Mat::from_fn(rays, 1, |_, _| T::ONE);
+        for layer in layers {
+            let coverage = layer.gather(&Mat::from_fn(layer.ncols(), 1, |_, _| T::ONE));
+            zip!(&mut seen, &coverage).for_each(|unzip!(s, c)| {
+                if *c <= T::ZERO {
+                    *s = T::ZERO;
+                }
+            });
+        }
+        seen
+    }
+
+    /// CGLS on the free pixels, continuing from `u`
+    fn cgls<T: Scalar>(
+        &self,
+        layers: &[IndexMapping<T>],
+        seen: &Mat<T>,
+        free: &[Mat<T>],
+        b: &Mat<T>,
+        u: &mut [Mat<T>],
+    ) {
+        let apply = |u: &[Mat<T>]| Self::apply(layers, seen, u);
+        // A^T r, for the free pixels only
+        let adjoint = |r: &Mat<T>| -> Stacked<T> {
+            layers
+                .iter()
+                .zip(free)
+                .map(|(layer, free)| {
+                    let mut s = layer.scatter(r);
+                    zip!(&mut s, free).for_each(|unzip!(s, f)| *s *= *f);
+                    s
+                })
+                .collect()
+        };
+        let squared_norm = |x: &[Mat<T>]| x.iter().map(|x| x.squared_norm_l2()).sum::<T>();
+
+        let mut r = b - apply(u);
+        let mut s = adjoint(&r);
+        let mut p = s.clone();
+        let mut gamma = squared_norm(&s);
+        let stop = gamma * T::from_f32(self.tolerance).powi(2);
+        for _ in 0..self.iterations {
+            if gamma <= stop || gamma == T::ZERO {
+                break;
+            }
+            let q = apply(&p);
+            let curvature = q.squared_norm_l2();
+            if curvature == T::ZERO {
+                break;
+            }
+            let alpha = gamma / curvature;
+            for (u, p) in u.iter_mut().zip(&p) {
+                zip!(u, p).for_each(|unzip!(u, p)| *u += alpha * *p);
+            }
+            zip!(&mut r, &q).for_each(|unzip!(r, q)| *r -= alpha * *q);
+            s = adjoint(&r);
+            let next = squared_norm(&s);
+            let beta = next / gamma;
+            for (p, s) in p.iter_mut().zip(&s) {
+                zip!(p, s).for_each(|unzip!(p, s)| *p = *s + beta * *p);
+            }
+            gamma = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        initialization::Initialization, scalar, solver::SolverKind, synthetic::Synthetic, utils,
+        LFSettings, Lff,
+    };
+
+    /// Product of the panels along every ray
+    fn estimate<T: Scalar>(layers: &[IndexMapping<T>], panels: &[Mat<T>]) -> Mat<T> {
+        let mut estimate = Mat::from_fn(layers[0].nrows(), 1, |_, _| T::ONE);
+        for (layer, panel) in layers.iter().zip(panels) {
+            zip!(&mut estimate, &layer.gather(panel)).for_each(|unzip!(e, x)| *e *= *x);
+        }
+        estimate
+    }
+
+    #[test]
+    fn recovers_exact_products() {
+        let settings = LogLeastSquares::default();
+        for seed in 0..3 {
+            let problem = Synthetic::<f64>::new(seed, &[4, 3], 3, 20, 0.2);
+            let capture = &problem.capture;
+            let panels = settings.solve(&capture.layers, &capture.l_vec);
+            for panel in &panels {
+                assert!(panel
+                    .col(0)
+                    .iter()
+                    .all(|x| (settings.floor as f64..=1.0).contains(x)));
+            }
+            let residual = (estimate(&capture.layers, &panels) - &capture.l_vec).norm_l2();
+            assert!(
+                residual < 1e-6 * capture.l_vec.norm_l2(),
+                "residual {residual} on problem {seed}"
+            );
+        }
+    }
+
+    #[test]
+    fn stays_in_the_box() {
+        // Samples above 1 and below the floor have no solution inside the box
+        let problem = Synthetic::<f32>::new(7, &[4, 3], 3, 20, 0.0).scaled(1.5);
+        let settings = LogLeastSquares::default();
+        let capture = &problem.capture;
+        for panel in settings.solve(&capture.layers, &capture.l_vec) {
+            assert!(panel
+                .col(0)
+                .iter()
+                .all(|x| (settings.floor..=1.0).contains(x)));
+        }
+    }
+
+    #[test]
+    fn warm_starts_multiplicative_updates() {
+        let problem = Synthetic::<f64>::new(8, &[4, 3], 3, 20, 0.2);
+        let settings = LFSettings {
+            iter_count: 5,
+            solver: SolverKind::Multiplicative,
+            filter: false,
+            debug_prints: false,
+            ..Default::default()
+        };
+        let (_, cold) = problem.solve(&settings);
+        let (_, warm) = problem.solve(&LFSettings {
+            initialization: Initialization::LogLeastSquares,
+            ..settings.clone()
+        });
+        assert!(warm.last().unwrap() < cold.last().unwrap());
+
+        // Without iterations the solve is the log domain solution
+        let capture = &problem.capture;
+        let settings = LFSettings {
+            iter_count: 0,
+            initialization: Initialization::LogLeastSquares,
+            ..settings
+        };
+        let report = capture.factorize(&settings, &()).unwrap().unwrap();
+        let expected = settings
+            .log_least_squares
+            .solve(&capture.layers, &capture.l_vec);
+        let layers = report.layers().iter().zip(&expected);
+        for ((image, expected), size) in layers.zip(&capture.layer_sizes) {
+            let expected = utils::vector_to_image(&scalar::cast(expected), size.0, size.1);
+            assert_eq!(image, &expected);
+        }
+    }
+}
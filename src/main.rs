@@ -1,6 +1,15 @@
 #[macro_use]
 use image::DynamicImage;
 use light_field_test::app::*;
+use light_field_test::capture::{CaptureKind, CaptureMetadata};
+use light_field_test::cpu_sampler::{CpuSampler, MAX_SAMPLES};
+use light_field_test::error;
+use light_field_test::initialization::{save_panels, Initialization};
+use light_field_test::mapping::{MappingMode, SamplePattern};
+use light_field_test::parallelism::Parallelism;
+use light_field_test::report::{SolveReport, StopCriteria};
+use light_field_test::scalar::{Precision, Scalar};
+use light_field_test::solver::SolverKind;
 use light_field_test::FileWatcher;
 use light_field_test::{LFMatrices, LFSettings, Lff, StereoMatrix};
 use notify::Watcher;
@@ -19,6 +28,8 @@ enum HeadlessType {
     SepOld,
     Stereo,
     Load,
+    /// Sample a scene capture on the CPU and save both matrix captures
+    CpuSample,
 }
 impl std::fmt::Display for HeadlessType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -34,25 +45,123 @@ struct Commands {
 
     #[arg(short, long)]
     type_head: Option<HeadlessType>,
+
+    /// Scene capture (in ./saves/scene_capture/) to sample with cpu-sample
+    #[arg(short, long, default_value = "Tucan")]
+    capture: String,
+
+    /// Update rule used by the headless solvers
+    #[arg(short, long, default_value_t = SolverKind::Multiplicative)]
+    solver: SolverKind,
+
+    /// Panel pairs to solve for, shown one after the other
+    #[arg(short, long, default_value_t = 1)]
+    frames: usize,
+
+    /// How cpu-sample spreads every ray over the panel pixels
+    #[arg(short, long, default_value_t = MappingMode::Nearest)]
+    mapping: MappingMode,
+
+    /// Rays per target pixel along each axis for cpu-sample
+    #[arg(long, default_value_t = 1)]
+    supersampling: u32,
+
+    /// Where the rays of a supersampled pixel start
+    #[arg(long, default_value_t = SamplePattern::Stratified)]
+    pattern: SamplePattern,
+
+    /// Threads of the headless solvers: single, available or a count
+    #[arg(long, default_value_t = Parallelism::Available)]
+    threads: Parallelism,
+
+    /// Iterations between checkpoints of the headless solvers, 0 writes none
+    #[arg(long, default_value_t = 0)]
+    checkpoint_every: usize,
+
+    /// Checkpoint (in ./saves/checkpoints/) the headless solvers write and resume
+    #[arg(long, default_value = "headless")]
+    checkpoint: String,
+
+    /// Continue the headless solve from its checkpoint
+    #[arg(long, default_value_t = false)]
+    resume: bool,
+
+    /// Precision of the headless sep and stereo solves, f64 for reference solves
+    #[arg(long, default_value_t = Precision::F32)]
+    precision: Precision,
+
+    /// Starting panels of the headless solvers: constant, random:<seed>, square-root,
+    /// log-least-squares, saved:<sep|stereo>:<name> or other-approach
+    #[arg(long, default_value_t = Initialization::Constant)]
+    initialization: Initialization,
+
+    /// Most iterations of the headless solvers
+    #[arg(long, default_value_t = 5)]
+    iterations: usize,
+
+    /// Stop once an iteration changes the residual by less than this fraction, 0 never stops
+    #[arg(long, default_value_t = 0.0)]
+    tolerance: f32,
+
+    /// Iterations in a row under the tolerance before stopping
+    #[arg(long, default_value_t = 1)]
+    patience: usize,
+
+    /// Seconds the headless solvers may take, 0 has no budget
+    #[arg(long, default_value_t = 0.0)]
+    time_budget: f32,
+
+    /// MiB the headless sep solver may keep per view projections in, 0 caches none
+    #[arg(long, default_value_t = 1024.0)]
+    cache_budget: f32,
 }
 
 fn main() {
     let args = Commands::parse();
 
     if args.headless {
-        let mut diagonal = LFMatrices::load("2000.ro".to_string());
-
-        let stereo = StereoMatrix::load("2000.ro".to_string());
         let settings = LFSettings {
+            iter_count: args.iterations,
+            stopping: StopCriteria {
+                relative_tolerance: args.tolerance,
+                patience: args.patience,
+                time_budget: args.time_budget,
+            },
             debug_prints: false,
+            solver: args.solver,
+            frames: args.frames,
+            parallelism: args.threads,
+            checkpoint_every: args.checkpoint_every,
+            cache_budget: args.cache_budget,
+            save_to: args.checkpoint.clone(),
+            initialization: args.initialization.clone(),
             ..Default::default()
         };
-        if let Some(bench) = args.type_head {
+        if let Some(bench) = &args.type_head {
             match bench {
-                HeadlessType::Sep => bench_sep(settings, &mut diagonal),
-                HeadlessType::SepOld => bench_old(settings, &mut diagonal),
-                HeadlessType::Stereo => bench_stereo(settings, &stereo),
-                HeadlessType::Load => (),
+                HeadlessType::Sep => match LFMatrices::load("2000.ro".to_string()) {
+                    Ok(mut diagonal) => {
+                        bench_sep(settings, &mut diagonal, args.resume, args.precision)
+                    }
+                    Err(err) => println!("Could not load capture: {err}"),
+                },
+                HeadlessType::SepOld => match LFMatrices::load("2000.ro".to_string()) {
+                    Ok(mut diagonal) => bench_old(settings, &mut diagonal),
+                    Err(err) => println!("Could not load capture: {err}"),
+                },
+                HeadlessType::Stereo => match StereoMatrix::load("2000.ro".to_string()) {
+                    Ok(stereo) => bench_stereo(settings, &stereo, args.resume, args.precision),
+                    Err(err) => println!("Could not load capture: {err}"),
+                },
+                HeadlessType::Load => {
+                    if let Err(err) = LFMatrices::load("2000.ro".to_string()) {
+                        println!("Could not load capture: {err}");
+                    }
+                    if let Err(err) = StereoMatrix::load("2000.ro".to_string()) {
+                        println!("Could not load capture: {err}");
+                    }
+                }
+                HeadlessType::CpuSample => cpu_sample(&args),
             }
         } else {
             #[cfg(not(target_arch = "wasm32"))]
@@ -67,19 +176,103 @@ fn main() {
         }
     }
 }
-fn bench_sep(settings: LFSettings, diagonal: &mut LFMatrices) {
+fn bench_sep(settings: LFSettings, diagonal: &mut LFMatrices, resume: bool, precision: Precision) {
     diagonal.c_t = DynamicImage::new_rgb8(diagonal.target_size.0, diagonal.target_size.1);
-    diagonal.factorize(&settings);
+    match precision {
+        Precision::F32 => solve_sep(settings, diagonal, resume),
+        Precision::F64 => solve_sep(settings, &diagonal.cast::<f64>(), resume),
+    }
+}
+fn solve_sep<T: Scalar>(settings: LFSettings, diagonal: &LFMatrices<T>, resume: bool) {
+    let solution = if resume {
+        diagonal.resume(settings.save_to.clone(), &settings, &())
+    } else {
+        diagonal.factorize(&settings, &())
+    };
+    save_solution(CaptureKind::Separable, &settings, solution);
 }
 fn bench_old(settings: LFSettings, diagonal: &mut LFMatrices) {
     diagonal.c_t = DynamicImage::new_rgb8(diagonal.target_size.0, diagonal.target_size.1);
 
     let stacked_matrices = diagonal.stack();
 
-    diagonal.old_factorize(&settings, &stacked_matrices);
+    match diagonal.old_factorize(&settings, &stacked_matrices) {
+        Ok(Some(report)) => println!("{report}"),
+        Ok(None) => {}
+        Err(err) => println!("Could not solve: {err}"),
+    }
+}
+fn bench_stereo(settings: LFSettings, stereo: &StereoMatrix, resume: bool, precision: Precision) {
+    match precision {
+        Precision::F32 => solve_stereo(settings, stereo, resume),
+        Precision::F64 => solve_stereo(settings, &stereo.cast::<f64>(), resume),
+    }
+}
+fn solve_stereo<T: Scalar>(settings: LFSettings, stereo: &StereoMatrix<T>, resume: bool) {
+    let solution = if resume {
+        stereo.resume(settings.save_to.clone(), &settings, &())
+    } else {
+        stereo.factorize(&settings, &())
+    };
+    save_solution(CaptureKind::Stereo, &settings, solution);
+}
+/// Panels of a headless solve with its settings, the other approach can start from them
+fn save_solution(
+    kind: CaptureKind,
+    settings: &LFSettings,
+    solution: error::Result<Option<SolveReport>>,
+) {
+    match solution {
+        Ok(Some(report)) => {
+            println!("{report}");
+            if let Some(err) = &report.checkpoint_error {
+                println!("Could not write checkpoint: {err}");
+            }
+            if let Err(err) = save_panels(kind, settings.save_to.clone(), &report.frames, settings)
+            {
+                println!("Could not save panels: {err}");
+            }
+        }
+        Ok(None) => {}
+        Err(err) => println!("Could not solve: {err}"),
+    }
 }
-fn bench_stereo(settings: LFSettings, stereo: &StereoMatrix) {
-    stereo.factorize(&settings);
+fn cpu_sample(args: &Commands) {
+    let capture = &args.capture;
+    if !(1..=MAX_SAMPLES).contains(&args.supersampling) {
+        println!("Supersampling takes 1 to {MAX_SAMPLES} rays per axis");
+        return;
+    }
+    let (sampler, target) = match CpuSampler::from_capture(capture) {
+        Ok((sampler, target)) => (
+            sampler
+                .with_mapping_mode(args.mapping)
+                .with_supersampling(args.supersampling, args.pattern),
+            target,
+        ),
+        Err(err) => {
+            println!("Could not load scene capture {capture}: {err}");
+            return;
+        }
+    };
+    println!(
+        "Sampling {capture} with {} view points, {} mappings and {}x{} rays per pixel",
+        sampler.number_of_view_points(),
+        args.mapping,
+        args.supersampling,
+        args.supersampling
+    );
+    let saved = CaptureMetadata::from_scene_capture(capture).and_then(|metadata| {
+        sampler
+            .sample_separable(&target)
+            .save(capture.to_string(), &metadata)?;
+        sampler
+            .sample_stereo(&target)
+            .save(capture.to_string(), &metadata)
+    });
+    if let Err(err) = saved {
+        println!("Could not save matrix capture: {err}");
+    }
 }
 
 async fn execute() {
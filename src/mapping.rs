@@ -0,0 +1,559 @@
+use faer::{
+    sparse::{SparseColMat, SparseColMatRef, Triplet},
+    Mat,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::scalar::Scalar;
+
+/// Entry of a ray that does not hit the panel
+pub const NO_HIT: u32 = u32::MAX;
+
+/// How the samplers spread a ray over the pixels around the point where it crosses a panel
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum MappingMode {
+    /// The pixel the ray crosses, with a weight of 1
+    #[default]
+    Nearest,
+    /// The 2x2 pixels around the crossing, weighted by distance to their centers
+    Bilinear,
+    /// The 2x2 pixels around the crossing, weighted by how much of the ray footprint they cover.
+    /// The footprint is the target pixel seen through the panel, at most one panel pixel wide.
+    AreaCoverage,
+}
+
+impl MappingMode {
+    pub const ALL: [MappingMode; 3] = [
+        MappingMode::Nearest,
+        MappingMode::Bilinear,
+        MappingMode::AreaCoverage,
+    ];
+
+    /// Pixels along one axis with their weights, `position` in pixels from the panel border.
+    /// `footprint` is the width of the ray in pixels, only used for `AreaCoverage`.
+    /// Unused taps have a weight of 0.
+    pub fn taps(self, position: f32, pixels: u32, footprint: f32) -> [(u32, f32); 2] {
+        let width = match self {
+            // Saturating cast, like u32() in WGSL
+            MappingMode::Nearest => return [(position as u32, 1.0), (NO_HIT, 0.0)],
+            MappingMode::Bilinear => 1.0,
+            MappingMode::AreaCoverage if footprint.is_finite() => footprint.clamp(1e-3, 1.0),
+            MappingMode::AreaCoverage => 1.0,
+        };
+        // The footprint [start, start + width] overlaps pixel `first` and maybe the next one
+        let start = position - width / 2.0;
+        let first = start.floor();
+        let next = ((start + width - first - 1.0) / width).clamp(0.0, 1.0);
+
+        let last = pixels.saturating_sub(1) as f32;
+        let (first, second) = (
+            first.clamp(0.0, last) as u32,
+            (first + 1.0).clamp(0.0, last) as u32,
+        );
+        if first == second || next == 0.0 {
+            [(first, 1.0), (NO_HIT, 0.0)]
+        } else if next == 1.0 {
+            [(second, 1.0), (NO_HIT, 0.0)]
+        } else {
+            [(first, 1.0 - next), (second, next)]
+        }
+    }
+}
+
+impl std::fmt::Display for MappingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// Where the rays of a supersampled target pixel start, the pixel is split into a grid of cells
+/// with one ray each
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum SamplePattern {
+    /// Center of every cell
+    #[default]
+    Stratified,
+    /// Random point in every cell, the same for every sampling of a scene
+    Jittered,
+}
+
+impl SamplePattern {
+    pub const ALL: [SamplePattern; 2] = [SamplePattern::Stratified, SamplePattern::Jittered];
+
+    /// Position of the ray in `cell` out of `samples` along one axis of the pixel, from 0 to 1.
+    /// `seed` picks the jitter.
+    pub fn offset(self, cell: u32, samples: u32, seed: u64) -> f32 {
+        let within = match self {
+            SamplePattern::Stratified => 0.5,
+            SamplePattern::Jittered => {
+                // splitmix64, 24 random bits are all an f32 can hold
+                let mut x = seed.wrapping_add(0x9e3779b97f4a7c15);
+                x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+                x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+                x ^= x >> 31;
+                (x >> 40) as f32 / (1u64 << 24) as f32
+            }
+        };
+        (cell as f32 + within) / samples as f32
+    }
+}
+
+impl std::fmt::Display for SamplePattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// Ray to pixel mapping where every ray hits at most `taps` pixels.
+/// This is the structure of every matrix the samplers build, so instead of a sparse matrix only
+/// the columns hit by each row are stored. `M x` becomes a gather and `M^T y` a scatter.
+/// Nearest neighbour mappings have a single tap and no weights, every hit counts as 1.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IndexMapping<T: Scalar = f32> {
+    /// Columns hit by every row, `taps` entries per row, `NO_HIT` for unused entries
+    indices: Vec<u32>,
+    /// Weight of every entry of `indices`, empty when all of them are 1
+    weights: Vec<T>,
+    taps: usize,
+    ncols: usize,
+}
+
+impl<T: Scalar> IndexMapping<T> {
+    /// Mapping from the pixel index of every ray, as the samplers write them.
+    /// Indices outside of the `ncols` pixels are rays that miss.
+    pub fn from_hits(hits: Vec<u32>, ncols: usize) -> Self {
+        let mut indices = hits;
+        for index in indices.iter_mut() {
+            if *index as usize >= ncols {
+                *index = NO_HIT;
+            }
+        }
+        IndexMapping {
+            indices,
+            weights: Vec::new(),
+            taps: 1,
+            ncols,
+        }
+    }
+
+    /// Mapping from `(pixel, weight)` entries, `taps` per ray. Pixels outside of the `ncols`
+    /// and entries without weight are dropped. Falls back to a single tap without weights
+    /// when every ray hits at most one pixel with a weight of 1.
+    pub fn from_weighted_hits(hits: &[(u32, T)], taps: usize, ncols: usize) -> Self {
+        assert!(taps > 0 && hits.len().is_multiple_of(taps));
+        let entries = hits.iter().map(|&(index, weight)| {
+            if index as usize >= ncols || weight == T::ZERO {
+                (NO_HIT, T::ZERO)
+            } else {
+                (index, weight)
+            }
+        });
+        let (indices, weights): (Vec<u32>, Vec<T>) = entries.unzip();
+
+        let nearest = indices
+            .chunks(taps)
+            .zip(weights.chunks(taps))
+            .all(|(row, weights)| {
+                let mut hits = row
+                    .iter()
+                    .zip(weights)
+                    .filter(|(index, _)| **index != NO_HIT);
+                hits.next().is_none_or(|(_, weight)| *weight == T::ONE) && hits.next().is_none()
+            });
+        if nearest {
+            let hits = indices
+                .chunks(taps)
+                .map(|row| {
+                    row.iter()
+                        .copied()
+                        .find(|index| *index != NO_HIT)
+                        .unwrap_or(NO_HIT)
+                })
+                .collect();
+            return Self::from_hits(hits, ncols);
+        }
+        IndexMapping {
+            indices,
+            weights,
+            taps,
+            ncols,
+        }
+    }
+
+    /// Mapping with a 1 at every `(row, col)`, None if a row has more than one entry or an
+    /// entry other than 1
+    pub fn from_triplets(
+        nrows: usize,
+        ncols: usize,
+        triplets: impl IntoIterator<Item = (usize, usize, T)>,
+    ) -> Option<Self> {
+        let mut indices = vec![NO_HIT; nrows];
+        for (row, col, value) in triplets {
+            if value != T::ONE || col >= ncols || indices.get(row) != Some(&NO_HIT) {
+                return None;
+            }
+            indices[row] = col as u32;
+        }
+        Some(Self::from_hits(indices, ncols))
+    }
+
+    /// See `from_triplets`
+    pub fn from_sparse(matrix: SparseColMatRef<'_, u32, T>) -> Option<Self> {
+        Self::from_triplets(
+            matrix.nrows(),
+            matrix.ncols(),
+            matrix
+                .triplet_iter()
+                .map(|triplet| (triplet.row, triplet.col, *triplet.val)),
+        )
+    }
+
+    pub fn to_sparse(&self) -> SparseColMat<u32, T> {
+        let triplets: Vec<Triplet<u32, u32, T>> = self
+            .hits()
+            .map(|(row, col, weight)| Triplet::new(row as u32, col as u32, weight))
+            .collect();
+        SparseColMat::try_new_from_triplets(self.nrows(), self.ncols, &triplets).unwrap()
+    }
+
+    /// Rows of `mappings` one after the other, the mapping of every view point at once.
+    /// Rows are padded to the most taps of any mapping.
+    pub fn stack(mappings: &[IndexMapping<T>]) -> Self {
+        let ncols = mappings.first().map_or(0, |x| x.ncols);
+        let taps = mappings.iter().map(|x| x.taps).max().unwrap_or(1);
+        let weighted = mappings.iter().any(|x| !x.weights.is_empty());
+        let (mut indices, mut weights) = (Vec::new(), Vec::new());
+        for mapping in mappings {
+            for row in 0..mapping.nrows() {
+                let entries = row * mapping.taps..(row + 1) * mapping.taps;
+                indices.extend(entries.clone().map(|entry| mapping.indices[entry]));
+                indices.extend((mapping.taps..taps).map(|_| NO_HIT));
+                if weighted {
+                    weights.extend(entries.map(|entry| mapping.weight(entry)));
+                    weights.extend((mapping.taps..taps).map(|_| T::ZERO));
+                }
+            }
+        }
+        IndexMapping {
+            indices,
+            weights,
+            taps,
+            ncols,
+        }
+    }
+
+    /// `M_y ⊗ M_x` as a mapping of single rays, the row form of one view of the separable
+    /// approach. Ray `x + y * m_x.nrows()` crosses pixel `col + row * m_x.ncols()` of the panel
+    /// vectorized row by row, like the rays and panels of the stereo approach.
+    pub fn kronecker(m_y: &IndexMapping<T>, m_x: &IndexMapping<T>) -> Self {
+        let taps = m_y.taps * m_x.taps;
+        let weighted = !m_y.weights.is_empty() || !m_x.weights.is_empty();
+        let (mut indices, mut weights) = (Vec::new(), Vec::new());
+        for ray_y in 0..m_y.nrows() {
+            for ray_x in 0..m_x.nrows() {
+                for entry_y in ray_y * m_y.taps..(ray_y + 1) * m_y.taps {
+                    for entry_x in ray_x * m_x.taps..(ray_x + 1) * m_x.taps {
+                        let (row, col) = (m_y.indices[entry_y], m_x.indices[entry_x]);
+                        let hit = row != NO_HIT && col != NO_HIT;
+                        indices.push(if hit {
+                            col + row * m_x.ncols as u32
+                        } else {
+                            NO_HIT
+                        });
+                        if weighted {
+                            weights.push(if hit {
+                                m_y.weight(entry_y) * m_x.weight(entry_x)
+                            } else {
+                                T::ZERO
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        IndexMapping {
+            indices,
+            weights,
+            taps,
+            ncols: m_y.ncols * m_x.ncols,
+        }
+    }
+
+    /// The same mapping in another precision
+    pub fn cast<U: Scalar>(&self) -> IndexMapping<U> {
+        IndexMapping {
+            indices: self.indices.clone(),
+            weights: self
+                .weights
+                .iter()
+                .map(|x| U::from_f64(x.to_f64()))
+                .collect(),
+            taps: self.taps,
+            ncols: self.ncols,
+        }
+    }
+
+    pub fn nrows(&self) -> usize {
+        self.indices.len() / self.taps
+    }
+    pub fn ncols(&self) -> usize {
+        self.ncols
+    }
+    pub fn shape(&self) -> (usize, usize) {
+        (self.nrows(), self.ncols)
+    }
+    /// Most pixels a single ray hits
+    pub fn taps(&self) -> usize {
+        self.taps
+    }
+
+    fn weight(&self, entry: usize) -> T {
+        self.weights.get(entry).copied().unwrap_or(T::ONE)
+    }
+
+    /// Pixel with the largest weight of a ray, the one a nearest neighbour mapping would hit
+    pub fn hit(&self, row: usize) -> Option<usize> {
+        (row * self.taps..(row + 1) * self.taps)
+            .filter(|entry| self.indices[*entry] != NO_HIT)
+            .max_by(|a, b| self.weight(*a).total_cmp(&self.weight(*b)))
+            .map(|entry| self.indices[entry] as usize)
+    }
+
+    /// `(row, col, weight)` of every pixel hit by a ray
+    pub fn hits(&self) -> impl Iterator<Item = (usize, usize, T)> + '_ {
+        let taps = self.taps;
+        self.indices
+            .iter()
+            .enumerate()
+            .filter(|(_, index)| **index != NO_HIT)
+            .map(move |(entry, index)| (entry / taps, *index as usize, self.weight(entry)))
+    }
+
+    /// Columns hit by at least one ray
+    pub fn active_columns(&self) -> Vec<bool> {
+        let mut active = vec![false; self.ncols];
+        for (_, col, _) in self.hits() {
+            active[col] = true;
+        }
+        active
+    }
+
+    /// `M x`
+    pub fn gather(&self, x: &Mat<T>) -> Mat<T> {
+        let mut out = Mat::zeros(self.nrows(), x.ncols());
+        for col in 0..x.ncols() {
+            let (source, target) = (x.col_as_slice(col), out.col_as_slice_mut(col));
+            for (row, pixel, weight) in self.hits() {
+                target[row] += weight * source[pixel];
+            }
+        }
+        out
+    }
+
+    /// `M^T y`
+    pub fn scatter(&self, y: &Mat<T>) -> Mat<T> {
+        let mut out = Mat::zeros(self.ncols, y.ncols());
+        for col in 0..y.ncols() {
+            let (source, target) = (y.col_as_slice(col), out.col_as_slice_mut(col));
+            for (row, pixel, weight) in self.hits() {
+                target[pixel] += weight * source[row];
+            }
+        }
+        out
+    }
+}
+
+/// `M_y c M_x^T`, the light field a panel `c` produces for one view of the separable approach
+pub fn project<T: Scalar>(m_y: &IndexMapping<T>, c: &Mat<T>, m_x: &IndexMapping<T>) -> Mat<T> {
+    let rows: Vec<(usize, usize, T)> = m_y.hits().collect();
+    let mut out = Mat::zeros(m_y.nrows(), m_x.nrows());
+    for (ray_x, column, weight_x) in m_x.hits() {
+        let (source, target) = (c.col_as_slice(column), out.col_as_slice_mut(ray_x));
+        for &(ray_y, row, weight_y) in &rows {
+            target[ray_y] += weight_x * weight_y * source[row];
+        }
+    }
+    out
+}
+
+/// Adds `M_y^T rays M_x` to `out`, sums the rays of one view back onto the panel pixels
+pub fn back_project_into<T: Scalar>(
+    m_y: &IndexMapping<T>,
+    rays: &Mat<T>,
+    m_x: &IndexMapping<T>,
+    out: &mut Mat<T>,
+) {
+    let rows: Vec<(usize, usize, T)> = m_y.hits().collect();
+    for (ray_x, column, weight_x) in m_x.hits() {
+        let (source, target) = (rays.col_as_slice(ray_x), out.col_as_slice_mut(column));
+        for &(ray_y, row, weight_y) in &rows {
+            target[row] += weight_x * weight_y * source[ray_y];
+        }
+    }
+}
+
+/// `m` as a single column, row by row. The layout of the rays and panels of the stereo approach.
+pub fn vectorize<T: Scalar>(m: &Mat<T>) -> Mat<T> {
+    let cols = m.ncols();
+    Mat::from_fn(m.nrows() * cols, 1, |index, _| {
+        m[(index / cols, index % cols)]
+    })
+}
+
+/// Inverse of `vectorize`, a column of `rows * cols` entries as a `rows` by `cols` panel
+pub fn unvectorize<T: Scalar>(v: &Mat<T>, rows: usize, cols: usize) -> Mat<T> {
+    assert_eq!(v.nrows(), rows * cols);
+    Mat::from_fn(rows, cols, |row, col| v[(col + row * cols, 0)])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Every fourth ray misses
+    fn mapping(nrows: usize, ncols: usize, seed: usize) -> IndexMapping {
+        let hits = (0..nrows)
+            .map(|row| {
+                if row % 4 == 3 {
+                    ncols as u32
+                } else {
+                    ((row * 7 + seed) % ncols) as u32
+                }
+            })
+            .collect();
+        IndexMapping::from_hits(hits, ncols)
+    }
+
+    fn assert_close(a: &Mat<f32>, b: &Mat<f32>) {
+        assert_eq!(a.shape(), b.shape());
+        assert!((a - b).norm_max() < 1e-5);
+    }
+
+    #[test]
+    fn kernels_match_sparse_products() {
+        let m = mapping(20, 6, 1);
+        let sparse = m.to_sparse();
+        assert_eq!(IndexMapping::from_sparse(sparse.as_ref()), Some(m.clone()));
+
+        let x = Mat::from_fn(6, 2, |row, col| (row * 3 + col) as f32 / 10.0);
+        assert_close(&m.gather(&x), &(&sparse * &x));
+        let y = Mat::from_fn(20, 2, |row, col| (row + col * 5) as f32 / 7.0);
+        assert_close(&m.scatter(&y), &(sparse.transpose() * &y));
+
+        let m_y = mapping(12, 5, 2);
+        let m_x = mapping(9, 4, 3);
+        let (sparse_y, sparse_x) = (m_y.to_sparse(), m_x.to_sparse());
+        let c = Mat::from_fn(5, 4, |row, col| (row * 4 + col) as f32 / 20.0);
+        assert_close(
+            &project(&m_y, &c, &m_x),
+            &(&sparse_y * &c * sparse_x.transpose()),
+        );
+        let rays = Mat::from_fn(12, 9, |row, col| (row + col * 2) as f32 / 30.0);
+        let mut out = Mat::zeros(5, 4);
+        back_project_into(&m_y, &rays, &m_x, &mut out);
+        assert_close(&out, &(sparse_y.transpose() * &rays * &sparse_x));
+    }
+
+    /// Two taps per ray, the second one missing on every third ray
+    fn weighted_mapping(nrows: usize, ncols: usize, seed: usize) -> IndexMapping {
+        let hits: Vec<(u32, f32)> = (0..nrows)
+            .flat_map(|row| {
+                let first = ((row * 5 + seed) % ncols) as u32;
+                let weight = (row % 4 + 1) as f32 / 5.0;
+                let second = if row % 3 == 0 {
+                    NO_HIT
+                } else {
+                    (first + 1) % ncols as u32
+                };
+                [(first, weight), (second, 1.0 - weight)]
+            })
+            .collect();
+        IndexMapping::from_weighted_hits(&hits, 2, ncols)
+    }
+
+    #[test]
+    fn weighted_kernels_match_sparse_products() {
+        let m = weighted_mapping(20, 6, 1);
+        assert_eq!(m.taps(), 2);
+        let sparse = m.to_sparse();
+        let x = Mat::from_fn(6, 2, |row, col| (row * 3 + col) as f32 / 10.0);
+        assert_close(&m.gather(&x), &(&sparse * &x));
+        let y = Mat::from_fn(20, 2, |row, col| (row + col * 5) as f32 / 7.0);
+        assert_close(&m.scatter(&y), &(sparse.transpose() * &y));
+
+        let m_y = weighted_mapping(12, 5, 2);
+        let m_x = mapping(9, 4, 3);
+        let (sparse_y, sparse_x) = (m_y.to_sparse(), m_x.to_sparse());
+        let c = Mat::from_fn(5, 4, |row, col| (row * 4 + col) as f32 / 20.0);
+        assert_close(
+            &project(&m_y, &c, &m_x),
+            &(&sparse_y * &c * sparse_x.transpose()),
+        );
+        let rays = Mat::from_fn(12, 9, |row, col| (row + col * 2) as f32 / 30.0);
+        let mut out = Mat::zeros(5, 4);
+        back_project_into(&m_y, &rays, &m_x, &mut out);
+        assert_close(&out, &(sparse_y.transpose() * &rays * &sparse_x));
+
+        let stacked = IndexMapping::stack(&[m.clone(), mapping(20, 6, 2)]);
+        assert_eq!(stacked.nrows(), 40);
+        assert_close(&stacked.gather(&x).subrows(0, 20).to_owned(), &m.gather(&x));
+    }
+
+    #[test]
+    fn kronecker_rows_match_separable_projection() {
+        for m_y in [mapping(12, 5, 2), weighted_mapping(12, 5, 2)] {
+            let m_x = weighted_mapping(9, 4, 3);
+            let rows = IndexMapping::kronecker(&m_y, &m_x);
+            assert_eq!(rows.shape(), (12 * 9, 5 * 4));
+
+            let c = Mat::from_fn(5, 4, |row, col| (row * 4 + col) as f32 / 20.0);
+            assert_close(
+                &rows.gather(&vectorize(&c)),
+                &vectorize(&project(&m_y, &c, &m_x)),
+            );
+            let rays = Mat::from_fn(12, 9, |row, col| (row + col * 2) as f32 / 30.0);
+            let mut out = Mat::zeros(5, 4);
+            back_project_into(&m_y, &rays, &m_x, &mut out);
+            assert_close(&rows.scatter(&vectorize(&rays)), &vectorize(&out));
+        }
+    }
+
+    #[test]
+    fn taps_split_the_ray_between_pixel_centers() {
+        let nearest = MappingMode::Nearest.taps(2.7, 4, 0.3);
+        assert_eq!(nearest, [(2, 1.0), (NO_HIT, 0.0)]);
+        // Center of pixel 2, 20% of the way to the center of pixel 3
+        let [(first, a), (second, b)] = MappingMode::Bilinear.taps(2.7, 4, 0.3);
+        assert_eq!((first, second), (2, 3));
+        assert!((a - 0.8).abs() < 1e-5 && (b - 0.2).abs() < 1e-5);
+        // A footprint of [2.55, 2.85] stays inside pixel 2, one pixel wide matches bilinear
+        assert_eq!(MappingMode::AreaCoverage.taps(2.7, 4, 0.3), nearest);
+        assert_eq!(
+            MappingMode::AreaCoverage.taps(2.7, 4, 1.0),
+            MappingMode::Bilinear.taps(2.7, 4, 0.3)
+        );
+        // Clamped to the border pixels
+        assert_eq!(
+            MappingMode::Bilinear.taps(3.9, 4, 1.0),
+            [(3, 1.0), (NO_HIT, 0.0)]
+        );
+
+        // Nearest taps give back the unweighted mapping
+        let hits: Vec<(u32, f32)> = (0..8)
+            .flat_map(|row| MappingMode::Nearest.taps(row as f32 * 0.6, 4, 1.0))
+            .collect();
+        let nearest = IndexMapping::from_weighted_hits(&hits, 2, 4);
+        assert_eq!(nearest.taps(), 1);
+        assert_eq!(nearest.hit(7), None);
+        assert_eq!(nearest.hit(5), Some(3));
+    }
+
+    #[test]
+    fn only_one_hit_per_ray() {
+        assert!(IndexMapping::from_triplets(2, 3, [(0, 1, 1.0), (0, 2, 1.0)]).is_none());
+        assert!(IndexMapping::from_triplets(2, 3, [(0, 1, 0.5)]).is_none());
+        let m = IndexMapping::from_triplets(2, 3, [(1, 2, 1.0)]).unwrap();
+        assert_eq!(m.hit(0), None);
+        assert_eq!(m.hit(1), Some(2));
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::VecDeque;
+
+use cgmath::{Vector2, Vector3, Vector4};
+use image::{DynamicImage, ImageBuffer, Rgba, RgbaImage};
+
+use crate::{
+    camera::Camera,
+    error::Result,
+    raytracer::RayTraceInfo,
+    save::Save,
+    scene::{Scene, ScenePanel, Target},
+    shape::{Quad, VWPanel},
+};
+
+/// CPU version of the `panels_use_texture` path of `simple.wgsl`.
+/// Renders what an observer sees through every attenuating panel, for any camera.
+pub struct ObserverRenderer {
+    target: Quad,
+    target_image: RgbaImage,
+    world_color: Vector4<f32>,
+    /// Closest panel to the sampling camera first, one per layer
+    panels: Vec<VWPanel>,
+    panel_images: Vec<RgbaImage>,
+}
+
+impl ObserverRenderer {
+    /// `panel_images` are in solver order, closest layer first, so the panels are sorted relative
+    /// to the camera the light field was sampled from
+    pub fn new(
+        world: &Target,
+        panels: &[ScenePanel],
+        sampling_camera: &Camera,
+        panel_images: &[DynamicImage],
+        target_image: &DynamicImage,
+    ) -> Self {
+        Self::from_parts(
+            world.placed_quad(),
+            world.world_color,
+            Scene::placed_panels(panels, sampling_camera),
+            panel_images,
+            target_image,
+        )
+    }
+
+    pub fn from_scene(
+        scene: &Scene,
+        sampling_camera: &Camera,
+        panel_images: &[DynamicImage],
+        target_image: &DynamicImage,
+    ) -> Self {
+        Self::new(
+            &scene.world,
+            &scene.panels,
+            sampling_camera,
+            panel_images,
+            target_image,
+        )
+    }
+
+    /// Placed geometry, panels have to be in the same order as `panel_images`
+    pub fn from_parts(
+        target: Quad,
+        world_color: Vector4<f32>,
+        panels: Vec<VWPanel>,
+        panel_images: &[DynamicImage],
+        target_image: &DynamicImage,
+    ) -> Self {
+        assert_eq!(
+            panels.len(),
+            panel_images.len(),
+            "Every panel needs an image"
+        );
+        ObserverRenderer {
+            target,
+            target_image: target_image.to_rgba8(),
+            world_color,
+            panels,
+            panel_images: panel_images.iter().map(|x| x.to_rgba8()).collect(),
+        }
+    }
+
+    /// Renderer and cameras of a scene capture in `./saves/scene_capture/{name}/`.
+    /// Panels are sorted relative to the first camera, as in `CpuSampler::from_capture`.
+    pub fn from_capture(
+        name: &str,
+        panel_images: &[DynamicImage],
+    ) -> Result<(Self, VecDeque<Camera>)> {
+        let save = Save::load(name)?;
+        let camera = save.first_camera()?;
+        let panels: Vec<ScenePanel> = save.panels().into_iter().cloned().collect();
+        let target_image = save.to_cache()?.target_image;
+        let renderer = Self::new(&save.target, &panels, camera, panel_images, &target_image);
+        Ok((renderer, save.cameras))
+    }
+
+    /// Image seen by `camera`, rays are built like the fragment shader does for a window of
+    /// `width` x `height`
+    pub fn render(&self, camera: &Camera, width: u32, height: u32) -> DynamicImage {
+        self.render_rays(camera, width, height, true)
+    }
+
+    /// Same view with the panels removed, the image the panels should reproduce
+    pub fn render_target(&self, camera: &Camera, width: u32, height: u32) -> DynamicImage {
+        self.render_rays(camera, width, height, false)
+    }
+
+    fn render_rays(&self, camera: &Camera, width: u32, height: u32, panels: bool) -> DynamicImage {
+        let ray_tracer = RayTraceInfo::test(camera, height, width);
+        let image: RgbaImage = ImageBuffer::from_par_fn(width, height, |x, y| {
+            let (origin, direction) = ray_tracer.ray(x, y);
+            let colour = if panels {
+                self.trace(origin, direction)
+            } else {
+                self.trace_target(origin, direction)
+            };
+            Rgba(colour.map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8))
+        });
+        DynamicImage::ImageRgba8(image)
+    }
+
+    /// Colour along a single ray. Rays through a panel are attenuated by its texel with a white
+    /// backlight, other rays see the target or the world colour.
+    fn trace(&self, origin: Vector3<f32>, direction: Vector3<f32>) -> [f32; 4] {
+        let mut colour = [1.0; 4];
+        let mut hit_panel = false;
+        for (panel, image) in self.panels.iter().zip(&self.panel_images) {
+            if let Some(pixel) = panel.pixel_hit(origin, direction) {
+                let texel = sample(image, pixel, panel.pixel_count);
+                for channel in 0..3 {
+                    colour[channel] *= texel[channel];
+                }
+                hit_panel = true;
+            }
+        }
+        if hit_panel {
+            colour
+        } else {
+            self.trace_target(origin, direction)
+        }
+    }
+
+    fn trace_target(&self, origin: Vector3<f32>, direction: Vector3<f32>) -> [f32; 4] {
+        match self.target.texture_hit(origin, direction) {
+            Some(relative) => {
+                let (width, height) = self.target_image.dimensions();
+                let pixel = Vector2::new(
+                    (relative.x * width as f32) as u32,
+                    (relative.y * height as f32) as u32,
+                );
+                sample(&self.target_image, pixel, Vector2::new(width, height))
+            }
+            None => self.world_color.into(),
+        }
+    }
+}
+
+/// Nearest texel for a pixel out of `pixel_count`, scaled to the image size
+fn sample(image: &RgbaImage, pixel: Vector2<u32>, pixel_count: Vector2<u32>) -> [f32; 4] {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        // Nothing loaded yet, the panel lets everything through
+        return [1.0; 4];
+    }
+    let texel_x = ((pixel.x as u64 * width as u64) / pixel_count.x.max(1) as u64) as u32;
+    let texel_y = ((pixel.y as u64 * height as u64) / pixel_count.y.max(1) as u64) as u32;
+    let texel = image.get_pixel(
+        texel_x.min(width.saturating_sub(1)),
+        texel_y.min(height.saturating_sub(1)),
+    );
+    texel.0.map(|x| x as f32 / 255.0)
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::{Deg, Matrix4};
+
+    use super::*;
+    use crate::shape::Shape;
+
+    #[test]
+    fn panels_attenuate_product() {
+        let target = Quad::new(
+            Vector3::new(-0.5, 0.5, 0.0),
+            Vector3::new(0.5, 0.5, 0.0),
+            Vector3::new(-0.5, -0.5, 0.0),
+            Vector3::new(0.5, -0.5, 0.0),
+        );
+        let panel_a =
+            VWPanel::demo_panel().place(&Matrix4::from_translation(Vector3::new(0.0, 0.0, 2.0)));
+        let panel_b =
+            VWPanel::demo_panel().place(&Matrix4::from_translation(Vector3::new(0.0, 0.0, 1.0)));
+        let grey = |value: u8| {
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+                8,
+                8,
+                Rgba([value, value, value, 255]),
+            ))
+        };
+        let renderer = ObserverRenderer::from_parts(
+            target,
+            Vector4::new(0.0, 0.0, 0.0, 1.0),
+            vec![panel_a, panel_b],
+            &[grey(128), grey(64)],
+            &grey(255),
+        );
+        // Looking down the z axis at the center of the panels
+        let camera = Camera::new((0.0, 0.0, 4.0), Deg(-90.0), Deg(0.0), Deg(45.0));
+        let image = renderer.render(&camera, 33, 33).to_rgba8();
+        let expected = (128.0 * 64.0 / 255.0_f32).round() as u8;
+        assert_eq!(
+            image.get_pixel(16, 16).0,
+            [expected, expected, expected, 255]
+        );
+    }
+}
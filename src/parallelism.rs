@@ -0,0 +1,141 @@
+use std::{fmt, num::NonZero, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Environment variable the benchmarks read their parallelism from
+pub const PARALLELISM_ENV: &str = "LF_THREADS";
+
+/// Threads a solve may use. Every solve runs in a rayon pool of its own, nothing global is
+/// touched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Parallelism {
+    /// One thread, for deterministic benchmarks
+    Single,
+    /// One thread per available core
+    #[default]
+    Available,
+    /// A fixed number of threads
+    Threads(NonZero<usize>),
+}
+
+impl Parallelism {
+    /// Number of threads in the pool of a solve
+    pub fn threads(&self) -> usize {
+        match self {
+            Parallelism::Single => 1,
+            Parallelism::Available => std::thread::available_parallelism()
+                .map(NonZero::get)
+                .unwrap_or(1),
+            Parallelism::Threads(threads) => threads.get(),
+        }
+    }
+
+    /// From `LF_THREADS`, `Available` if it is unset or can't be parsed
+    pub fn from_env() -> Self {
+        std::env::var(PARALLELISM_ENV)
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// Runs `solve` in a pool of `threads()` threads, any rayon iterator inside runs on it
+    pub fn install<R, F>(&self, solve: F) -> R
+    where
+        F: FnOnce() -> R + Send,
+        R: Send,
+    {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads())
+            .thread_name(|index| format!("lf-solver-{index}"))
+            .build()
+            .expect("Could not build the solver thread pool")
+            .install(solve)
+    }
+}
+
+impl fmt::Display for Parallelism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Parallelism::Single => write!(f, "single"),
+            Parallelism::Available => write!(f, "available"),
+            Parallelism::Threads(threads) => write!(f, "{threads}"),
+        }
+    }
+}
+
+impl FromStr for Parallelism {
+    type Err = String;
+
+    /// `single`, `available` or a number of threads, 1 being the same as `single`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "single" => Ok(Parallelism::Single),
+            "available" | "all" => Ok(Parallelism::Available),
+            threads => match threads.parse::<usize>() {
+                Ok(1) => Ok(Parallelism::Single),
+                Ok(threads) => NonZero::new(threads)
+                    .map(Parallelism::Threads)
+                    .ok_or_else(|| "A solve needs at least one thread".to_string()),
+                Err(_) => Err(format!(
+                    "Expected single, available or a number of threads, got {s}"
+                )),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn solves_run_on_their_own_pool() {
+        for (parallelism, threads) in [
+            (Parallelism::Single, 1),
+            (Parallelism::Threads(NonZero::new(3).unwrap()), 3),
+        ] {
+            assert_eq!(parallelism.install(rayon::current_num_threads), threads);
+            assert_eq!(parallelism.to_string().parse(), Ok(parallelism));
+        }
+        assert_eq!("1".parse(), Ok(Parallelism::Single));
+        assert!("0".parse::<Parallelism>().is_err());
+    }
+
+    #[test]
+    fn views_split_into_contiguous_chunks() {
+        assert_eq!(crate::view_chunks(7, 3), [0..3, 3..5, 5..7]);
+        assert_eq!(crate::view_chunks(2, 8), [0..1, 1..2]);
+        assert_eq!(crate::view_chunks(7, 1), vec![0..7]);
+    }
+
+    #[test]
+    fn threaded_separable_solves_match_single() {
+        use crate::{solver::SolverKind, synthetic, LFSettings, Lff};
+
+        // 7 views like a kernel capture, one chunk more than the threads have
+        let capture = synthetic::random_separable::<f64>(3, &[(6, 5), (5, 4)], 7, (16, 16));
+        for solver in SolverKind::ALL {
+            let solve = |parallelism: Parallelism| {
+                let settings = LFSettings {
+                    iter_count: 6,
+                    solver,
+                    frames: 2,
+                    save_error: true,
+                    debug_prints: false,
+                    parallelism,
+                    ..Default::default()
+                };
+                capture.factorize(&settings, &()).unwrap().unwrap()
+            };
+            let single = solve(Parallelism::Single);
+            let threads = Parallelism::Threads(NonZero::new(3).unwrap());
+            let threaded = solve(threads);
+            // Summed in view order, the same threads always give the same panels
+            assert_eq!(solve(threads).panels, threaded.panels, "{solver}");
+            for (single, threaded) in single.residuals.iter().zip(&threaded.residuals) {
+                assert!((single - threaded).abs() <= 1e-5 * single, "{solver}");
+            }
+            assert_eq!(single.residuals.len(), threaded.residuals.len());
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::VecDeque,
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::Instant,
+};
+
+use egui::Ui;
+use faer::Mat;
+
+use crate::{
+    checkpoint::{ChannelState, Checkpointer},
+    error,
+    report::{Convergence, SolveReport, StopReason},
+    scalar::Scalar,
+    LFSettings, Lff,
+};
+
+/// State of one channel of a solve after one of its iterations
+pub struct Progress<'a> {
+    /// Iterations done so far, from 1
+    pub iteration: usize,
+    /// `LFSettings::iter_count`, the stop criteria may finish before
+    pub iterations: usize,
+    pub channel: usize,
+    /// 1 for luma, 3 when solving in colour
+    pub channels: usize,
+    /// L2 norm of the reprojection residual, if it was computed this iteration
+    pub residual: Option<f32>,
+    /// Every frame of every layer when the observer asks for them, indexed by layer then frame.
+    /// Stereo panels are column vectors. Always `f32`, whatever the precision of the solve.
+    pub panels: Option<&'a [Vec<Mat<f32>>]>,
+}
+
+/// Receives the progress of a solve and can stop it. Channels are solved in parallel, so every
+/// method may be called from several threads at once.
+pub trait SolveObserver: Sync {
+    /// Compute the residual every iteration, even without `save_error` or a relative tolerance
+    fn wants_residual(&self) -> bool {
+        false
+    }
+    /// Hand the panels of this iteration to `on_iteration`
+    fn wants_panels(&self, iteration: usize) -> bool {
+        let _ = iteration;
+        false
+    }
+    /// Called after every iteration, `Break` stops the solve and keeps the panels so far
+    fn on_iteration(&self, progress: &Progress) -> ControlFlow<()>;
+}
+
+/// No observer, the solve runs to the end
+impl SolveObserver for () {
+    fn on_iteration(&self, progress: &Progress) -> ControlFlow<()> {
+        let _ = progress;
+        ControlFlow::Continue(())
+    }
+}
+
+/// Reports the iterations of a single channel, to the observer and to the checkpoints
+pub(crate) struct ChannelReporter<'a> {
+    pub observer: &'a dyn SolveObserver,
+    pub checkpointer: Option<&'a Checkpointer>,
+    pub channel: usize,
+    pub channels: usize,
+    pub iterations: usize,
+    /// When the whole solve started, for the time budget
+    pub started: Instant,
+}
+
+impl ChannelReporter<'_> {
+    pub fn wants_residual(&self) -> bool {
+        self.observer.wants_residual()
+    }
+    /// Where the channel continues from when resuming a checkpoint
+    pub fn start(&self) -> Option<ChannelState> {
+        self.checkpointer?.start(self.channel)
+    }
+    /// Reports an iteration and applies the stop criteria, why the channel stops after it if
+    /// it does
+    pub fn report<T: Scalar>(
+        &self,
+        convergence: &mut Convergence,
+        iteration: usize,
+        residual: Option<T>,
+        panels: &[Vec<Mat<T>>],
+        error: &VecDeque<f32>,
+    ) -> Option<StopReason> {
+        let single = self
+            .observer
+            .wants_panels(iteration)
+            .then(|| T::single_precision(panels));
+        let flow = self.observer.on_iteration(&Progress {
+            iteration,
+            iterations: self.iterations,
+            channel: self.channel,
+            channels: self.channels,
+            residual: residual.map(T::to_f32),
+            panels: single.as_deref(),
+        });
+        let stop = match flow {
+            ControlFlow::Break(()) => Some(StopReason::Stopped),
+            ControlFlow::Continue(()) => convergence.check(residual.map(T::to_f64)),
+        };
+        if let Some(checkpointer) = self.checkpointer {
+            // A stopped or finished solve can be picked up again later on
+            let last = stop.is_some() || iteration == self.iterations;
+            checkpointer.record(self.channel, iteration, panels, error, last);
+        }
+        stop
+    }
+}
+
+/// Progress shared between a solve running in the background and the UI
+#[derive(Default)]
+pub struct SolveProgress {
+    /// Latest iteration of every channel, counting those of a resumed checkpoint
+    done: Mutex<Vec<usize>>,
+    /// Iterations over every channel, known once the first one reports
+    total: AtomicUsize,
+    residual: Mutex<Option<f32>>,
+    stop: AtomicBool,
+}
+
+impl SolveProgress {
+    /// Asks the solve to stop after its current iteration
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+    pub fn is_stopping(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+    /// Between 0 and 1
+    pub fn fraction(&self) -> f32 {
+        let total = self.total.load(Ordering::Relaxed);
+        if total == 0 {
+            return 0.0;
+        }
+        let done: usize = self.done.lock().unwrap().iter().sum();
+        done as f32 / total as f32
+    }
+    /// Latest residual reported by any channel
+    pub fn residual(&self) -> Option<f32> {
+        *self.residual.lock().unwrap()
+    }
+}
+
+impl SolveObserver for SolveProgress {
+    fn wants_residual(&self) -> bool {
+        true
+    }
+    fn on_iteration(&self, progress: &Progress) -> ControlFlow<()> {
+        self.total
+            .store(progress.iterations * progress.channels, Ordering::Relaxed);
+        {
+            let mut done = self.done.lock().unwrap();
+            if done.len() < progress.channels {
+                done.resize(progress.channels, 0);
+            }
+            done[progress.channel] = done[progress.channel].max(progress.iteration);
+        }
+        if progress.residual.is_some() {
+            *self.residual.lock().unwrap() = progress.residual;
+        }
+        if self.is_stopping() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+}
+
+type Solution = error::Result<Option<SolveReport>>;
+
+/// A solve on its own thread, so the UI keeps drawing and can stop it
+pub struct BackgroundSolve {
+    pub progress: Arc<SolveProgress>,
+    handle: Option<JoinHandle<Solution>>,
+}
+
+impl BackgroundSolve {
+    /// Solves a copy of `matrices`, the originals stay free for sampling and saving
+    pub fn spawn<M>(matrices: M, settings: LFSettings) -> Self
+    where
+        M: Lff + Send + 'static,
+    {
+        let progress = Arc::new(SolveProgress::default());
+        let observer = progress.clone();
+        let handle = std::thread::spawn(move || matrices.factorize(&settings, observer.as_ref()));
+        BackgroundSolve {
+            progress,
+            handle: Some(handle),
+        }
+    }
+
+    /// The solution once the thread is done, `None` while it is still running
+    pub fn try_finish(&mut self) -> Option<Solution> {
+        if !self.handle.as_ref()?.is_finished() {
+            return None;
+        }
+        // A panicking solve has no solution
+        self.handle.take()?.join().ok()
+    }
+
+    /// Progress bar, residual and a Stop button
+    pub fn draw_ui(&self, ui: &mut Ui) {
+        let progress = &self.progress;
+        ui.add(egui::ProgressBar::new(progress.fraction()).show_percentage());
+        if let Some(residual) = progress.residual() {
+            ui.label(format!("Residual: {residual:.5}"));
+        }
+        if progress.is_stopping() {
+            ui.label("Stopping...");
+        } else if ui.button("Stop").clicked() {
+            progress.stop();
+        }
+    }
+}
+
+impl Drop for BackgroundSolve {
+    /// A replaced solve stops instead of running on unobserved
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            self.progress.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::synthetic::gradient_scene;
+
+    /// Stops after a few iterations, with the residual of every one
+    #[derive(Default)]
+    struct StopAfter {
+        residuals: Mutex<Vec<Option<f32>>>,
+    }
+
+    impl SolveObserver for StopAfter {
+        fn wants_residual(&self) -> bool {
+            true
+        }
+        fn on_iteration(&self, progress: &Progress) -> ControlFlow<()> {
+            let mut residuals = self.residuals.lock().unwrap();
+            residuals.push(progress.residual);
+            if progress.iteration == 3 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        }
+    }
+
+    #[test]
+    fn observer_stops_solve() {
+        let (sampler, image) = gradient_scene();
+        let settings = LFSettings {
+            iter_count: 50,
+            debug_prints: false,
+            ..Default::default()
+        };
+        let separable = sampler.sample_separable(&image);
+        let stereo = sampler.sample_stereo(&image);
+        let solves: [&dyn Lff; 2] = [&separable, &stereo];
+        for solve in solves {
+            let observer = StopAfter::default();
+            let report = solve.factorize(&settings, &observer).unwrap().unwrap();
+            assert_eq!(report.layers().len(), 2);
+            assert_eq!(report.stop, StopReason::Stopped);
+            assert_eq!(report.iterations, 3);
+            let residuals = observer.residuals.into_inner().unwrap();
+            assert_eq!(residuals.len(), 3);
+            assert!(residuals.iter().all(Option::is_some));
+        }
+    }
+
+    #[test]
+    fn resumed_solves_finish_the_progress_bar() {
+        let progress = SolveProgress::default();
+        // Both channels resume from iteration 6 of 10
+        for iteration in 7..=10 {
+            for channel in 0..2 {
+                let _ = progress.on_iteration(&Progress {
+                    iteration,
+                    iterations: 10,
+                    channel,
+                    channels: 2,
+                    residual: None,
+                    panels: None,
+                });
+            }
+            assert_eq!(progress.fraction(), iteration as f32 / 10.0);
+        }
+    }
+}
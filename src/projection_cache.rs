@@ -0,0 +1,127 @@
+use faer::Mat;
+
+use crate::{as_refs, scalar::Scalar, CompleteMapping, LFMatrices, LayerFrames};
+
+/// Per view products the separable solver keeps between updates, for as many views as fit in
+/// `LFSettings::cache_budget`. The projection of the target never changes, those of the panels
+/// are replaced whenever their panel is. Views past the budget are projected every time.
+pub(crate) struct ProjectionCache<T: Scalar> {
+    /// `M_y c_t M_x^T` of the first views
+    targets: Vec<Mat<T>>,
+    /// `M_y c M_x^T` of every frame of every layer, of the first views
+    panels: Vec<LayerFrames<T>>,
+}
+
+impl<T: Scalar> ProjectionCache<T> {
+    /// Projections of one channel `c_t` and of the panels the solve starts from, in at most
+    /// `budget` bytes. Target projections come first, a view of them costs as much as one panel.
+    pub fn new(
+        capture: &LFMatrices<T>,
+        c_t: &Mat<T>,
+        layers: &LayerFrames<T>,
+        budget: usize,
+    ) -> Self {
+        let views = capture.number_of_view_points as usize;
+        let rays = capture.t.y.matrix.first().map(|x| x.nrows()).unwrap_or(0)
+            * capture.t.x.matrix.first().map(|x| x.nrows()).unwrap_or(0);
+        let view_bytes = (rays * std::mem::size_of::<T>()).max(1);
+        let target_views = (budget / view_bytes).min(views);
+        let panel_count = layers.iter().map(Vec::len).sum::<usize>().max(1);
+        let remaining = budget - target_views * view_bytes;
+        let panel_views = (remaining / (view_bytes * panel_count)).min(views);
+
+        let layers = as_refs(layers);
+        ProjectionCache {
+            targets: (0..target_views)
+                .map(|view_point| capture.t.project(view_point, c_t))
+                .collect(),
+            panels: (0..panel_views)
+                .map(|view_point| capture.view_products(view_point, &layers))
+                .collect(),
+        }
+    }
+    /// Views whose target and panel projections are kept
+    pub fn views(&self) -> (usize, usize) {
+        (self.targets.len(), self.panels.len())
+    }
+    pub fn target(&self, view_point: usize) -> Option<&Mat<T>> {
+        self.targets.get(view_point)
+    }
+    /// Indexed by layer then by frame
+    pub fn panels(&self, view_point: usize) -> Option<&LayerFrames<T>> {
+        self.panels.get(view_point)
+    }
+    /// Projects one frame of one layer again, after `panel` replaced it
+    pub fn update(
+        &mut self,
+        mapping: &CompleteMapping<T>,
+        (layer, frame): (usize, usize),
+        panel: &Mat<T>,
+    ) {
+        for (view_point, products) in self.panels.iter_mut().enumerate() {
+            products[layer][frame] = mapping.project(view_point, panel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{report::SolveReport, solver::SolverKind, synthetic, LFSettings, Lff};
+
+    /// 32 KiB of rays per view in `f64`
+    fn capture() -> LFMatrices<f64> {
+        synthetic::random_separable(12, &[(6, 5), (5, 4)], 4, (64, 64))
+    }
+
+    #[test]
+    fn budget_bounds_cached_views() {
+        let capture = capture();
+        let c_t = Mat::from_fn(64, 64, |_, _| 0.5);
+        let layers: LayerFrames<f64> = vec![
+            vec![Mat::from_fn(6, 5, |_, _| 0.5)],
+            vec![Mat::from_fn(5, 4, |_, _| 0.5)],
+        ];
+        let kib = 1024;
+        for (budget, views) in [
+            (0, (0, 0)),
+            (31 * kib, (0, 0)),
+            (96 * kib, (3, 0)),
+            (191 * kib, (4, 0)),
+            (256 * kib, (4, 2)),
+            (1024 * kib, (4, 4)),
+        ] {
+            let cache = ProjectionCache::new(&capture, &c_t, &layers, budget);
+            assert_eq!(cache.views(), views, "{budget} bytes");
+            let (targets, panels) = views;
+            assert!((targets + 2 * panels) * 32 * kib <= budget);
+        }
+    }
+
+    #[test]
+    fn cached_solves_match_uncached() {
+        let capture = capture();
+        for solver in SolverKind::ALL {
+            let solve = |cache_budget: f32| -> SolveReport {
+                let settings = LFSettings {
+                    iter_count: 8,
+                    solver,
+                    frames: 2,
+                    colour: true,
+                    save_error: true,
+                    debug_prints: false,
+                    cache_budget,
+                    ..Default::default()
+                };
+                capture.factorize(&settings, &()).unwrap().unwrap()
+            };
+            let uncached = solve(0.0);
+            // Every target and 3 of the 4 views of the panels, then every view, for each channel
+            for cache_budget in [1.5, 64.0] {
+                let cached = solve(cache_budget);
+                assert_eq!(cached.residuals, uncached.residuals, "{solver}");
+                assert_eq!(cached.panels, uncached.panels, "{solver}");
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::fmt::Display;
+
+use dssim_core::Dssim;
+use egui::Ui;
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use rgb::FromSlice;
+use serde::{Deserialize, Serialize};
+
+use crate::{camera::Camera, observer::ObserverRenderer, utils::DrawUI};
+
+/// Scores of a single image against a reference
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImageQuality {
+    /// In dB, infinite for identical images
+    pub psnr: f64,
+    pub ssim: f64,
+    /// `1 / ssim - 1`, as reported by the `dssim` tool
+    pub dssim: f64,
+    /// Largest difference of any channel, 0 to 1
+    pub max_error: f32,
+}
+
+impl ImageQuality {
+    /// Compares the RGB channels, `image` is resized to the reference if they differ
+    pub fn compare(reference: &DynamicImage, image: &DynamicImage) -> Self {
+        let (width, height) = reference.dimensions();
+        let image = if image.dimensions() != (width, height) {
+            image.resize_exact(width, height, FilterType::Triangle)
+        } else {
+            image.clone()
+        };
+        let reference = reference.to_rgb8();
+        let image = image.to_rgb8();
+
+        let mut squared_error = 0.0f64;
+        let mut max_error = 0u8;
+        for (x, y) in reference.as_raw().iter().zip(image.as_raw()) {
+            let difference = x.abs_diff(*y);
+            squared_error += (difference as f64 / 255.0).powi(2);
+            max_error = max_error.max(difference);
+        }
+        let mse = squared_error / reference.as_raw().len().max(1) as f64;
+        let psnr = if mse == 0.0 {
+            f64::INFINITY
+        } else {
+            -10.0 * mse.log10()
+        };
+
+        let dssim = Dssim::new();
+        let reference = dssim
+            .create_image_rgb(reference.as_raw().as_rgb(), width as usize, height as usize)
+            .unwrap();
+        let image = dssim
+            .create_image_rgb(image.as_raw().as_rgb(), width as usize, height as usize)
+            .unwrap();
+        let dssim: f64 = dssim.compare(&reference, image).0.into();
+
+        ImageQuality {
+            psnr,
+            ssim: 1.0 / (1.0 + dssim),
+            dssim,
+            max_error: max_error as f32 / 255.0,
+        }
+    }
+
+    /// Mean of every score, apart from the max error which stays the worst one
+    pub fn aggregate(scores: &[ImageQuality]) -> Self {
+        let count = scores.len().max(1) as f64;
+        ImageQuality {
+            psnr: scores.iter().map(|x| x.psnr).sum::<f64>() / count,
+            ssim: scores.iter().map(|x| x.ssim).sum::<f64>() / count,
+            dssim: scores.iter().map(|x| x.dssim).sum::<f64>() / count,
+            max_error: scores.iter().map(|x| x.max_error).fold(0.0, f32::max),
+        }
+    }
+}
+
+impl Display for ImageQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PSNR: {:.2} dB, SSIM: {:.4}, DSSIM: {:.6}, Max error: {:.3}",
+            self.psnr, self.ssim, self.dssim, self.max_error
+        )
+    }
+}
+
+/// Scores of the perceived image of one viewpoint
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ViewQuality {
+    /// Against the target as seen from the viewpoint without panels
+    pub target: ImageQuality,
+    /// Against a reference render, e.g. the GPU output
+    pub reference: Option<ImageQuality>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QualityReport {
+    pub views: Vec<ViewQuality>,
+    pub target: ImageQuality,
+    pub reference: Option<ImageQuality>,
+}
+
+impl QualityReport {
+    /// `targets`, `perceived` and `references` hold one image per viewpoint
+    pub fn new(
+        targets: &[DynamicImage],
+        perceived: &[DynamicImage],
+        references: Option<&[DynamicImage]>,
+    ) -> Self {
+        assert_eq!(targets.len(), perceived.len(), "One target per view");
+        let views: Vec<ViewQuality> = perceived
+            .iter()
+            .enumerate()
+            .map(|(view, image)| ViewQuality {
+                target: ImageQuality::compare(&targets[view], image),
+                reference: references.map(|x| ImageQuality::compare(&x[view], image)),
+            })
+            .collect();
+
+        let target = ImageQuality::aggregate(&views.iter().map(|x| x.target).collect::<Vec<_>>());
+        let reference = references.map(|_| {
+            ImageQuality::aggregate(&views.iter().filter_map(|x| x.reference).collect::<Vec<_>>())
+        });
+        QualityReport {
+            views,
+            target,
+            reference,
+        }
+    }
+
+    /// Renders every camera on the CPU and scores the perceived image against the bare target
+    pub fn evaluate<'a>(
+        renderer: &ObserverRenderer,
+        cameras: impl IntoIterator<Item = &'a Camera>,
+        width: u32,
+        height: u32,
+        references: Option<&[DynamicImage]>,
+    ) -> Self {
+        let (targets, perceived): (Vec<DynamicImage>, Vec<DynamicImage>) = cameras
+            .into_iter()
+            .map(|camera| {
+                (
+                    renderer.render_target(camera, width, height),
+                    renderer.render(camera, width, height),
+                )
+            })
+            .unzip();
+        Self::new(&targets, &perceived, references)
+    }
+}
+
+impl Display for QualityReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, view) in self.views.iter().enumerate() {
+            writeln!(f, "View {index}: {}", view.target)?;
+        }
+        write!(f, "Overall: {}", self.target)?;
+        if let Some(reference) = self.reference {
+            write!(f, "\nAgainst reference: {reference}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings and last result of the quality evaluation in the UI
+pub struct QualityEvaluator {
+    pub evaluate: bool,
+    pub width: u32,
+    pub height: u32,
+    pub report: Option<QualityReport>,
+}
+
+impl Default for QualityEvaluator {
+    fn default() -> Self {
+        QualityEvaluator {
+            evaluate: false,
+            width: 512,
+            height: 512,
+            report: None,
+        }
+    }
+}
+
+impl DrawUI for QualityEvaluator {
+    fn draw_ui(&mut self, ctx: &egui::Context, title: Option<String>, ui: Option<&mut Ui>) {
+        let title = title.unwrap_or("Image Quality".to_string());
+        let _ = ui;
+        egui_winit::egui::Window::new(title)
+            .resizable(true)
+            .vscroll(true)
+            .default_open(false)
+            .show(ctx, |ui| {
+                ui.label("Render resolution");
+                ui.add(egui::Slider::new(&mut self.width, 64..=2048).text("Width"));
+                ui.add(egui::Slider::new(&mut self.height, 64..=2048).text("Height"));
+                self.evaluate = ui.button("Evaluate saved views").clicked();
+
+                let Some(report) = self.report.as_ref() else {
+                    ui.label("No evaluation yet");
+                    return;
+                };
+                egui::Grid::new("Quality").striped(true).show(ui, |ui| {
+                    ui.label("View");
+                    ui.label("PSNR");
+                    ui.label("SSIM");
+                    ui.label("DSSIM");
+                    ui.label("Max error");
+                    ui.end_row();
+                    let rows = report
+                        .views
+                        .iter()
+                        .enumerate()
+                        .map(|(index, view)| (index.to_string(), view.target))
+                        .chain(std::iter::once(("All".to_string(), report.target)));
+                    for (name, quality) in rows {
+                        ui.label(name);
+                        ui.label(format!("{:.2}", quality.psnr));
+                        ui.label(format!("{:.4}", quality.ssim));
+                        ui.label(format!("{:.6}", quality.dssim));
+                        ui.label(format!("{:.3}", quality.max_error));
+                        ui.end_row();
+                    }
+                });
+            });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn identical_and_offset_images() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(32, 32, |x, y| {
+            Rgb([(x * 7) as u8, (y * 7) as u8, 128])
+        }));
+        let same = ImageQuality::compare(&image, &image);
+        assert!(same.psnr.is_infinite());
+        assert_eq!(same.max_error, 0.0);
+        assert!(same.dssim.abs() < 1e-6);
+
+        let offset = DynamicImage::ImageRgb8(RgbImage::from_fn(32, 32, |x, y| {
+            Rgb([(x * 7) as u8 + 10, (y * 7) as u8 + 10, 138])
+        }));
+        let quality = ImageQuality::compare(&image, &offset);
+        // Every channel is off by 10/255
+        let expected = -20.0 * (10.0f64 / 255.0).log10();
+        assert!((quality.psnr - expected).abs() < 1e-6);
+        assert_eq!(quality.max_error, 10.0 / 255.0);
+        assert!(quality.ssim < 1.0);
+    }
+}
@@ -32,4 +32,13 @@ impl RayTraceInfo {
             p_1_m,
         }
     }
+
+    /// Origin and (unnormalized) direction of the ray through the center of pixel (x, y),
+    /// built the same way as in `fs_main`
+    pub fn ray(&self, x: u32, y: u32) -> (Vector3<f32>, Vector3<f32>) {
+        let f_x = x as f32 + 0.5;
+        let f_y = y as f32 + 0.5;
+        let direction = self.p_1_m + self.q_x * (f_x - 1.0) + self.q_y * (f_y - 1.0);
+        (self.ray_origin, direction)
+    }
 }
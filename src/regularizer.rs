@@ -0,0 +1,218 @@
+use faer::Mat;
+use serde::{Deserialize, Serialize};
+
+use crate::scalar::Scalar;
+
+/// Smoothing of the total variation, keeps its gradient finite on flat regions
+const TV_EPSILON: f32 = 0.01;
+
+/// Priors on the panels, added to the data term `0.5 * |l - estimate|^2` of every layer and
+/// frame. Weights are absolute, a weight of 0 disables the term.
+///
+/// Every term is split into a positive and a negative part of its gradient, so the
+/// multiplicative rule keeps working: the negative part goes to the numerator, the positive part
+/// to the denominator.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Regularization {
+    /// Smoothed total variation over horizontal and vertical neighbours
+    pub total_variation: f32,
+    /// Laplacian smoothness, `0.5 * (x_i - x_j)^2` over horizontal and vertical neighbours
+    pub smoothness: f32,
+    /// L1 norm of the panel, favours sparse (dark) pixels
+    pub sparsity: f32,
+    /// `0.5 * (x - brightness_target)^2` for every pixel
+    pub brightness: f32,
+    /// Value the brightness prior pulls towards, 1 is fully transparent
+    pub brightness_target: f32,
+}
+
+impl Default for Regularization {
+    fn default() -> Self {
+        Regularization {
+            total_variation: 0.0,
+            smoothness: 0.0,
+            sparsity: 0.0,
+            brightness: 0.0,
+            brightness_target: 1.0,
+        }
+    }
+}
+
+impl Regularization {
+    pub fn is_active(&self) -> bool {
+        self.total_variation > 0.0
+            || self.smoothness > 0.0
+            || self.sparsity > 0.0
+            || self.brightness > 0.0
+    }
+
+    /// Value of the priors for a panel of `(rows, cols)` pixels
+    pub fn value<T: Scalar>(&self, panel: &Mat<T>, size: (usize, usize)) -> T {
+        if !self.is_active() {
+            return T::ZERO;
+        }
+        let weights = self.cast::<T>();
+        let half = T::from_f32(0.5);
+        let mut value = T::ZERO;
+        for_each_edge(panel, size, |x_i, x_j| {
+            let difference = x_i - x_j;
+            value += half * weights.smoothness * difference * difference;
+            value +=
+                weights.total_variation * (difference * difference + weights.tv_epsilon).sqrt();
+        });
+        for_each_pixel(panel, size, |x| {
+            value += weights.sparsity * x;
+            value += half * weights.brightness * (x - weights.brightness_target).powi(2);
+        });
+        value
+    }
+
+    /// Adds the gradient of the priors to the update terms of `panel`, and their curvature to the
+    /// Hessian diagonal. The total variation uses the lagged diffusivity weights, a quadratic
+    /// upper bound of it at the current panel.
+    pub fn add_terms<T: Scalar>(
+        &self,
+        panel: &Mat<T>,
+        size: (usize, usize),
+        numerator: &mut Mat<T>,
+        denominator: &mut Mat<T>,
+        mut hessian: Option<&mut Mat<T>>,
+    ) {
+        if !self.is_active() {
+            return;
+        }
+        let weights = self.cast::<T>();
+        let cols = size.1;
+        for row in 0..size.0 {
+            for col in 0..cols {
+                let i = index(panel, cols, row, col);
+                let x_i = panel[i];
+                for (neighbour_row, neighbour_col) in [(row, col + 1), (row + 1, col)] {
+                    if neighbour_row >= size.0 || neighbour_col >= cols {
+                        continue;
+                    }
+                    let j = index(panel, cols, neighbour_row, neighbour_col);
+                    let x_j = panel[j];
+                    let difference = x_i - x_j;
+                    let weight = weights.smoothness
+                        + weights.total_variation
+                            / (difference * difference + weights.tv_epsilon).sqrt();
+
+                    numerator[i] += weight * x_j;
+                    numerator[j] += weight * x_i;
+                    denominator[i] += weight * x_i;
+                    denominator[j] += weight * x_j;
+                    if let Some(hessian) = hessian.as_deref_mut() {
+                        hessian[i] += weight;
+                        hessian[j] += weight;
+                    }
+                }
+
+                denominator[i] += weights.sparsity + weights.brightness * x_i;
+                numerator[i] += weights.brightness * weights.brightness_target;
+                if let Some(hessian) = hessian.as_deref_mut() {
+                    hessian[i] += weights.brightness;
+                }
+            }
+        }
+    }
+
+    /// Weights in the precision of the solve
+    fn cast<T: Scalar>(&self) -> Weights<T> {
+        Weights {
+            total_variation: T::from_f32(self.total_variation),
+            smoothness: T::from_f32(self.smoothness),
+            sparsity: T::from_f32(self.sparsity),
+            brightness: T::from_f32(self.brightness),
+            brightness_target: T::from_f32(self.brightness_target),
+            tv_epsilon: T::from_f32(TV_EPSILON),
+        }
+    }
+}
+
+/// `Regularization` in the precision of a solve
+struct Weights<T> {
+    total_variation: T,
+    smoothness: T,
+    sparsity: T,
+    brightness: T,
+    brightness_target: T,
+    tv_epsilon: T,
+}
+
+/// Entry of pixel `(row, col)` of a panel, stored as a matrix or as a row by row vector for the
+/// stereo approach
+fn index<T>(panel: &Mat<T>, cols: usize, row: usize, col: usize) -> (usize, usize) {
+    if panel.ncols() == 1 && cols > 1 {
+        (col + row * cols, 0)
+    } else {
+        (row, col)
+    }
+}
+
+fn for_each_pixel<T: Scalar>(panel: &Mat<T>, (rows, cols): (usize, usize), mut f: impl FnMut(T)) {
+    for row in 0..rows {
+        for col in 0..cols {
+            f(panel[index(panel, cols, row, col)]);
+        }
+    }
+}
+
+/// Every pair of horizontal and vertical neighbours
+fn for_each_edge<T: Scalar>(panel: &Mat<T>, (rows, cols): (usize, usize), mut f: impl FnMut(T, T)) {
+    for row in 0..rows {
+        for col in 0..cols {
+            let x = panel[index(panel, cols, row, col)];
+            if col + 1 < cols {
+                f(x, panel[index(panel, cols, row, col + 1)]);
+            }
+            if row + 1 < rows {
+                f(x, panel[index(panel, cols, row + 1, col)]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn terms_match_finite_differences() {
+        let regularization = Regularization {
+            total_variation: 0.3,
+            smoothness: 0.5,
+            sparsity: 0.2,
+            brightness: 0.7,
+            brightness_target: 0.9,
+        };
+        let size = (4, 5);
+        let matrix = Mat::from_fn(4, 5, |x, y| ((x * 7 + y * 3) % 10) as f32 / 10.0);
+        // Same panel as the stereo approach stores it
+        let vector = Mat::from_fn(20, 1, |x, _| matrix[(x / 5, x % 5)]);
+
+        for panel in [matrix, vector] {
+            let mut numerator = Mat::zeros(panel.nrows(), panel.ncols());
+            let mut denominator = Mat::zeros(panel.nrows(), panel.ncols());
+            regularization.add_terms(&panel, size, &mut numerator, &mut denominator, None);
+
+            let step = 1e-3;
+            for row in 0..panel.nrows() {
+                for col in 0..panel.ncols() {
+                    let mut forward = panel.clone();
+                    forward[(row, col)] += step;
+                    let mut backward = panel.clone();
+                    backward[(row, col)] -= step;
+                    let numeric = (regularization.value(&forward, size)
+                        - regularization.value(&backward, size))
+                        / (2.0 * step);
+                    let gradient = denominator[(row, col)] - numerator[(row, col)];
+                    assert!(
+                        (numeric - gradient).abs() < 1e-2,
+                        "({row}, {col}): {numeric} against {gradient}"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! What a solve produced, how it got there and why it stopped
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, FramePanels, Frames};
+
+/// When a solve stops before `LFSettings::iter_count`, the most iterations it runs
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StopCriteria {
+    /// An iteration changing the residual by less than this fraction of it made no progress,
+    /// 0 never stops
+    pub relative_tolerance: f32,
+    /// Iterations in a row without progress before the solve stops
+    pub patience: usize,
+    /// Seconds the solve may take, 0 has no budget. Channels solved one after the other share
+    /// it.
+    pub time_budget: f32,
+}
+
+impl Default for StopCriteria {
+    fn default() -> Self {
+        StopCriteria {
+            relative_tolerance: 0.0,
+            patience: 1,
+            time_budget: 0.0,
+        }
+    }
+}
+
+impl StopCriteria {
+    /// The relative tolerance compares the residual of every iteration
+    pub fn needs_residual(&self) -> bool {
+        self.relative_tolerance > 0.0
+    }
+}
+
+/// Why a solve stopped
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StopReason {
+    /// Ran every one of `LFSettings::iter_count`
+    #[default]
+    Iterations,
+    /// The residual stopped improving, see `StopCriteria::relative_tolerance`
+    Converged,
+    /// Ran out of `StopCriteria::time_budget`
+    TimeBudget,
+    /// The observer stopped it
+    Stopped,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Iterations => write!(f, "ran every iteration"),
+            StopReason::Converged => write!(f, "converged"),
+            StopReason::TimeBudget => write!(f, "ran out of time"),
+            StopReason::Stopped => write!(f, "was stopped"),
+        }
+    }
+}
+
+/// Applies the `StopCriteria` to the iterations of one channel
+pub(crate) struct Convergence {
+    criteria: StopCriteria,
+    started: Instant,
+    previous: Option<f64>,
+    /// Iterations in a row without progress
+    stale: usize,
+}
+
+impl Convergence {
+    /// `started` is when the whole solve started, `previous` the last residual of a resumed
+    /// solve
+    pub fn new(criteria: StopCriteria, started: Instant, previous: Option<f32>) -> Self {
+        Convergence {
+            criteria,
+            started,
+            previous: previous.map(f64::from),
+            stale: 0,
+        }
+    }
+
+    /// Why the solve stops after an iteration, if it does. `residual` is in the precision of
+    /// the solve, `None` when it was not computed.
+    pub fn check(&mut self, residual: Option<f64>) -> Option<StopReason> {
+        let tolerance = self.criteria.relative_tolerance as f64;
+        if let Some(residual) = residual {
+            if let Some(previous) = self.previous.replace(residual) {
+                if tolerance > 0.0 && (previous - residual).abs() <= tolerance * previous {
+                    self.stale += 1;
+                } else {
+                    self.stale = 0;
+                }
+            }
+        }
+        if tolerance > 0.0 && self.stale >= self.criteria.patience.max(1) {
+            return Some(StopReason::Converged);
+        }
+        let budget = self.criteria.time_budget;
+        if budget > 0.0 && self.started.elapsed().as_secs_f32() >= budget {
+            return Some(StopReason::TimeBudget);
+        }
+        None
+    }
+}
+
+/// Everything a solve produced and how it got there
+#[derive(Clone)]
+pub struct SolveReport {
+    /// Every layer of every frame as an image, indexed by frame then by layer
+    pub frames: Frames,
+    /// The panels of the images, indexed by frame, then by layer, then by channel. Stereo panels
+    /// are column vectors.
+    pub panels: FramePanels,
+    /// L2 norm of the residual over every channel after every iteration it was computed,
+    /// including those of a resumed checkpoint
+    pub residuals: Vec<f32>,
+    /// Time of every iteration of this run, summed over channels
+    pub times: Vec<Duration>,
+    /// Wall clock time of the whole solve
+    pub elapsed: Duration,
+    /// Why the channel that ran the longest stopped
+    pub stop: StopReason,
+    /// Iterations of the channel that ran the longest, including those of a resumed checkpoint
+    pub iterations: usize,
+    /// First checkpoint that could not be written, the solve went on without it
+    pub checkpoint_error: Option<Arc<Error>>,
+}
+
+impl SolveReport {
+    /// Layers of the first frame, the complete solution when solving a single frame
+    pub fn layers(&self) -> &[DynamicImage] {
+        self.frames.first().map(Vec::as_slice).unwrap_or_default()
+    }
+    /// Residual after the last iteration, if it was computed
+    pub fn residual(&self) -> Option<f32> {
+        self.residuals.last().copied()
+    }
+    /// Average time of an iteration of this run
+    pub fn average_time(&self) -> Duration {
+        self.times.iter().sum::<Duration>() / self.times.len().max(1) as u32
+    }
+}
+
+impl fmt::Display for SolveReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} iterations in {:.2?} ({:.2?} each), {}",
+            self.iterations,
+            self.elapsed,
+            self.average_time(),
+            self.stop
+        )?;
+        if let Some(residual) = self.residual() {
+            write!(f, ", residual {residual:.5}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{synthetic::Synthetic, LFSettings, Lff};
+
+    fn settings(iter_count: usize, stopping: StopCriteria) -> LFSettings {
+        LFSettings {
+            iter_count,
+            stopping,
+            filter: false,
+            debug_prints: false,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn relative_tolerance_waits_for_patience() {
+        let criteria = StopCriteria {
+            relative_tolerance: 0.01,
+            patience: 2,
+            ..Default::default()
+        };
+        let mut convergence = Convergence::new(criteria, Instant::now(), Some(10.0));
+        // Progress resets the patience, only two stale iterations in a row stop
+        for (residual, expected) in [
+            (9.995, None),
+            (5.0, None),
+            (4.99, None),
+            (4.0, None),
+            (3.999, None),
+            (3.998, Some(StopReason::Converged)),
+        ] {
+            assert_eq!(convergence.check(Some(residual)), expected, "at {residual}");
+        }
+        // Iterations without a residual neither make nor lose progress
+        let mut convergence = Convergence::new(criteria, Instant::now(), None);
+        assert_eq!(convergence.check(None), None);
+        assert_eq!(convergence.check(Some(1.0)), None);
+    }
+
+    #[test]
+    fn solves_report_why_they_stopped() {
+        let problem = Synthetic::<f64>::new(9, &[4, 3], 3, 20, 0.2);
+        let capture = &problem.capture;
+
+        let report = capture
+            .factorize(&settings(30, StopCriteria::default()), &())
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.stop, StopReason::Iterations);
+        assert_eq!(report.iterations, 30);
+        assert_eq!(report.times.len(), 30);
+        assert!(report.residuals.is_empty());
+        assert_eq!(report.layers().len(), 2);
+        assert_eq!(report.panels[0][1][0].nrows(), 9);
+
+        let converging = StopCriteria {
+            relative_tolerance: 1e-3,
+            patience: 3,
+            ..Default::default()
+        };
+        let report = capture
+            .factorize(&settings(5000, converging), &())
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.stop, StopReason::Converged);
+        assert!(report.iterations < 5000);
+        assert_eq!(report.residuals.len(), report.iterations);
+        let last = &report.residuals[report.iterations - 4..];
+        for pair in last.windows(2) {
+            assert!(pair[0] - pair[1] <= 1e-3 * pair[0]);
+        }
+
+        let hurried = StopCriteria {
+            time_budget: 1e-9,
+            ..Default::default()
+        };
+        let report = capture
+            .factorize(&settings(30, hurried), &())
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.stop, StopReason::TimeBudget);
+        assert_eq!(report.iterations, 1);
+    }
+}
@@ -10,13 +10,16 @@ use serde::{Deserialize, Serialize};
 use std::{collections::VecDeque, fs, path::PathBuf};
 use walkdir::WalkDir;
 
+use crate::error::{Error, Result, WithPath};
+use crate::report::SolveReport;
 use crate::utils::DrawUI;
 use crate::{
     camera::Camera,
     scene::{Scene, ScenePanel, Target},
 };
 
-type OutCache = Option<(DynamicImage, DynamicImage, Option<Vec<f32>>)>;
+/// Report of the last solve, its first frame is shown
+type OutCache = Option<SolveReport>;
 /// Cache the current textures if they need to be saved
 pub struct ImageCache {
     pub target_image: DynamicImage,
@@ -37,7 +40,7 @@ impl ImageCache {
                 &self.separable_out
             }
         };
-        if let Some((_x, _y, Some(error))) = out {
+        if let Some(error) = out.as_ref().map(|x| &x.residuals).filter(|x| !x.is_empty()) {
             let max = error.clone().into_iter().reduce(f32::max).unwrap();
 
             let root = BitMapBackend::new(&location, (640, 480)).into_drawing_area();
@@ -86,16 +89,14 @@ impl ImageCache {
     }
     pub fn load_output(&mut self, stereo: bool) -> Result<(), ()> {
         if stereo {
-            if let Some((image_1, image_2, _)) = self.stereo_out.as_ref() {
-                let images = vec![image_1.clone(), image_2.clone()];
-                self.panels = images;
+            if let Some(report) = self.stereo_out.as_ref() {
+                self.panels = report.layers().to_vec();
                 Ok(())
             } else {
                 Err(())
             }
-        } else if let Some((image_1, image_2, _)) = self.separable_out.as_ref() {
-            let images = vec![image_1.clone(), image_2.clone()];
-            self.panels = images;
+        } else if let Some(report) = self.separable_out.as_ref() {
+            self.panels = report.layers().to_vec();
 
             Ok(())
         } else {
@@ -104,6 +105,9 @@ impl ImageCache {
     }
 
     pub fn cache_panel(&mut self, entry: usize, image: DynamicImage) {
+        if entry >= self.panels.len() {
+            self.panels.resize(entry + 1, DynamicImage::default());
+        }
         self.panels[entry] = image;
     }
     pub fn load_world(&mut self, img: DynamicImage) {
@@ -146,6 +150,9 @@ pub struct Save {
     pub target: Target,
     panel_1: ScenePanel,
     panel_2: ScenePanel,
+    /// Layers behind the first two, empty for two panel captures
+    #[serde(default)]
+    extra_panels: Vec<ScenePanel>,
     panel_1_texture_sep: Option<PathBuf>,
     panel_2_texture_sep: Option<PathBuf>,
     panel_1_texture_stereo: Option<PathBuf>,
@@ -159,11 +166,11 @@ impl Save {
         name: &String,
         cache: &ImageCache,
         scene: &Scene,
-    ) -> Self {
+    ) -> Result<Self> {
         let path_core = PathBuf::from(format!("./saves/scene_capture/{name}/"));
 
         if !path_core.exists() {
-            std::fs::create_dir(&path_core).unwrap();
+            std::fs::create_dir(&path_core).with_path(&path_core)?;
         }
 
         let mut plot_core = path_core.clone();
@@ -172,7 +179,10 @@ impl Save {
 
         let mut target_image_path = path_core.clone();
         target_image_path.push("target.png");
-        cache.target_image.save(&target_image_path).ok();
+        cache
+            .target_image
+            .save(&target_image_path)
+            .with_path(&target_image_path)?;
 
         let save = Save {
             target: scene.world.clone(),
@@ -186,34 +196,55 @@ impl Save {
             panel_2_texture_stereo: None,
             panel_1: scene.panels[0].clone(),
             panel_2: scene.panels[1].clone(),
+            extra_panels: scene.panels[2..].to_vec(),
         };
-        save.save_settings();
-        save
+        save.save_settings()?;
+        Ok(save)
     }
-    pub fn save_settings(&self) {
-        let content = ron::ser::to_string_pretty(&self, ron::ser::PrettyConfig::default()).unwrap();
-
+    pub fn save_settings(&self) -> Result<()> {
         let path_core = PathBuf::from(format!("./saves/scene_capture/{}/save.ro", self.name));
-        fs::write(path_core, content).unwrap();
+        let content = ron::ser::to_string_pretty(&self, ron::ser::PrettyConfig::default())
+            .with_path(&path_core)?;
+
+        fs::write(&path_core, content).with_path(&path_core)
     }
 
-    pub fn to_cache(&self) -> ImageCache {
+    pub fn to_cache(&self) -> Result<ImageCache> {
         let target = ImageReader::open(&self.target_path)
-            .unwrap()
+            .with_path(&self.target_path)?
             .decode()
-            .unwrap();
+            .with_path(&self.target_path)?;
 
-        ImageCache {
+        Ok(ImageCache {
             target_image: target,
             stereo_out: None,
             separable_out: None,
             ..Default::default()
-        }
+        })
+    }
+    /// Load a single scene capture from `./saves/scene_capture/{name}/`
+    pub fn load(name: &str) -> Result<Save> {
+        let path_core = PathBuf::from(format!("./saves/scene_capture/{name}/save.ro"));
+        let string = std::fs::read_to_string(&path_core).with_path(&path_core)?;
+        let mut save = ron::from_str::<Save>(string.as_str()).with_path(&path_core)?;
+        save.target.texture.texture_file = save.target_path.clone();
+        Ok(save)
+    }
+    /// First camera of the capture, the panels are sorted relative to it
+    pub fn first_camera(&self) -> Result<&Camera> {
+        self.cameras.front().ok_or_else(|| Error::NoCameras {
+            path: PathBuf::from(format!("./saves/scene_capture/{}/save.ro", self.name)),
+        })
+    }
+    pub fn panels(&self) -> Vec<&ScenePanel> {
+        [&self.panel_1, &self.panel_2]
+            .into_iter()
+            .chain(&self.extra_panels)
+            .collect()
     }
     pub fn update_scene(&self, scene: &mut Scene) {
         scene.world = self.target.clone();
-        scene.panels[0] = self.panel_1.clone();
-        scene.panels[1] = self.panel_2.clone();
+        scene.panels = self.panels().into_iter().cloned().collect();
     }
 }
 
@@ -259,10 +290,11 @@ impl SaveManager {
         }
     }
 
-    pub fn add_save(&mut self, save: Save) {
-        save.save_settings();
+    pub fn add_save(&mut self, save: Save) -> Result<()> {
+        save.save_settings()?;
         self.saves.push_back(save);
         self.save_open = false;
+        Ok(())
     }
 
     pub fn next_save(&mut self) -> Option<&Save> {
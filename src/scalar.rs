@@ -0,0 +1,144 @@
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    fmt::{Debug, Display},
+    iter::Sum,
+    ops::{AddAssign, MulAssign, SubAssign},
+};
+
+use faer::{traits::RealField, Mat};
+use serde::{Deserialize, Serialize};
+
+/// Floating point type the mappings and the solvers work in. Everything runs in `f32` unless a
+/// reference solve asks for `f64`, images and residuals are always exported as `f32`.
+pub trait Scalar:
+    RealField
+    + Copy
+    + Debug
+    + Display
+    + Send
+    + Sync
+    + Serialize
+    + AddAssign
+    + SubAssign
+    + MulAssign
+    + Sum
+    + 'static
+{
+    const ZERO: Self;
+    const ONE: Self;
+    /// Added to the denominators of the update rules, keeps pixels no ray sees finite. The
+    /// first is for the closest layer, the second, smaller one for the others where the
+    /// approach uses one.
+    const DENOMINATOR_EPSILON: (Self, Self);
+
+    fn from_f64(x: f64) -> Self;
+    fn to_f64(self) -> f64;
+    fn from_f32(x: f32) -> Self {
+        Self::from_f64(x as f64)
+    }
+    fn to_f32(self) -> f32 {
+        self.to_f64() as f32
+    }
+    fn from_usize(x: usize) -> Self {
+        Self::from_f64(x as f64)
+    }
+
+    fn sqrt(self) -> Self;
+    fn abs(self) -> Self;
+    fn min(self, other: Self) -> Self;
+    fn max(self, other: Self) -> Self;
+    fn clamp(self, min: Self, max: Self) -> Self;
+    fn powi(self, n: i32) -> Self;
+    fn ln(self) -> Self;
+    fn exp(self) -> Self;
+    fn total_cmp(&self, other: &Self) -> Ordering;
+
+    /// Panels as `f32`, only copied when solving in another precision
+    fn single_precision(layers: &[Vec<Mat<Self>>]) -> Cow<'_, [Vec<Mat<f32>>]>;
+}
+
+macro_rules! impl_scalar {
+    ($scalar:ty, $epsilon:expr, $single:expr) => {
+        impl Scalar for $scalar {
+            const ZERO: Self = 0.0;
+            const ONE: Self = 1.0;
+            const DENOMINATOR_EPSILON: (Self, Self) = $epsilon;
+
+            fn from_f64(x: f64) -> Self {
+                x as $scalar
+            }
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+            fn sqrt(self) -> Self {
+                <$scalar>::sqrt(self)
+            }
+            fn abs(self) -> Self {
+                <$scalar>::abs(self)
+            }
+            fn min(self, other: Self) -> Self {
+                <$scalar>::min(self, other)
+            }
+            fn max(self, other: Self) -> Self {
+                <$scalar>::max(self, other)
+            }
+            fn clamp(self, min: Self, max: Self) -> Self {
+                <$scalar>::clamp(self, min, max)
+            }
+            fn powi(self, n: i32) -> Self {
+                <$scalar>::powi(self, n)
+            }
+            fn ln(self) -> Self {
+                <$scalar>::ln(self)
+            }
+            fn exp(self) -> Self {
+                <$scalar>::exp(self)
+            }
+            fn total_cmp(&self, other: &Self) -> Ordering {
+                <$scalar>::total_cmp(self, other)
+            }
+            fn single_precision(layers: &[Vec<Mat<Self>>]) -> Cow<'_, [Vec<Mat<f32>>]> {
+                $single(layers)
+            }
+        }
+    };
+}
+
+impl_scalar!(f32, (1e-7, 1e-9), Cow::Borrowed);
+impl_scalar!(f64, (1e-12, 1e-14), |layers: &[Vec<Mat<f64>>]| Cow::Owned(
+    cast_layers(layers)
+));
+
+/// `m` in another precision
+pub fn cast<T: Scalar, U: Scalar>(m: &Mat<T>) -> Mat<U> {
+    Mat::from_fn(m.nrows(), m.ncols(), |row, col| {
+        U::from_f64(m[(row, col)].to_f64())
+    })
+}
+
+/// Every frame of every layer in another precision
+pub fn cast_layers<T: Scalar, U: Scalar>(layers: &[Vec<Mat<T>>]) -> Vec<Vec<Mat<U>>> {
+    layers
+        .iter()
+        .map(|frames| frames.iter().map(cast).collect())
+        .collect()
+}
+
+/// Precision of a solve, for the command line
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum Precision {
+    #[default]
+    F32,
+    /// Reference solves, slower but tells numerical stagnation from algorithmic
+    F64,
+}
+
+impl Display for Precision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Precision::F32 => write!(f, "f32"),
+            Precision::F64 => write!(f, "f64"),
+        }
+    }
+}
@@ -7,7 +7,9 @@ use winit::event_loop::EventLoopProxy;
 
 use crate::{
     camera::Camera,
+    cpu_sampler::MAX_SAMPLES,
     file_picker::FilePicker,
+    mapping::{MappingMode, SamplePattern},
     raytracer::RayTraceInfo,
     shape::{Quad, Shape, Sphere, VWPanel},
     texture::{self, Texture},
@@ -25,6 +27,9 @@ const LEFT: &[u8] = include_bytes!("../resources/skybox/left.jpg");
 const RIGHT: &[u8] = include_bytes!("../resources/skybox/right.jpg");
 const TOP: &[u8] = include_bytes!("../resources/skybox/top.jpg");
 
+/// Panels the shaders sample and display, `array<Panel, 2>` in the WGSL
+pub const GPU_PANELS: usize = 2;
+
 /*
 TODO: SCENE ONLY USES QUAD, MIGHT WANT MORE?
 Scene struct. Encapsulates UI and handles access to the raw quads
@@ -33,6 +38,11 @@ pub struct Scene {
     pub world: Target,
     pub sphere: SphereHolder,
     pub panels: Vec<ScenePanel>,
+    /// How the CPU sampler spreads rays over the panel pixels, the GPU always takes the nearest
+    pub mapping_mode: MappingMode,
+    /// Rays per target pixel along each axis, more than one is sampled on the CPU
+    pub supersampling: u32,
+    pub sample_pattern: SamplePattern,
     ray_tracer: RayTraceInfo,
     pub target_binds: TargetBinds,
     pub panel_binds: PanelBinds,
@@ -601,6 +611,10 @@ impl Target {
         clone.quad = quad;
         clone
     }
+    /// Quad of the target as it is placed in the world
+    pub fn placed_quad(&self) -> Quad {
+        self.place_target().quad
+    }
     fn target_to_bytes(&self) -> Vec<u8> {
         let mut output: Vec<u8> = Vec::new();
         let mut writer = std140::Writer::new(&mut output);
@@ -729,6 +743,9 @@ impl Scene {
             sphere,
             world,
             panels,
+            mapping_mode: MappingMode::Nearest,
+            supersampling: 1,
+            sample_pattern: SamplePattern::Stratified,
             ray_tracer,
             target_binds,
             panel_binds,
@@ -849,23 +866,46 @@ impl Scene {
         }
         buffer
     }
-    /// Will always place the closest panel first
+    /// Will always place the closest panel first. The shaders only see the closest
+    /// `GPU_PANELS` of them
     pub fn panels_as_bytes(panels: &[ScenePanel], camera: &Camera) -> [u8; 256] {
         let mut buffer = [0u8; 256];
         let mut writer = Writer::new(&mut buffer[..]);
+        let mut panels = Self::placed_panels(panels, camera);
+        panels.truncate(GPU_PANELS);
+        let _count = writer.write(panels.as_slice()).unwrap();
+        buffer
+    }
+    /// Panels as the shaders see them, placed in the world with the closest panel first
+    pub fn placed_panels(panels: &[ScenePanel], camera: &Camera) -> Vec<VWPanel> {
         let mut panels: Vec<VWPanel> = panels
             .iter()
             .map(|x| x.place_panel())
             .map(|x| x.border_correction())
             .collect();
         panels.sort_by(|x, y| x.distance_compar(y, camera.position));
-        let _count = writer.write(panels.as_slice()).unwrap();
-        buffer
+        panels
     }
     pub fn change_panel_res(&mut self, new_res: usize) {
         let new_res = new_res as u32;
-        self.panels[0].panel.pixel_count = Vector2::new(new_res, new_res);
-        self.panels[1].panel.pixel_count = Vector2::new(new_res, new_res);
+        for panel in self.panels.iter_mut() {
+            panel.panel.pixel_count = Vector2::new(new_res, new_res);
+        }
+    }
+    /// Adds a layer one unit behind the last panel, with the same pixel count
+    pub fn add_panel(&mut self) {
+        let last = self.panels.last().unwrap();
+        let mut place_vec = last.placement.w;
+        place_vec.z -= 1.0;
+        let mut panel = ScenePanel::new(place_vec, self.panels.len() + 1);
+        panel.panel.pixel_count = last.panel.pixel_count;
+        self.panels.push(panel);
+    }
+    /// Removes the last layer, a light field always keeps two panels
+    pub fn remove_panel(&mut self) {
+        if self.panels.len() > 2 {
+            self.panels.pop();
+        }
     }
 }
 
@@ -971,6 +1011,47 @@ impl DrawUI for Scene {
 
             count += 1;
         }
+        egui_winit::egui::Window::new("Panel Stack")
+            .resizable(true)
+            .default_open(false)
+            .show(ctx, |ui| {
+                ui.label(format!("{} attenuation layers", self.panels.len()));
+                if self.panels.len() > GPU_PANELS {
+                    ui.label(format!(
+                        "Sampled on the CPU, the display shows the closest {GPU_PANELS}"
+                    ));
+                }
+                egui::ComboBox::from_label("Ray mapping")
+                    .selected_text(self.mapping_mode.to_string())
+                    .show_ui(ui, |ui| {
+                        for mode in MappingMode::ALL {
+                            ui.selectable_value(&mut self.mapping_mode, mode, mode.to_string());
+                        }
+                    });
+                ui.label("Rays per target pixel side");
+                ui.add(egui::Slider::new(&mut self.supersampling, 1..=MAX_SAMPLES));
+                egui::ComboBox::from_label("Ray pattern")
+                    .selected_text(self.sample_pattern.to_string())
+                    .show_ui(ui, |ui| {
+                        for pattern in SamplePattern::ALL {
+                            ui.selectable_value(
+                                &mut self.sample_pattern,
+                                pattern,
+                                pattern.to_string(),
+                            );
+                        }
+                    });
+                if self.mapping_mode != MappingMode::Nearest || self.supersampling > 1 {
+                    ui.label("Sampled on the CPU");
+                }
+                if ui.button("Add panel").clicked() {
+                    self.add_panel();
+                }
+                let remove = egui::Button::new("Remove panel");
+                if ui.add_enabled(self.panels.len() > 2, remove).clicked() {
+                    self.remove_panel();
+                }
+            });
     }
 }
 impl DrawUI for SphereHolder {
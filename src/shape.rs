@@ -1,7 +1,10 @@
 use std::cmp::Ordering;
 
 use crate::utils::DrawUI;
-use cgmath::{EuclideanSpace, Matrix4, MetricSpace, Point3, Vector2, Vector3, Vector4};
+use cgmath::{
+    EuclideanSpace, InnerSpace, Matrix4, MetricSpace, Point3, Vector2, Vector3, Vector4,
+    VectorSpace,
+};
 use serde::{Deserialize, Serialize};
 use winit::event_loop::EventLoopProxy;
 
@@ -57,8 +60,94 @@ impl Quad {
             + self.c.distance2(point.to_vec())
             + self.d.distance2(point.to_vec())
     }
+
+    /// Ray intersection against triangle ABC (or BCD), same as `intersection_panel` in the shaders.
+    /// Returns the barycentric coordinates (u, v, w) of the hit.
+    pub fn intersect(
+        &self,
+        origin: Vector3<f32>,
+        direction: Vector3<f32>,
+        abc: bool,
+    ) -> Option<Vector3<f32>> {
+        let (a, b, c) = if abc {
+            (self.a, self.b, self.c)
+        } else {
+            (self.b, self.c, self.d)
+        };
+        let e1 = b - a;
+        let e2 = c - a;
+        let ray_cross_e2 = e2.cross(direction);
+        let det = ray_cross_e2.dot(e1);
+
+        if det > -INTERSECTION_EPS && det < INTERSECTION_EPS {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = origin - a;
+        let u = inv_det * ray_cross_e2.dot(s);
+
+        if (u < 0.0 && u.abs() > INTERSECTION_EPS)
+            || (u > 1.0 && (u - 1.0).abs() > INTERSECTION_EPS)
+        {
+            return None;
+        }
+        let s_cross_e1 = e1.cross(s);
+        let v = inv_det * s_cross_e1.dot(direction);
+        let w = 1.0 - v - u;
+
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = inv_det * s_cross_e1.dot(e2);
+        if t > INTERSECTION_EPS {
+            Some(Vector3::new(u, v, w))
+        } else {
+            None
+        }
+    }
+
+    /// Texture coordinates (0 to 1, origin at A) where the ray crosses the quad.
+    /// A ray on the diagonal hits both triangles, BCD is recorded last and wins.
+    pub fn texture_hit(
+        &self,
+        origin: Vector3<f32>,
+        direction: Vector3<f32>,
+    ) -> Option<Vector2<f32>> {
+        // Texture coordinates of the triangle corners, in order of the barycentric coordinates
+        let hit = |abc: bool, tex_coords: [(f32, f32); 3]| {
+            let bary = self.intersect(origin, direction, abc)?;
+            let x = bary.x * tex_coords[0].0 + bary.y * tex_coords[1].0 + bary.z * tex_coords[2].0;
+            let y = bary.x * tex_coords[0].1 + bary.y * tex_coords[1].1 + bary.z * tex_coords[2].1;
+            Some(Vector2::new(x, y))
+        };
+        let abc = hit(true, [(1.0, 0.0), (0.0, 1.0), (0.0, 0.0)]);
+        let bcd = hit(false, [(0.0, 1.0), (1.0, 1.0), (1.0, 0.0)]);
+        bcd.or(abc)
+    }
+
+    /// World location of the center of a pixel, same as `pixel_to_world_location` in the shaders
+    pub fn pixel_to_world(&self, pixel_count: Vector2<u32>, pixel: Vector2<u32>) -> Vector3<f32> {
+        let center = Vector2::new(pixel.x as f32 + 0.5, pixel.y as f32 + 0.5);
+        self.position_to_world(pixel_count, center)
+    }
+
+    /// World location of a point given in pixels from corner A, pixel centers are at 0.5
+    pub fn position_to_world(
+        &self,
+        pixel_count: Vector2<u32>,
+        position: Vector2<f32>,
+    ) -> Vector3<f32> {
+        let x_relative = position.x / pixel_count.x as f32;
+        let y_relative = position.y / pixel_count.y as f32;
+        let p = self.a.lerp(self.b, x_relative);
+        let q = self.c.lerp(self.d, x_relative);
+        p.lerp(q, y_relative)
+    }
 }
 
+/// Same epsilon as the shaders use for ray intersections
+const INTERSECTION_EPS: f32 = 0.00001;
+
 #[derive(crevice::std140::AsStd140, Clone, Serialize, Deserialize)]
 pub struct Sphere {
     pub position: Vector3<f32>,
@@ -174,4 +263,23 @@ impl VWPanel {
     pub fn distance_to(&self, point: Point3<f32>) -> f32 {
         self.quad.distance_to(point)
     }
+
+    /// Texture coordinates (0 to 1) where the ray crosses the panel
+    pub fn texture_hit(
+        &self,
+        origin: Vector3<f32>,
+        direction: Vector3<f32>,
+    ) -> Option<Vector2<f32>> {
+        self.quad.texture_hit(origin, direction)
+    }
+
+    /// Pixel of the panel the ray goes through, mirrors `pixel_hit` in the shaders.
+    pub fn pixel_hit(&self, origin: Vector3<f32>, direction: Vector3<f32>) -> Option<Vector2<u32>> {
+        let relative = self.texture_hit(origin, direction)?;
+        // Saturating cast, like u32() in WGSL
+        Some(Vector2::new(
+            (relative.x * self.pixel_count.x as f32) as u32,
+            (relative.y * self.pixel_count.y as f32) as u32,
+        ))
+    }
 }
@@ -0,0 +1,228 @@
+use faer::{unzip, zip, Mat};
+use serde::{Deserialize, Serialize};
+
+use crate::{capture::CaptureKind, scalar::Scalar};
+
+/// Terms for updating one panel while the other one is held fixed.
+/// For the objective `0.5 * |l - (M_a a) * (M_b b)|^2` the gradient is `denominator - numerator`.
+pub struct UpdateTerms<'a, T: Scalar = f32> {
+    /// Layer of the panel, 0 is the closest to the observers
+    pub layer: usize,
+    /// `M^T (other * l)`
+    pub numerator: &'a Mat<T>,
+    /// `M^T (other * estimate)`, `M^T (other^2 * M x)` for a single frame
+    pub denominator: &'a Mat<T>,
+    /// `M^T other^2`, the diagonal of the Hessian. Only filled if the strategy asks for it
+    pub hessian: Option<&'a Mat<T>>,
+}
+
+/// Update rule used by both `Lff` implementations for a single panel
+pub trait SolverStrategy<T: Scalar = f32>: Sync + Send {
+    /// If `UpdateTerms::hessian` has to be computed
+    fn needs_hessian(&self) -> bool {
+        false
+    }
+    /// Updates `panel` in place. `objective` evaluates the objective for a candidate panel
+    fn update(&self, panel: &mut Mat<T>, terms: &UpdateTerms<T>, objective: &dyn Fn(&Mat<T>) -> T);
+}
+
+/// Lee–Seung style multiplicative update, clamped to 1
+pub struct Multiplicative<T: Scalar = f32> {
+    /// Added to the denominator of the closest layer, then of every other layer
+    pub epsilon: (T, T),
+}
+
+impl<T: Scalar> SolverStrategy<T> for Multiplicative<T> {
+    fn update(&self, panel: &mut Mat<T>, terms: &UpdateTerms<T>, objective: &dyn Fn(&Mat<T>) -> T) {
+        let _ = objective;
+        let epsilon = if terms.layer == 0 {
+            self.epsilon.0
+        } else {
+            self.epsilon.1
+        };
+        zip!(panel, terms.numerator, terms.denominator)
+            .for_each(|unzip!(x, n, d)| *x = T::ONE.min(*x * *n / (*d + epsilon)));
+    }
+}
+
+/// Gradient step projected onto [0, 1], with an Armijo backtracking line search
+pub struct ProjectedGradient {
+    pub sufficient_decrease: f32,
+    pub max_backtracks: usize,
+}
+
+impl<T: Scalar> SolverStrategy<T> for ProjectedGradient {
+    fn needs_hessian(&self) -> bool {
+        true
+    }
+    fn update(&self, panel: &mut Mat<T>, terms: &UpdateTerms<T>, objective: &dyn Fn(&Mat<T>) -> T) {
+        let hessian = terms.hessian.expect("Projected gradient needs the Hessian");
+        let gradient = zip!(terms.denominator, terms.numerator).map(|unzip!(d, n)| *d - *n);
+
+        // Start from the inverse of the mean curvature, larger than the safe 1 / max
+        let mean_curvature =
+            hessian.sum() / T::from_usize((hessian.nrows() * hessian.ncols()).max(1));
+        if mean_curvature <= T::ZERO {
+            return;
+        }
+        let mut step = T::ONE / mean_curvature;
+        let sufficient_decrease = T::from_f32(self.sufficient_decrease);
+        let half = T::from_f32(0.5);
+
+        let current = objective(panel);
+        for _ in 0..self.max_backtracks {
+            let candidate = zip!(&*panel, &gradient)
+                .map(|unzip!(x, g)| (*x - step * *g).clamp(T::ZERO, T::ONE));
+            let mut decrease = T::ZERO;
+            zip!(&candidate, &*panel, &gradient)
+                .for_each(|unzip!(c, x, g)| decrease += *g * (*c - *x));
+
+            if objective(&candidate) <= current + sufficient_decrease * decrease {
+                *panel = candidate;
+                return;
+            }
+            step *= half;
+        }
+    }
+}
+
+/// Every ray crosses a single pixel of each panel, so with the other panel fixed the normal
+/// equations are diagonal and each pixel is solved exactly (a Newton step), then clamped to
+/// [0, 1]. For a single frame the multiplicative rule reaches the same point, up to its epsilon.
+pub struct AlternatingLeastSquares<T: Scalar = f32> {
+    pub epsilon: T,
+}
+
+impl<T: Scalar> SolverStrategy<T> for AlternatingLeastSquares<T> {
+    fn needs_hessian(&self) -> bool {
+        true
+    }
+    fn update(&self, panel: &mut Mat<T>, terms: &UpdateTerms<T>, objective: &dyn Fn(&Mat<T>) -> T) {
+        let _ = objective;
+        let hessian = terms
+            .hessian
+            .expect("Alternating least squares needs the Hessian");
+        zip!(panel, terms.numerator, terms.denominator, hessian).for_each(|unzip!(x, n, d, h)| {
+            // Pixels no ray goes through keep their value
+            if *h > self.epsilon {
+                *x = (*x - (*d - *n) / *h).clamp(T::ZERO, T::ONE);
+            }
+        });
+    }
+}
+
+/// Selectable strategies, for `LFSettings` and the command line
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum SolverKind {
+    #[default]
+    Multiplicative,
+    ProjectedGradient,
+    LeastSquares,
+}
+
+impl SolverKind {
+    pub const ALL: [SolverKind; 3] = [
+        SolverKind::Multiplicative,
+        SolverKind::ProjectedGradient,
+        SolverKind::LeastSquares,
+    ];
+
+    /// Strategy in the precision of the solve, with the epsilons of that precision. Only the
+    /// separable solver gives the layers behind the closest a smaller epsilon
+    pub fn strategy<T: Scalar>(&self, kind: CaptureKind) -> Box<dyn SolverStrategy<T>> {
+        let (closest, other) = T::DENOMINATOR_EPSILON;
+        match self {
+            SolverKind::Multiplicative => Box::new(Multiplicative {
+                epsilon: match kind {
+                    CaptureKind::Separable => (closest, other),
+                    CaptureKind::Stereo => (closest, closest),
+                },
+            }),
+            SolverKind::ProjectedGradient => Box::new(ProjectedGradient {
+                sufficient_decrease: 0.0001,
+                max_backtracks: 20,
+            }),
+            SolverKind::LeastSquares => Box::new(AlternatingLeastSquares { epsilon: closest }),
+        }
+    }
+}
+
+impl std::fmt::Display for SolverKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Panel of a single pixel seen by two rays, `other` is held at 0.5 on both
+    fn terms(x: f32, l: [f32; 2]) -> (Mat<f32>, Mat<f32>, Mat<f32>) {
+        let other = 0.5f32;
+        let numerator = Mat::from_fn(1, 1, |_, _| other * (l[0] + l[1]));
+        let denominator = Mat::from_fn(1, 1, |_, _| 2.0 * other * other * x);
+        let hessian = Mat::from_fn(1, 1, |_, _| 2.0 * other * other);
+        (numerator, denominator, hessian)
+    }
+
+    fn objective(l: [f32; 2]) -> impl Fn(&Mat<f32>) -> f32 {
+        move |x| 0.5 * l.iter().map(|l| (l - x[(0, 0)] * 0.5).powi(2)).sum::<f32>()
+    }
+
+    #[test]
+    fn strategies_decrease_objective() {
+        let l = [0.2, 0.3];
+        // Least squares optimum is (0.2 + 0.3) / 2 / 0.5
+        let optimum = 0.5;
+        for kind in SolverKind::ALL {
+            let strategy = kind.strategy::<f32>(CaptureKind::Stereo);
+            let mut panel = Mat::from_fn(1, 1, |_, _| 0.9f32);
+            let (numerator, denominator, hessian) = terms(panel[(0, 0)], l);
+            let before = objective(l)(&panel);
+            strategy.update(
+                &mut panel,
+                &UpdateTerms {
+                    layer: 0,
+                    numerator: &numerator,
+                    denominator: &denominator,
+                    hessian: Some(&hessian),
+                },
+                &objective(l),
+            );
+            assert!(objective(l)(&panel) < before, "{kind} did not improve");
+            if kind == SolverKind::LeastSquares {
+                assert!((panel[(0, 0)] - optimum).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn multiplicative_epsilon_depends_on_the_layer() {
+        // A pixel no ray sees, only the epsilon keeps the update finite
+        let numerator = Mat::from_fn(1, 1, |_, _| 1e-9f32);
+        let denominator = Mat::zeros(1, 1);
+        // The separable solver adds 1e-7 to the closest layer and 1e-9 to the others, the
+        // stereo one 1e-7 to every layer
+        for (kind, expected) in [
+            (CaptureKind::Separable, [1e-2, 1.0, 1.0]),
+            (CaptureKind::Stereo, [1e-2, 1e-2, 1e-2]),
+        ] {
+            let strategy = SolverKind::Multiplicative.strategy::<f32>(kind);
+            for (layer, expected) in expected.into_iter().enumerate() {
+                let mut panel = Mat::from_fn(1, 1, |_, _| 1.0f32);
+                let terms = UpdateTerms {
+                    layer,
+                    numerator: &numerator,
+                    denominator: &denominator,
+                    hessian: None,
+                };
+                strategy.update(&mut panel, &terms, &|_| 0.0);
+                assert!(
+                    (panel[(0, 0)] - expected).abs() < 1e-6,
+                    "{kind:?} layer {layer}"
+                );
+            }
+        }
+    }
+}
@@ -2,11 +2,13 @@ use std::time::Instant;
 
 use cgmath::Vector2;
 use egui::Ui;
-use faer::sparse::{SparseColMat, Triplet};
 use faer::Mat;
-use image::DynamicImage;
 use wgpu::{util::DeviceExt, Buffer};
 
+use crate::capture::CaptureKind;
+use crate::initialization::save_panels;
+use crate::progress::BackgroundSolve;
+use crate::report::SolveReport;
 use crate::utils::DrawUI;
 use crate::*;
 
@@ -14,13 +16,63 @@ pub struct StereoscopeBuffer {
     l_buffer: Buffer,
     a_buffer: Buffer,
     b_buffer: Buffer,
+    /// Colour of every ray when factorizing in colour, a single entry otherwise
+    rgb_buffer: Buffer,
+    /// Entries of `rgb_buffer`
+    rgb_rays: u32,
+    /// Rays the buffers were last prepared for
+    prepared_rays: Option<u32>,
 
     pub bind_group_layout: wgpu::BindGroupLayout,
     pub bind_group: wgpu::BindGroup,
-    matrix_rep: Option<StereoMatrix>,
+    /// Scene of the last sampling, written into the header of saved captures
+    pub capture_metadata: crate::capture::CaptureMetadata,
+    /// Failed save of the matrix capture, shown by the app as a toast
+    pub save_error: Option<crate::error::Error>,
+    /// Capture too large for the buffers, shown by the app as a toast
+    pub sample_error: Option<crate::error::Error>,
+    /// Solve that could not start, shown by the app as a toast
+    pub solve_error: Option<crate::error::Error>,
+    pub matrix_rep: Option<StereoMatrix>,
+    /// Solve started from the UI, running on its own thread
+    pub background_solve: Option<BackgroundSolve>,
     settings: crate::LFSettings,
 }
 const BUFFER_SIZE: usize = 6000 * 6000 * 4 * 10;
+/// Bytes of the colour of one ray, a vec4 of f32
+const RGB_BYTES: u64 = 4 * 4;
+
+fn rgb_buffer(device: &wgpu::Device, rays: u32) -> Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("RGB buffer"),
+        size: rays as u64 * RGB_BYTES,
+        usage: wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::MAP_READ
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
+
+fn bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    buffers: [&Buffer; 4],
+) -> wgpu::BindGroup {
+    let entries: Vec<wgpu::BindGroupEntry> = buffers
+        .iter()
+        .enumerate()
+        .map(|(binding, buffer)| wgpu::BindGroupEntry {
+            binding: binding as u32,
+            resource: buffer.as_entire_binding(),
+        })
+        .collect();
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Bind group for Sampler"),
+        layout,
+        entries: &entries,
+    })
+}
 
 impl StereoscopeBuffer {
     pub fn set_up(device: &wgpu::Device) -> Self {
@@ -38,12 +90,19 @@ impl StereoscopeBuffer {
         // Bind group implements copy, so we are actually copying it around
         let mut layout_entry_1 = layout_entry_0;
         let mut layout_entry_2 = layout_entry_0;
+        let mut layout_entry_3 = layout_entry_0;
         layout_entry_1.binding = 1;
         layout_entry_2.binding = 2;
+        layout_entry_3.binding = 3;
 
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("LFFactorizer Bind group layout"),
-            entries: &[layout_entry_0, layout_entry_1, layout_entry_2],
+            entries: &[
+                layout_entry_0,
+                layout_entry_1,
+                layout_entry_2,
+                layout_entry_3,
+            ],
         });
 
         let a_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -79,24 +138,12 @@ impl StereoscopeBuffer {
                 | wgpu::BufferUsages::COPY_SRC,
         });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Bind group for Sampler"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: a_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: b_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: l_buffer.as_entire_binding(),
-                },
-            ],
-        });
+        let rgb_buffer = rgb_buffer(device, 1);
+        let bind_group = bind_group(
+            device,
+            &bind_group_layout,
+            [&a_buffer, &b_buffer, &l_buffer, &rgb_buffer],
+        );
         let settings = LFSettings {
             debug_prints: false,
             ..Default::default()
@@ -105,20 +152,85 @@ impl StereoscopeBuffer {
             a_buffer,
             b_buffer,
             l_buffer,
+            rgb_buffer,
+            rgb_rays: 1,
+            prepared_rays: None,
             bind_group_layout,
             bind_group,
+            capture_metadata: Default::default(),
+            save_error: None,
+            sample_error: None,
+            solve_error: None,
             matrix_rep: None,
+            background_solve: None,
             settings,
         }
     }
 
-    pub fn build_l(&self, device: &wgpu::Device, rays_cast: u32) -> Mat<f32> {
-        let raw_bytes = utils::sample_buffer(&self.l_buffer, device);
-        let entries: Vec<f32> = raw_bytes[0..(rays_cast * 4) as usize]
-            .chunks(4)
-            .map(|x| f32::from_ne_bytes(x[0..4].try_into().unwrap()))
-            .collect();
-        Mat::from_fn(rays_cast as usize, 1, |x, _y| entries[x])
+    /// Sizes the buffers for a pass casting `rays_cast` rays, before it is dispatched. Every
+    /// ray records its luma, and its colour as well when factorizing in colour.
+    pub fn prepare(&mut self, device: &wgpu::Device, rays_cast: u32) -> error::Result<()> {
+        self.prepared_rays = None;
+        let rays = rays_cast as u64;
+        let luma = 4 * rays;
+        if luma > BUFFER_SIZE as u64 {
+            return Err(error::Error::RayBuffers {
+                rays,
+                bytes: luma,
+                limit: BUFFER_SIZE as u64,
+            });
+        }
+        let rgb_rays = if self.settings.colour {
+            rays_cast.max(1)
+        } else {
+            1
+        };
+        let limits = device.limits();
+        let limit = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+        if rgb_rays as u64 * RGB_BYTES > limit {
+            return Err(error::Error::RayBuffers {
+                rays,
+                bytes: rgb_rays as u64 * RGB_BYTES,
+                limit,
+            });
+        }
+        if rgb_rays != self.rgb_rays {
+            self.rgb_buffer = rgb_buffer(device, rgb_rays);
+            self.rgb_rays = rgb_rays;
+            self.bind_group = bind_group(
+                device,
+                &self.bind_group_layout,
+                [
+                    &self.a_buffer,
+                    &self.b_buffer,
+                    &self.l_buffer,
+                    &self.rgb_buffer,
+                ],
+            );
+        }
+        self.prepared_rays = Some(rays_cast);
+        Ok(())
+    }
+
+    /// Reads back the luma sampled by every ray, and its colour if the buffers were prepared
+    /// for it. Without colour `l_rgb` is empty and colour solves fall back to luma.
+    pub fn build_l(&self, device: &wgpu::Device, rays_cast: u32) -> (Mat<f32>, Mat<f32>) {
+        let floats = |buffer: &Buffer, count: u32| -> Vec<f32> {
+            utils::sample_buffer(buffer, device)[0..(count * 4) as usize]
+                .chunks(4)
+                .map(|x| f32::from_ne_bytes(x[0..4].try_into().unwrap()))
+                .collect()
+        };
+        let luma = floats(&self.l_buffer, rays_cast);
+        let l_vec = Mat::from_fn(rays_cast as usize, 1, |x, _y| luma[x]);
+        let l_rgb = if self.rgb_rays == rays_cast {
+            // Every ray records a vec4 (rgba)
+            let entries = floats(&self.rgb_buffer, rays_cast * 4);
+            Mat::from_fn(rays_cast as usize, 3, |x, y| entries[x * 4 + y])
+        } else {
+            Mat::zeros(0, 3)
+        };
+        (l_vec, l_rgb)
         // TODO:
         // Debug print to check for sanity (is it being sampled correctly)
     }
@@ -128,21 +240,14 @@ impl StereoscopeBuffer {
         device: &wgpu::Device,
         rays_cast: u32,
         panel_size: (u32, u32),
-    ) -> SparseColMat<u32, f32> {
+    ) -> IndexMapping {
         // Build triplets
         // Build Matrix from Triplets
         let rows = rays_cast;
         let columns = panel_size.0 * panel_size.1;
 
         let vec_a = utils::buffer_to_sparse_triplet(&self.a_buffer, device, rays_cast);
-        let mut triplets = vec_a
-            .into_iter()
-            .enumerate()
-            .map(|(index, entry)| Triplet::new(index as u32, entry, 1.0f32))
-            .collect();
-        utils::check_triplets(rows, columns, &mut triplets);
-
-        SparseColMat::try_new_from_triplets(rows as usize, columns as usize, &triplets).unwrap()
+        utils::build_ray_mapping(vec_a, rows, columns)
     }
 
     pub fn build_m_b(
@@ -150,21 +255,14 @@ impl StereoscopeBuffer {
         device: &wgpu::Device,
         rays_cast: u32,
         panel_size: (u32, u32),
-    ) -> SparseColMat<u32, f32> {
+    ) -> IndexMapping {
         // Build triplets
         // Build Matrix from Triplets
         let rows = rays_cast;
         let columns = panel_size.0 * panel_size.1;
 
         let vec_b = utils::buffer_to_sparse_triplet(&self.b_buffer, device, rays_cast);
-        let mut triplets = vec_b
-            .into_iter()
-            .enumerate()
-            .map(|(index, entry)| Triplet::new(index as u32, entry, 1.0f32))
-            .collect();
-        utils::check_triplets(rows, columns, &mut triplets);
-
-        SparseColMat::try_new_from_triplets(rows as usize, columns as usize, &triplets).unwrap()
+        utils::build_ray_mapping(vec_b, rows, columns)
     }
     pub fn sample_light_field(
         &mut self,
@@ -177,20 +275,21 @@ impl StereoscopeBuffer {
         let rays_cast = target_size.0 * target_size.1 * number_of_view_points;
         let panel_a_size = (pixel_count_a.x, pixel_count_a.y);
         let panel_b_size = (pixel_count_b.x, pixel_count_b.y);
-        let l_vec = self.build_l(device, rays_cast);
-        let ray_total_memory = 4 * rays_cast;
-        if ray_total_memory as usize > BUFFER_SIZE {
-            panic!("Cannot store the results of all rays in allocated buffers");
+        // `prepare` already reported why the rays did not fit
+        if self.prepared_rays != Some(rays_cast) {
+            self.matrix_rep = None;
+            return;
         }
+        let (l_vec, l_rgb) = self.build_l(device, rays_cast);
 
-        let a_matrix = self.build_m_a(device, rays_cast, panel_a_size).into();
-        let b_matrix = self.build_m_b(device, rays_cast, panel_b_size).into();
+        // The shader samples the two closest panels
+        let a_matrix = self.build_m_a(device, rays_cast, panel_a_size);
+        let b_matrix = self.build_m_b(device, rays_cast, panel_b_size);
         let stereo = StereoMatrix {
-            panel_a_size: (pixel_count_a.x, pixel_count_a.y),
-            panel_b_size: (pixel_count_b.x, pixel_count_b.y),
             l_vec,
-            a_matrix,
-            b_matrix,
+            l_rgb,
+            layers: vec![a_matrix, b_matrix],
+            layer_sizes: vec![panel_a_size, panel_b_size],
             target_size,
             number_of_view_points,
         };
@@ -203,8 +302,44 @@ impl StereoscopeBuffer {
     pub fn will_solve(&self) -> bool {
         self.settings.solve_next_redraw_flag
     }
-    pub fn factorize_stereo(&self) -> Option<(DynamicImage, DynamicImage, Option<Vec<f32>>)> {
-        self.matrix_rep.as_ref()?.factorize(&self.settings)
+    pub fn set_parallelism(&mut self, parallelism: Parallelism) {
+        self.settings.parallelism = parallelism;
+    }
+    /// Every layer of every frame when solving for more than one frame
+    pub fn factorize_stereo(&self) -> error::Result<Option<SolveReport>> {
+        match &self.matrix_rep {
+            Some(rep) => rep.factorize(&self.settings, &()),
+            None => Ok(None),
+        }
+    }
+    /// Starts `factorize_stereo` on its own thread, replacing any running solve
+    pub fn start_background_solve(&mut self) {
+        if let Some(rep) = &self.matrix_rep {
+            self.background_solve =
+                Some(BackgroundSolve::spawn(rep.clone(), self.settings.clone()));
+        }
+    }
+    /// Solution of the background solve once it is done. A solve that could not start leaves
+    /// its error in `solve_error`.
+    pub fn finished_background_solve(&mut self) -> Option<Option<SolveReport>> {
+        let solution = self.background_solve.as_mut()?.try_finish()?;
+        self.background_solve = None;
+        let solution = match solution {
+            Ok(solution) => solution,
+            Err(err) => {
+                self.solve_error = Some(err);
+                return None;
+            }
+        };
+        // Kept with the settings that solved them, the other approach can start from them
+        if let Some(report) = &solution {
+            let name = self.settings.save_to.clone();
+            let frames = &report.frames;
+            if let Err(err) = save_panels(CaptureKind::Stereo, name, frames, &self.settings) {
+                self.save_error = Some(err);
+            }
+        }
+        Some(solution)
     }
 }
 
@@ -225,9 +360,16 @@ impl DrawUI for StereoscopeBuffer {
 
                 if ui.button("Save").clicked() {
                     if let Some(rep) = &self.matrix_rep {
-                        rep.save(self.settings.save_to.clone());
+                        if let Err(err) =
+                            rep.save(self.settings.save_to.clone(), &self.capture_metadata)
+                        {
+                            self.save_error = Some(err);
+                        }
                     }
                 }
+                if let Some(solve) = &self.background_solve {
+                    solve.draw_ui(ui);
+                }
                 self.settings.draw_ui(ctx, Some(title), Some(ui));
             });
     }
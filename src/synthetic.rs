@@ -0,0 +1,266 @@
+//! Small problems with a known exact solution, for testing the solvers without a scene, and
+//! small scenes sampled on the CPU
+
+use cgmath::{Matrix4, Vector2, Vector3};
+use faer::{
+    stats::prelude::{Rng, SeedableRng, StdRng},
+    unzip, zip, Mat,
+};
+
+use image::{DynamicImage, Rgb, RgbImage};
+
+use crate::{
+    cpu_sampler::CpuSampler,
+    mapping::IndexMapping,
+    scalar::Scalar,
+    shape::{Quad, Shape, VWPanel},
+    solver::SolverKind,
+    CompleteMapping, LFMatrices, LFSettings, Lff, MappingMatrix, StereoMatrix,
+};
+
+/// Target at the origin, panels at every depth, closest to the observers first
+pub(crate) fn stacked_sampler(
+    depths: &[f32],
+    target_pixels: u32,
+    panel_pixels: u32,
+    observers: Vec<Vector3<f32>>,
+) -> CpuSampler {
+    let target = Quad::new(
+        Vector3::new(-0.5, 0.5, 0.0),
+        Vector3::new(0.5, 0.5, 0.0),
+        Vector3::new(-0.5, -0.5, 0.0),
+        Vector3::new(0.5, -0.5, 0.0),
+    );
+    let panels = depths
+        .iter()
+        .map(|z| {
+            let mut panel = VWPanel::demo_panel();
+            panel.pixel_count = Vector2::new(panel_pixels, panel_pixels);
+            panel.place(&Matrix4::from_translation(Vector3::new(0.0, 0.0, *z)))
+        })
+        .collect();
+    CpuSampler::from_parts(
+        target,
+        Vector2::new(target_pixels, target_pixels),
+        panels,
+        observers,
+    )
+}
+
+/// Two panels seen by one observer in front of a colour gradient, small enough to solve in
+/// a few milliseconds
+pub(crate) fn gradient_scene() -> (CpuSampler, DynamicImage) {
+    let sampler = stacked_sampler(&[2.0, 1.0], 16, 12, vec![Vector3::new(0.1, 0.2, 4.0)]);
+    let image = DynamicImage::ImageRgb8(RgbImage::from_fn(16, 16, |x, y| {
+        Rgb([(x * 16) as u8, (y * 16) as u8, 128])
+    }));
+    (sampler, image)
+}
+
+/// Same problems on every run
+pub(crate) fn rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+/// Panel with every pixel in `[low, 1]`
+pub(crate) fn random_panel<T: Scalar>(
+    rng: &mut StdRng,
+    rows: usize,
+    cols: usize,
+    low: f64,
+) -> Mat<T> {
+    Mat::from_fn(rows, cols, |_, _| T::from_f64(rng.gen_range(low..=1.0)))
+}
+
+/// Every one of the `rays` hits one of the first `hit` of the `ncols` pixels, the rest are
+/// never seen
+pub(crate) fn random_mapping<T: Scalar>(
+    rng: &mut StdRng,
+    rays: usize,
+    hit: usize,
+    ncols: usize,
+) -> IndexMapping<T> {
+    let hits = (0..rays).map(|_| rng.gen_range(0..hit) as u32).collect();
+    IndexMapping::from_hits(hits, ncols)
+}
+
+/// Separable mapping of a `size` panel from `views` view points of `rays` (rows, columns),
+/// only the first `hit` rows and columns are seen
+pub(crate) fn random_complete_mapping<T: Scalar>(
+    rng: &mut StdRng,
+    views: usize,
+    rays: (usize, usize),
+    hit: (usize, usize),
+    size: (usize, usize),
+) -> CompleteMapping<T> {
+    let mut matrices = |rays, hit, ncols| {
+        MappingMatrix::new(
+            (0..views)
+                .map(|_| random_mapping(rng, rays, hit, ncols))
+                .collect(),
+        )
+    };
+    let x = matrices(rays.1, hit.1, size.1);
+    let y = matrices(rays.0, hit.0, size.0);
+    CompleteMapping::new(x, y, (size.0 as u32, size.1 as u32))
+}
+
+/// Separable capture of a random colour target from `views` view points of `rays` (rows,
+/// columns) each, through panels of `sizes` (rows, columns) that every ray sees
+pub(crate) fn random_separable<T: Scalar>(
+    seed: u64,
+    sizes: &[(usize, usize)],
+    views: usize,
+    rays: (usize, usize),
+) -> LFMatrices<T> {
+    let mut rng = rng(seed);
+    let layers = sizes
+        .iter()
+        .map(|size| random_complete_mapping(&mut rng, views, rays, *size, *size))
+        .collect();
+    let t = random_complete_mapping(&mut rng, views, rays, rays, rays);
+    let c_t = image::RgbImage::from_fn(rays.1 as u32, rays.0 as u32, |_, _| image::Rgb(rng.gen()));
+    // The solver sizes its per view buffers as (rows, columns) of rays
+    let target_size = (rays.0 as u32, rays.1 as u32);
+    LFMatrices::new(layers, t, c_t.into(), target_size, views as u32)
+}
+
+/// Stereo capture of random panels from `views` view points of `rays` rays each, the
+/// samples are exactly the product of the panels so a zero residual solution exists
+pub(crate) struct Synthetic<T: Scalar = f32> {
+    /// Closest to the observers first, column vectors
+    pub panels: Vec<Mat<T>>,
+    pub capture: StereoMatrix<T>,
+}
+
+impl<T: Scalar> Synthetic<T> {
+    /// Square panels of `sizes` pixels a side, every one in `[low, 1]`
+    pub fn new(seed: u64, sizes: &[usize], views: usize, rays: usize, low: f64) -> Self {
+        let mut rng = rng(seed);
+        let panels: Vec<Mat<T>> = sizes
+            .iter()
+            .map(|size| random_panel(&mut rng, size * size, 1, low))
+            .collect();
+        let layers: Vec<IndexMapping<T>> = sizes
+            .iter()
+            .map(|size| random_mapping(&mut rng, rays * views, size * size, size * size))
+            .collect();
+        let mut l_vec = Mat::from_fn(rays * views, 1, |_, _| T::ONE);
+        for (panel, layer) in panels.iter().zip(&layers) {
+            zip!(&mut l_vec, &layer.gather(panel)).for_each(|unzip!(l, x)| *l *= *x);
+        }
+        let capture = StereoMatrix {
+            l_vec,
+            l_rgb: Mat::zeros(0, 3),
+            layers,
+            layer_sizes: sizes.iter().map(|x| (*x as u32, *x as u32)).collect(),
+            target_size: (rays as u32, 1),
+            number_of_view_points: views as u32,
+        };
+        Synthetic { panels, capture }
+    }
+
+    /// Same panels, with every sample scaled by `factor`
+    pub fn scaled(mut self, factor: f64) -> Self {
+        let factor = T::from_f64(factor);
+        zip!(&mut self.capture.l_vec).for_each(|unzip!(l)| *l *= factor);
+        self
+    }
+
+    /// Panels and residual history of a solve of the capture
+    pub fn solve(&self, settings: &LFSettings) -> (Vec<Mat<f32>>, Vec<f32>) {
+        let settings = LFSettings {
+            save_error: true,
+            debug_prints: false,
+            colour: false,
+            ..settings.clone()
+        };
+        let mut report = self.capture.factorize(&settings, &()).unwrap().unwrap();
+        // The first frame of every layer, of the only channel
+        let panels = report
+            .panels
+            .swap_remove(0)
+            .into_iter()
+            .map(|mut x| x.remove(0));
+        (panels.collect(), report.residuals)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings(solver: SolverKind, iter_count: usize) -> LFSettings {
+        LFSettings {
+            iter_count,
+            solver,
+            filter: false,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn exact_product_has_zero_residual() {
+        let problem = Synthetic::<f64>::new(1, &[4, 3], 3, 20, 0.2);
+        let mut estimate = Mat::from_fn(problem.capture.l_vec.nrows(), 1, |_, _| 1.0);
+        for (panel, layer) in problem.panels.iter().zip(&problem.capture.layers) {
+            zip!(&mut estimate, &layer.gather(panel)).for_each(|unzip!(e, x)| *e *= *x);
+        }
+        assert!((&estimate - &problem.capture.l_vec).norm_max() < 1e-15);
+    }
+
+    #[test]
+    fn solvers_recover_zero_residual() {
+        for seed in 0..3 {
+            let problem = Synthetic::<f64>::new(seed, &[4, 3], 3, 20, 0.2);
+            let target = problem.capture.l_vec.norm_l2() as f32;
+            for kind in SolverKind::ALL {
+                let (_, error) = problem.solve(&settings(kind, 400));
+                let last = *error.last().unwrap();
+                assert!(
+                    last < 1e-3 * target,
+                    "{kind} stopped at {last} of {target} on problem {seed}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn updates_never_increase_residual() {
+        for (seed, sizes, frames) in [(3, &[4, 3][..], 1), (4, &[3, 3, 2], 1), (5, &[4, 3], 2)] {
+            let problem = Synthetic::<f64>::new(seed, sizes, 3, 20, 0.2);
+            for kind in SolverKind::ALL {
+                let settings = LFSettings {
+                    frames,
+                    ..settings(kind, 60)
+                };
+                let (_, error) = problem.solve(&settings);
+                assert_eq!(error.len(), 60);
+                for (iteration, pair) in error.windows(2).enumerate() {
+                    assert!(
+                        pair[1] <= pair[0] * (1.0 + 1e-6),
+                        "{kind} went from {} to {} after iteration {iteration} of problem {seed}",
+                        pair[0],
+                        pair[1]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn panels_stay_clamped() {
+        // No panel in [0, 1] reaches samples of up to 2
+        let problem = Synthetic::<f32>::new(6, &[4, 3], 3, 20, 0.5).scaled(2.0);
+        for kind in SolverKind::ALL {
+            let (panels, _) = problem.solve(&settings(kind, 30));
+            assert_eq!(panels.len(), 2);
+            for panel in &panels {
+                assert!(
+                    panel.col(0).iter().all(|x| (0.0..=1.0).contains(x)),
+                    "{kind} left [0, 1]"
+                );
+            }
+        }
+    }
+}
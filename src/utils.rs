@@ -1,10 +1,7 @@
 use std::{iter::zip, time::Instant};
 
 use cgmath::Vector2;
-use egui::{
-    ahash::{HashSet, HashSetExt},
-    Context, Ui,
-};
+use egui::{ahash::HashSet, Context, Ui};
 use faer::{
     sparse::{SparseColMat, SparseRowMat, Triplet},
     Col, ColRef, Mat, MatMut, MatRef, Row, RowRef,
@@ -13,7 +10,7 @@ use image::{DynamicImage, GenericImageView, ImageBuffer};
 use rayon::iter::{IndexedParallelIterator, ParallelIterator};
 use wgpu::Buffer;
 
-use crate::{CompleteMapping, FileWatcher, MappingMatrix};
+use crate::{mapping::IndexMapping, scalar::Scalar, CompleteMapping, FileWatcher, MappingMatrix};
 
 pub fn sample_buffer(sample_buffer: &Buffer, device: &wgpu::Device) -> Vec<u8> {
     let buffer_slice = sample_buffer.slice(..);
@@ -129,10 +126,16 @@ pub fn image_to_matrix(image: &DynamicImage) -> Mat<f32> {
             assert!(*x <= 1.0, "Pixel value is {x}");
         }
 
-        pixel[0] * 0.299 + 0.587 * pixel[1] + 0.114 * pixel[2]
+        luma([pixel[0], pixel[1], pixel[2]])
     })
 }
 
+/// 8 bit value of a pixel in [0, 1], rounded so pixels read from an image are written back as
+/// they were
+fn channel_byte(value: f32) -> u8 {
+    (value * 255.0).round() as u8
+}
+
 pub fn matrix_to_image(mat: &Mat<f32, usize, usize>) -> DynamicImage {
     let (height, width) = mat.shape();
     let image_buffer = ImageBuffer::from_par_fn(width as u32, height as u32, |x, y| {
@@ -141,9 +144,9 @@ pub fn matrix_to_image(mat: &Mat<f32, usize, usize>) -> DynamicImage {
         assert!(value <= 1.0, "Pixel value is {x}");
 
         image::Rgba::<u8>([
-            (value * 255.0) as u8,
-            (value * 255.0) as u8,
-            (value * 255.0) as u8,
+            channel_byte(value),
+            channel_byte(value),
+            channel_byte(value),
             (255.0) as u8,
         ])
     });
@@ -153,15 +156,15 @@ pub fn matrix_to_image(mat: &Mat<f32, usize, usize>) -> DynamicImage {
 pub fn vector_to_image(mat: &Mat<f32, usize, usize>, height: u32, width: u32) -> DynamicImage {
     assert!(mat.shape().1 <= 1, "This vector has more than 1 Column?");
     let image_buffer = ImageBuffer::from_par_fn(width, height, |x, y| {
-        // Assuming the image is
-        let coordinate = x + (y * height);
+        // Panels are vectorized row by row, see record_hit_Theta_A
+        let coordinate = x + (y * width);
 
         let value = mat[(coordinate as usize, 0)];
 
         image::Rgba::<u8>([
-            (value * 255.0) as u8,
-            (value * 255.0) as u8,
-            (value * 255.0) as u8,
+            channel_byte(value),
+            channel_byte(value),
+            channel_byte(value),
             (255.0) as u8,
         ])
     });
@@ -169,6 +172,56 @@ pub fn vector_to_image(mat: &Mat<f32, usize, usize>, height: u32, width: u32) ->
     DynamicImage::ImageRgba8(image_buffer)
 }
 
+/// Split an image into its red, green and blue channels, each as a matrix in [0, 1].
+pub fn image_to_channels(image: &DynamicImage) -> [Mat<f32>; 3] {
+    let rows = image.height() as usize;
+    let column = image.width() as usize;
+    let image = image.to_rgba8();
+
+    [0, 1, 2].map(|channel| {
+        Mat::from_fn(rows, column, |x, y| {
+            image.get_pixel(y as u32, x as u32).0[channel] as f32 / 255.0
+        })
+    })
+}
+
+/// Inverse of [`image_to_channels`]: write one matrix per channel into an RGBA image.
+pub fn channels_to_image(channels: &[Mat<f32>; 3]) -> DynamicImage {
+    let (height, width) = channels[0].shape();
+    let image_buffer = ImageBuffer::from_par_fn(width as u32, height as u32, |x, y| {
+        let [r, g, b] = channels.each_ref().map(|mat| {
+            let value = mat[(y as usize, x as usize)];
+            assert!(value <= 1.0, "Pixel value is {value}");
+            channel_byte(value)
+        });
+
+        image::Rgba::<u8>([r, g, b, 255])
+    });
+    DynamicImage::ImageRgba8(image_buffer)
+}
+
+/// Colour version of [`vector_to_image`], one column vector per channel.
+pub fn vectors_to_image(channels: &[Mat<f32>; 3], height: u32, width: u32) -> DynamicImage {
+    for mat in channels {
+        assert!(mat.shape().1 <= 1, "This vector has more than 1 Column?");
+    }
+    let image_buffer = ImageBuffer::from_par_fn(width, height, |x, y| {
+        let coordinate = (x + (y * width)) as usize;
+        let [r, g, b] = channels
+            .each_ref()
+            .map(|mat| channel_byte(mat[(coordinate, 0)]));
+
+        image::Rgba::<u8>([r, g, b, 255])
+    });
+
+    DynamicImage::ImageRgba8(image_buffer)
+}
+
+/// Luma of a RGB triplet, same weights as `image_to_matrix` and the shaders.
+pub fn luma(rgb: [f32; 3]) -> f32 {
+    rgb[0] * 0.299 + 0.587 * rgb[1] + 0.114 * rgb[2]
+}
+
 pub fn verify_matrix(mat: &Mat<f32>) {
     for col in mat.col_iter() {
         for entry in col.iter() {
@@ -278,6 +331,41 @@ pub fn build_tripltes(
     triplets
 }
 
+/// Build the per view point mapping matrices from the index buffers `diagonal.wgsl` writes.
+/// Every ray maps to the single pixel whose index is stored in the buffer.
+pub fn build_mapping(buffer: Vec<u32>, rays_per_view_point: usize, columns: u32) -> MappingMatrix {
+    let matrix = buffer
+        .chunks(rays_per_view_point)
+        .map(|chunk| IndexMapping::from_hits(chunk.to_vec(), columns as usize))
+        .collect();
+    MappingMatrix { matrix }
+}
+
+/// X and Y mappings of one layer. `size` is (rows, columns) for panels, for the target
+/// the columns come first (`transposed_size`), matching how `LFBuffers` samples it.
+pub fn build_complete_mapping(
+    buffer_x: Vec<u32>,
+    buffer_y: Vec<u32>,
+    rays_per_view_point: (u32, u32),
+    size: (u32, u32),
+    transposed_size: bool,
+) -> CompleteMapping {
+    let (columns_x, columns_y) = if transposed_size {
+        (size.0, size.1)
+    } else {
+        (size.1, size.0)
+    };
+    let x = build_mapping(buffer_x, rays_per_view_point.0 as usize, columns_x);
+    let y = build_mapping(buffer_y, rays_per_view_point.1 as usize, columns_y);
+    CompleteMapping { x, y, size }
+}
+
+/// Build the ray to pixel mapping from the index buffer `reverse_projection.wgsl` writes.
+pub fn build_ray_mapping(mut buffer: Vec<u32>, rows: u32, columns: u32) -> IndexMapping {
+    buffer.resize(rows as usize, u32::MAX);
+    IndexMapping::from_hits(buffer, columns as usize)
+}
+
 pub trait DrawUI {
     /*
     Draw UI for this element
@@ -289,7 +377,11 @@ pub trait DrawUI {
     }
 }
 
-pub fn filter_zeroes(mat: &mut Mat<f32, usize, usize>, mapping_mat: &CompleteMapping) {
+/// Sets the pixels no ray of any view point hits to 1, `mapping_mat` may be of any precision
+pub fn filter_zeroes<T: Scalar>(
+    mat: &mut Mat<f32, usize, usize>,
+    mapping_mat: &CompleteMapping<T>,
+) {
     let x_filter = filter_combine(&mapping_mat.x);
     let y_filter = filter_combine(&mapping_mat.y);
     let filters: Vec<(HashSet<usize>, HashSet<usize>)> = zip(x_filter, y_filter).collect();
@@ -305,18 +397,16 @@ pub fn filter_zeroes(mat: &mut Mat<f32, usize, usize>, mapping_mat: &CompleteMap
         }
     }
 }
-fn filter_combine(mapping: &MappingMatrix) -> Vec<HashSet<usize>> {
+fn filter_combine<T: Scalar>(mapping: &MappingMatrix<T>) -> Vec<HashSet<usize>> {
     mapping.matrix.iter().map(active_columns).collect()
 }
-fn active_columns(mat: &SparseColMat<u32, f32>) -> HashSet<usize> {
-    let pntr = mat.col_ptr();
-    let mut set = HashSet::new();
-    for column in 0..mat.ncols() {
-        if pntr[column + 1] != pntr[column] {
-            set.insert(column);
-        }
-    }
-    set
+fn active_columns<T: Scalar>(mapping: &IndexMapping<T>) -> HashSet<usize> {
+    mapping
+        .active_columns()
+        .into_iter()
+        .enumerate()
+        .filter_map(|(column, active)| active.then_some(column))
+        .collect()
 }
 
 fn filter_helper(
@@ -367,6 +457,7 @@ mod test {
     use faer::*;
 
     use super::*;
+    use crate::{mapping, synthetic};
 
     #[test]
     fn image_around_the_world() {
@@ -375,12 +466,15 @@ mod test {
         let matrix = image_to_matrix(&image);
         let new_image = matrix_to_image(&matrix);
         let new_matrix = image_to_matrix(&new_image);
-        // Write both into
-        image.save("./resources/test/OG.png").unwrap();
-        new_image.save("./resources/test/NEW.png").unwrap();
+        // Write both outside the tree, the committed NEW.png is the expected output
+        let out = std::env::temp_dir();
+        image.save(out.join("OG.png")).unwrap();
+        new_image.save(out.join("NEW.png")).unwrap();
         for (og, new) in std::iter::zip(image.pixels(), new_image.pixels()) {
             assert_eq!(og, new);
         }
+        let expected = image::open("./resources/test/NEW.png").unwrap();
+        assert_eq!(expected.to_rgba8(), new_image.to_rgba8());
 
         //assert_eq!(image, new_image);
         assert_eq!(new_matrix, matrix);
@@ -400,4 +494,52 @@ mod test {
         // Ergo, we can test
         assert_eq!(selected, other_selected);
     }
+
+    /// Panel on the 8 bit grid, so it survives being written to an image
+    fn quantized_panel(seed: u64, rows: usize, cols: usize) -> Mat<f32> {
+        let mut rng = synthetic::rng(seed);
+        let panel: Mat<f32> = synthetic::random_panel(&mut rng, rows, cols, 0.0);
+        zip!(&panel).map(|unzip!(x)| (*x * 255.0).round() / 255.0)
+    }
+
+    #[test]
+    fn synthetic_panels_around_the_world() {
+        let panel = quantized_panel(7, 6, 5);
+        let image = matrix_to_image(&panel);
+        // The luma weights sum to 1 only up to rounding
+        assert!((image_to_matrix(&image) - &panel).norm_max() < 1e-6);
+        assert_eq!(matrix_to_image(&image_to_matrix(&image)), image);
+
+        let channels = [8, 9, 10].map(|seed| quantized_panel(seed, 6, 5));
+        assert_eq!(image_to_channels(&channels_to_image(&channels)), channels);
+
+        // Stereo panels are vectorized row by row
+        let vectors = channels.each_ref().map(mapping::vectorize);
+        let image = vectors_to_image(&vectors, 6, 5);
+        assert_eq!(image_to_channels(&image), channels);
+        let image = vector_to_image(&vectors[0], 6, 5);
+        assert_eq!(image_to_channels(&image)[0], channels[0]);
+    }
+
+    #[test]
+    fn filter_only_touches_unseen_pixels() {
+        let mut rng = synthetic::rng(11);
+        // Rows from 5 and columns from 4 on are never hit
+        let mapping: CompleteMapping<f64> =
+            synthetic::random_complete_mapping(&mut rng, 3, (40, 30), (5, 4), (7, 6));
+        let original = quantized_panel(12, 7, 6);
+        let mut panel = original.clone();
+        filter_zeroes(&mut panel, &mapping);
+        for row in 0..7 {
+            for col in 0..6 {
+                let seen = (0..3).any(|view| {
+                    mapping.y.matrix[view].active_columns()[row]
+                        && mapping.x.matrix[view].active_columns()[col]
+                });
+                let expected = if seen { original[(row, col)] } else { 1.0 };
+                assert_eq!(panel[(row, col)], expected, "pixel ({row}, {col})");
+                assert_eq!(seen, row < 5 && col < 4);
+            }
+        }
+    }
 }